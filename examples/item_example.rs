@@ -1,6 +1,6 @@
 use core::panic;
 
-use reco_forge::{create_model, pass_item, Data, Tensor, HashMap};
+use reco_forge::{create_model, pass_item_by_id, read_item, Data, Tensor, HashMap};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("This is an example of how you can find similar items to another item in a dataset. You must input an item in the dataset for this example to work
@@ -44,12 +44,10 @@ so check the github repositoy (https://github.com/jameslk3/reco-forge) to find t
     tags_input = tags_input.trim().to_string();
 
     println!("Find something similar to... (input an item in the dataset)");
-    let mut query: String = String::new();
-    std::io::stdin().read_line(&mut query).expect("Failed to read line");
-    query = query.trim().to_string();
+    let seed_id = read_item(&model, &mut std::io::stdin().lock(), |prompt| println!("{}", prompt))?.id;
     println!();
 
-    let recommendations = pass_item(&model, query, tags_input, 10);
+    let recommendations = pass_item_by_id(&model, seed_id, tags_input, 10);
     match recommendations {
         Ok(recommendations) => {
             println!("Recommendations:");
@@ -0,0 +1,227 @@
+use super::recommendation::create_input_embeddings;
+use super::tags::tag_vocabulary;
+use super::types::{AutoTagOptions, AutoTagStrategy, Data, SuggestedTag, TagSuggestions};
+use super::utils::cosine_similarity;
+use anyhow::{Error as E, Result};
+use candle::Tensor;
+use std::collections::HashMap;
+
+/// Whether an item has no tags (blank tags don't count)
+pub(crate) fn is_untagged(item: &Data) -> bool {
+    item.tags.iter().all(|tag| tag.trim().is_empty())
+}
+
+/// Receives the model and suggests tags for every item that doesn't have any, using the tags of the items that do
+///
+/// @param `data` - the model
+/// @param `options` - the strategy and its thresholds
+///
+/// @return `Ok()` with the suggestions for each untagged item, ordered by id [OR] `Err()` if an item has no embedding,
+/// no item has tags or the tag embeddings couldn't be created
+pub(crate) fn suggest_tags(data: &HashMap<Data, Option<Tensor>>, options: &AutoTagOptions) -> Result<Vec<TagSuggestions>> {
+    let mut tagged: Vec<(&Data, Vec<f32>)> = Vec::new();
+    let mut untagged: Vec<(&Data, Vec<f32>)> = Vec::new();
+    for (key, value) in data.iter() {
+        let embedding = match value {
+            Some(embedding) => embedding.to_vec1::<f32>()?,
+            None => return Err(E::msg(format!("Item {} has no embedding", key.id))),
+        };
+        if is_untagged(key) {
+            untagged.push((key, embedding));
+        } else {
+            tagged.push((key, embedding));
+        }
+    }
+    untagged.sort_by(|a, b| a.0.id.cmp(&b.0.id).then(a.0.name.cmp(&b.0.name)));
+    if untagged.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let vocabulary = tag_vocabulary(tagged.iter().map(|(key, _)| *key), None);
+    if vocabulary.is_empty() {
+        return Err(E::msg("No item has tags to suggest from"));
+    }
+
+    let mut suggestions: Vec<TagSuggestions> = Vec::new();
+    match options.strategy {
+        AutoTagStrategy::TagNames => {
+            let texts: Vec<String> = vocabulary
+                .iter()
+                .map(|info| {
+                    let description = options.descriptions.iter().find(|(tag, _)| tag.trim().to_lowercase() == info.tag.trim().to_lowercase());
+                    match description {
+                        Some((_, description)) => format!("{}: {}", info.tag, description),
+                        None => info.tag.clone(),
+                    }
+                })
+                .collect();
+            let texts: Vec<&str> = texts.iter().map(|x| x.as_str()).collect();
+            let tag_embeddings = create_input_embeddings(&texts)?
+                .iter()
+                .map(|embedding| Ok(embedding.to_vec1::<f32>()?))
+                .collect::<Result<Vec<Vec<f32>>>>()?;
+
+            for (key, embedding) in untagged {
+                let tags: Vec<SuggestedTag> = vocabulary
+                    .iter()
+                    .zip(tag_embeddings.iter())
+                    .map(|(info, tag_embedding)| SuggestedTag { tag: info.tag.clone(), confidence: cosine_similarity(&embedding, tag_embedding) })
+                    .filter(|tag| tag.confidence >= options.threshold)
+                    .collect();
+                suggestions.push(TagSuggestions { id: key.id, name: key.name.clone(), tags: strongest(tags, options.max_tags) });
+            }
+        },
+        AutoTagStrategy::Neighbours => {
+            let spellings: HashMap<String, String> = vocabulary.iter().map(|info| (info.tag.trim().to_lowercase(), info.tag.clone())).collect();
+            for (key, embedding) in untagged {
+                let neighbours = nearest_tagged(&embedding, &tagged, options.num_neighbours, None);
+                let votes = tag_votes(&neighbours);
+                let tags: Vec<SuggestedTag> = votes
+                    .into_iter()
+                    .map(|(tag, vote)| SuggestedTag { confidence: vote, tag: spellings.get(&tag).cloned().unwrap_or(tag) })
+                    .filter(|tag| tag.confidence >= options.min_confidence)
+                    .collect();
+                suggestions.push(TagSuggestions { id: key.id, name: key.name.clone(), tags: strongest(tags, options.max_tags) });
+            }
+        },
+    }
+    Ok(suggestions)
+}
+
+/// The `num_neighbours` tagged items most similar to `embedding`, most similar first and ties by id
+///
+/// @param `embedding` - the embedding of the item whose neighbours are wanted
+/// @param `tagged` - the tagged items with their embeddings
+/// @param `num_neighbours` - the number of neighbours
+/// @param `skip` - the item itself, if it is one of the tagged items
+///
+/// @return (neighbour, similarity) tuples
+pub(crate) fn nearest_tagged<'a>(embedding: &[f32], tagged: &[(&'a Data, Vec<f32>)], num_neighbours: usize, skip: Option<&Data>) -> Vec<(&'a Data, f32)> {
+    let mut neighbours: Vec<(&Data, f32)> = tagged
+        .iter()
+        .filter(|(other, _)| Some(*other) != skip)
+        .map(|(other, other_embedding)| (*other, cosine_similarity(embedding, other_embedding)))
+        .collect();
+    neighbours.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.id.cmp(&b.0.id)));
+    neighbours.truncate(num_neighbours);
+    neighbours
+}
+
+/// The share of the neighbours' votes each tag (lowercased) gets. Each neighbour votes once for each of its tags,
+/// weighted by its similarity, and dissimilar neighbours don't vote.
+pub(crate) fn tag_votes(neighbours: &[(&Data, f32)]) -> HashMap<String, f32> {
+    let mut votes: HashMap<String, f32> = HashMap::new();
+    let mut total = 0.0;
+    for (neighbour, similarity) in neighbours.iter().filter(|(_, similarity)| *similarity > 0.0) {
+        total += similarity;
+        let mut voted: Vec<String> = Vec::new();
+        for tag in neighbour.tags.iter().map(|x| x.trim().to_lowercase()).filter(|x| !x.is_empty()) {
+            if !voted.contains(&tag) {
+                *votes.entry(tag.clone()).or_insert(0.0) += similarity;
+                voted.push(tag);
+            }
+        }
+    }
+    for vote in votes.values_mut() {
+        *vote /= total;
+    }
+    votes
+}
+
+/// The `max_tags` most confident tags, ties broken alphabetically
+fn strongest(mut tags: Vec<SuggestedTag>, max_tags: usize) -> Vec<SuggestedTag> {
+    tags.sort_by(|a, b| b.confidence.total_cmp(&a.confidence).then(a.tag.cmp(&b.tag)));
+    tags.truncate(max_tags);
+    tags
+}
+
+/// Gives every untagged item the tags suggested for its id
+///
+/// @param `items` - the items to fill in
+/// @param `suggestions` - the suggestions from `suggest_tags`
+///
+/// @return the number of items that were given tags
+pub(crate) fn apply_suggestions(items: &mut [Data], suggestions: &[TagSuggestions]) -> usize {
+    let mut applied = 0;
+    for item in items.iter_mut().filter(|item| is_untagged(item)) {
+        let suggestion = suggestions.iter().find(|suggestion| suggestion.id == item.id && !suggestion.tags.is_empty());
+        if let Some(suggestion) = suggestion {
+            item.tags = suggestion.tags.iter().map(|tag| tag.tag.clone()).collect();
+            applied += 1;
+        }
+    }
+    applied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle::Device;
+
+    fn item(id: i32, tags: &[&str], embedding: &[f32]) -> (Data, Option<Tensor>) {
+        let data = Data { id, name: format!("Item {}", id), summary: String::new(), tags: tags.iter().map(|tag| tag.to_string()).collect() };
+        (data, Some(Tensor::new(embedding, &Device::Cpu).unwrap()))
+    }
+
+    /// Items 10 and 11 are untagged, Item 10 is close to the comedies and Item 11 to Item 4
+    fn catalog() -> HashMap<Data, Option<Tensor>> {
+        [
+            item(1, &["Comedy"], &[1.0, 0.0]),
+            item(2, &["comedy", "Romance", "Comedy"], &[0.8, 0.6]),
+            item(3, &["Horror"], &[-1.0, 0.0]),
+            item(4, &["Horror"], &[0.0, 1.0]),
+            item(10, &[], &[1.0, 0.0]),
+            item(11, &[" "], &[0.0, 1.0]),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    fn neighbours(min_confidence: f32, max_tags: usize) -> AutoTagOptions {
+        AutoTagOptions { strategy: AutoTagStrategy::Neighbours, num_neighbours: 3, min_confidence, max_tags, ..AutoTagOptions::default() }
+    }
+
+    fn suggested(suggestions: &[TagSuggestions]) -> Vec<(i32, Vec<&str>)> {
+        suggestions.iter().map(|suggestion| (suggestion.id, suggestion.tags.iter().map(|tag| tag.tag.as_str()).collect())).collect()
+    }
+
+    #[test]
+    fn votes_are_weighted_by_similarity_and_counted_once_per_neighbour() {
+        let data = catalog();
+        let item = |id: i32| data.keys().find(|key| key.id == id).unwrap();
+        let neighbours = [(item(1), 1.0), (item(2), 0.8), (item(4), 0.0), (item(3), -1.0)];
+
+        let votes = tag_votes(&neighbours);
+        assert_eq!(votes.len(), 2);
+        assert!((votes["comedy"] - 1.0).abs() < 1e-6);
+        assert!((votes["romance"] - 0.8 / 1.8).abs() < 1e-6);
+        assert!(!votes.contains_key("horror"));
+    }
+
+    #[test]
+    fn neighbours_suggest_the_tags_above_the_minimum_confidence() {
+        let data = catalog();
+
+        let suggestions = suggest_tags(&data, &neighbours(0.5, 3)).unwrap();
+        assert_eq!(suggested(&suggestions), vec![(10, vec!["Comedy"]), (11, vec!["Horror"])]);
+        assert!((suggestions[1].tags[0].confidence - 1.0 / 1.6).abs() < 1e-6);
+
+        // Romance has 0.44 of Item 10's votes, Comedy and Romance have 0.375 of Item 11's
+        let suggestions = suggest_tags(&data, &neighbours(0.4, 3)).unwrap();
+        assert_eq!(suggested(&suggestions), vec![(10, vec!["Comedy", "Romance"]), (11, vec!["Horror"])]);
+        let suggestions = suggest_tags(&data, &neighbours(0.3, 3)).unwrap();
+        assert_eq!(suggested(&suggestions), vec![(10, vec!["Comedy", "Romance"]), (11, vec!["Horror", "Comedy", "Romance"])]);
+        let suggestions = suggest_tags(&data, &neighbours(0.3, 1)).unwrap();
+        assert_eq!(suggested(&suggestions), vec![(10, vec!["Comedy"]), (11, vec!["Horror"])]);
+    }
+
+    #[test]
+    fn suggestions_need_tagged_items_and_embeddings() {
+        let untagged: HashMap<Data, Option<Tensor>> = [item(10, &[], &[1.0, 0.0])].into_iter().collect();
+        assert!(suggest_tags(&untagged, &neighbours(0.5, 3)).is_err());
+
+        let mut data = catalog();
+        data.insert(item(12, &[], &[1.0, 0.0]).0, None);
+        assert!(suggest_tags(&data, &neighbours(0.5, 3)).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use anyhow::{Error as E, Result};
+use candle::{DType, Device, Module, Tensor, D};
+use candle_nn::{Embedding, LayerNorm, VarBuilder};
+
+/// A linear layer, with either f32 or quantized weights
+pub(crate) type Linear = Box<dyn Module + Send + Sync>;
+
+/// The activation of the intermediate layers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Activation {
+    Gelu,
+    GeluApproximate,
+    Relu,
+}
+
+impl Activation {
+    /// Reads the activation from the `hidden_act` of a config.json
+    ///
+    /// @param `hidden_act` - the activation name, `None` for the BERT default of "gelu"
+    /// @param `approximate_gelu` - whether Gelu is replaced with its tanh approximation
+    ///
+    /// @return `Ok()` with the activation [OR] `Err()` if it isn't supported
+    pub(crate) fn from_config(hidden_act: Option<&str>, approximate_gelu: bool) -> Result<Activation> {
+        let activation = match hidden_act {
+            Some("gelu") | None => Activation::Gelu,
+            Some("gelu_approximate") | Some("gelu_new") | Some("gelu_pytorch_tanh") => Activation::GeluApproximate,
+            Some("relu") => Activation::Relu,
+            Some(other) => return Err(E::msg(format!("BERT models with {} activations aren't supported", other))),
+        };
+        Ok(match activation {
+            Activation::Gelu if approximate_gelu => Activation::GeluApproximate,
+            activation => activation,
+        })
+    }
+
+    /// The name `from_config` reads back
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Activation::Gelu => "gelu",
+            Activation::GeluApproximate => "gelu_approximate",
+            Activation::Relu => "relu",
+        }
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        Ok(match self {
+            Activation::Gelu => xs.gelu_erf()?,
+            Activation::GeluApproximate => xs.gelu()?,
+            Activation::Relu => xs.relu()?,
+        })
+    }
+}
+
+pub(crate) struct BertEmbeddings {
+    pub(crate) word_embeddings: Embedding,
+    pub(crate) position_embeddings: Embedding,
+    pub(crate) token_type_embeddings: Embedding,
+    pub(crate) layer_norm: LayerNorm,
+}
+
+pub(crate) struct BertLayer {
+    pub(crate) query: Linear,
+    pub(crate) key: Linear,
+    pub(crate) value: Linear,
+    pub(crate) attention_output: Linear,
+    pub(crate) attention_layer_norm: LayerNorm,
+    pub(crate) intermediate: Linear,
+    pub(crate) output: Linear,
+    pub(crate) output_layer_norm: LayerNorm,
+}
+
+/// A BERT encoder that keeps padding out of the attention, so a text gets the same token embeddings whatever else is in
+/// its batch. The layers are the same as `candle_transformers::models::bert::BertModel`, which doesn't take a mask. The
+/// quantized models are built from the same layers with quantized linear weights.
+pub(crate) struct BertModel {
+    pub(crate) embeddings: BertEmbeddings,
+    pub(crate) layers: Vec<BertLayer>,
+    pub(crate) num_attention_heads: usize,
+    pub(crate) activation: Activation,
+    pub(crate) device: Device,
+}
+
+impl BertModel {
+    /// Loads the model from the weights of a BERT checkpoint, with or without the `bert.` prefix of the BertFor... classes
+    ///
+    /// @param `config_json` - the contents of config.json
+    /// @param `vb` - the weights
+    /// @param `approximate_gelu` - whether to use the tanh approximation of Gelu
+    ///
+    /// @return `Ok()` with the model [OR] `Err()` if the config isn't a BERT config or the weights don't fit it
+    pub(crate) fn load(config_json: &str, vb: VarBuilder, approximate_gelu: bool) -> Result<BertModel> {
+        let config: serde_json::Value = serde_json::from_str(config_json)?;
+        let number = |key: &str| config[key].as_u64().map(|x| x as usize).ok_or(E::msg(format!("config.json has no {}", key)));
+        let (hidden, intermediate) = (number("hidden_size")?, number("intermediate_size")?);
+        let eps = config["layer_norm_eps"].as_f64().unwrap_or(1e-12);
+        if config["position_embedding_type"].as_str().is_some_and(|x| x != "absolute") {
+            return Err(E::msg("Only BERT models with absolute position embeddings are supported"));
+        }
+        let vb = match vb.contains_tensor("bert.embeddings.word_embeddings.weight") {
+            true => vb.pp("bert"),
+            false => vb,
+        };
+
+        let embeddings_vb = vb.pp("embeddings");
+        let embeddings = BertEmbeddings {
+            word_embeddings: candle_nn::embedding(number("vocab_size")?, hidden, embeddings_vb.pp("word_embeddings"))?,
+            position_embeddings: candle_nn::embedding(number("max_position_embeddings")?, hidden, embeddings_vb.pp("position_embeddings"))?,
+            token_type_embeddings: candle_nn::embedding(number("type_vocab_size")?, hidden, embeddings_vb.pp("token_type_embeddings"))?,
+            layer_norm: candle_nn::layer_norm(hidden, eps, embeddings_vb.pp("LayerNorm"))?,
+        };
+        let linear = |in_dim: usize, out_dim: usize, vb: VarBuilder| -> Result<Linear> { Ok(Box::new(candle_nn::linear(in_dim, out_dim, vb)?)) };
+        let mut layers: Vec<BertLayer> = Vec::new();
+        for i in 0..number("num_hidden_layers")? {
+            let vb = vb.pp(format!("encoder.layer.{}", i));
+            layers.push(BertLayer {
+                query: linear(hidden, hidden, vb.pp("attention.self.query"))?,
+                key: linear(hidden, hidden, vb.pp("attention.self.key"))?,
+                value: linear(hidden, hidden, vb.pp("attention.self.value"))?,
+                attention_output: linear(hidden, hidden, vb.pp("attention.output.dense"))?,
+                attention_layer_norm: candle_nn::layer_norm(hidden, eps, vb.pp("attention.output.LayerNorm"))?,
+                intermediate: linear(hidden, intermediate, vb.pp("intermediate.dense"))?,
+                output: linear(intermediate, hidden, vb.pp("output.dense"))?,
+                output_layer_norm: candle_nn::layer_norm(hidden, eps, vb.pp("output.LayerNorm"))?,
+            });
+        }
+        Ok(BertModel {
+            embeddings,
+            layers,
+            num_attention_heads: number("num_attention_heads")?,
+            activation: Activation::from_config(config["hidden_act"].as_str(), approximate_gelu)?,
+            device: vb.device().clone(),
+        })
+    }
+
+    /// Runs a batch of tokens through the model
+    ///
+    /// @param `token_ids` - the (number of texts, number of tokens) token ids
+    /// @param `attention_mask` - the (number of texts, number of tokens) attention mask, 1 for real tokens and 0 for padding
+    ///
+    /// @return `Ok()` with the (number of texts, number of tokens, hidden size) token embeddings [OR] `Err()`
+    pub(crate) fn forward(&self, token_ids: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+        let (_, seq_len) = token_ids.dims2()?;
+        let position_ids = Tensor::arange(0u32, seq_len as u32, &self.device)?;
+        let embeddings = (self.embeddings.word_embeddings.forward(token_ids)?
+            + self.embeddings.token_type_embeddings.forward(&token_ids.zeros_like()?)?)?
+            .broadcast_add(&self.embeddings.position_embeddings.forward(&position_ids)?)?;
+        let mut hidden_states = self.embeddings.layer_norm.forward(&embeddings)?;
+
+        // Padding gets a large negative score before the softmax, which leaves it with a weight of exactly 0
+        let mask = ((attention_mask.to_dtype(DType::F32)? - 1.0)? * 10_000.0)?.unsqueeze(1)?.unsqueeze(1)?;
+        for layer in self.layers.iter() {
+            hidden_states = self.forward_layer(layer, &hidden_states, &mask)?;
+        }
+        Ok(hidden_states)
+    }
+
+    fn forward_layer(&self, layer: &BertLayer, hidden_states: &Tensor, mask: &Tensor) -> Result<Tensor> {
+        let (batch_size, seq_len, hidden_size) = hidden_states.dims3()?;
+        let head_size = hidden_size / self.num_attention_heads;
+        let heads = |x: Tensor| -> Result<Tensor> {
+            Ok(x.reshape((batch_size, seq_len, self.num_attention_heads, head_size))?.transpose(1, 2)?.contiguous()?)
+        };
+        let query = heads(layer.query.forward(hidden_states)?)?;
+        let key = heads(layer.key.forward(hidden_states)?)?;
+        let value = heads(layer.value.forward(hidden_states)?)?;
+
+        let scores = (query.matmul(&key.t()?)? / (head_size as f64).sqrt())?.broadcast_add(mask)?;
+        let probabilities = candle_nn::ops::softmax(&scores, D::Minus1)?;
+        let context = probabilities.matmul(&value)?.transpose(1, 2)?.contiguous()?.flatten_from(D::Minus2)?;
+        let attention = layer.attention_layer_norm.forward(&(layer.attention_output.forward(&context)? + hidden_states)?)?;
+
+        let intermediate = self.activation.forward(&layer.intermediate.forward(&attention)?)?;
+        Ok(layer.output_layer_norm.forward(&(layer.output.forward(&intermediate)? + attention)?)?)
+    }
+}
@@ -0,0 +1,218 @@
+use super::pagination::rank_order;
+use super::recommendation::through_tag_filter;
+use super::types::{Args, ChunkAggregation, ChunkIndex, ChunkedRecommendation, ChunkingOptions, Data, SummaryChunk};
+use super::utils::{cosine_similarity, embed_batch};
+use anyhow::{Error as E, Result};
+use candle::Tensor;
+use clap::Parser;
+use std::collections::HashMap;
+use tokenizers::Tokenizer;
+
+/// The number of tokens the tokenizer adds around every text, like [CLS] and [SEP]
+const SPECIAL_TOKENS: usize = 2;
+
+/// Receives a summary and splits it into overlapping windows of tokens
+///
+/// @param `tokenizer` - the tokenizer of the model, without truncation
+/// @param `text` - the summary
+/// @param `window_tokens` - the number of tokens in each window
+/// @param `overlap_tokens` - the number of tokens each window shares with the one before it
+///
+/// @return `Ok()` with (text, start token, end token) tuples, a single window for short or blank summaries [OR] `Err()`
+/// if the summary couldn't be tokenized
+pub(crate) fn chunk_summary(tokenizer: &Tokenizer, text: &str, window_tokens: usize, overlap_tokens: usize) -> Result<Vec<(String, usize, usize)>> {
+    let encoding = tokenizer.encode(text, false).map_err(E::msg)?;
+    let offsets = encoding.get_offsets();
+    if offsets.len() <= window_tokens {
+        return Ok(vec![(text.to_string(), 0, offsets.len())]);
+    }
+
+    let stride = window_tokens.saturating_sub(overlap_tokens).max(1);
+    let mut windows: Vec<(String, usize, usize)> = Vec::new();
+    let mut start = 0;
+    loop {
+        let end = (start + window_tokens).min(offsets.len());
+        windows.push((text[offsets[start].0..offsets[end - 1].1].to_string(), start, end));
+        if end == offsets.len() {
+            break;
+        }
+        start += stride;
+    }
+    Ok(windows)
+}
+
+/// Receives the model and splits every item's summary into overlapping windows, embedding each window
+///
+/// @param `data` - the model
+/// @param `options` - the size and overlap of the windows and how many are embedded at once
+///
+/// @return `Ok()` with the chunk index [OR] `Err()` if the model couldn't be loaded or the windows couldn't be embedded
+pub(crate) fn build_chunk_index(data: &HashMap<Data, Option<Tensor>>, options: &ChunkingOptions) -> Result<ChunkIndex> {
+    let args = Args::parse();
+    let (model, mut tokenizer) = args.build_model_and_tokenizer()?;
+    let max_length = args.max_length(&tokenizer)?;
+    // The document template takes up some of every window
+    let template_tokens = tokenizer.encode(args.document_text(""), false).map_err(E::msg)?.len();
+    let window_tokens = options.window_tokens.unwrap_or(max_length.saturating_sub(SPECIAL_TOKENS + template_tokens)).max(1);
+    if options.overlap_tokens >= window_tokens {
+        return Err(E::msg("The overlap has to be smaller than the window"));
+    }
+
+    let mut splitter = tokenizer.clone();
+    splitter.with_truncation(None).map_err(E::msg)?;
+    if let Some(pp) = tokenizer.get_padding_mut() {
+        pp.strategy = tokenizers::PaddingStrategy::BatchLongest
+    }
+
+    let mut windows: Vec<(Data, String, usize, usize)> = Vec::new();
+    for key in data.keys() {
+        for (text, start, end) in chunk_summary(&splitter, &key.summary, window_tokens, options.overlap_tokens)? {
+            windows.push((key.clone(), text, start, end));
+        }
+    }
+
+    let mut chunks: HashMap<Data, Vec<SummaryChunk>> = HashMap::new();
+    for batch in windows.chunks(options.batch_size.max(1)) {
+        let texts: Vec<String> = batch.iter().map(|(_, text, _, _)| args.document_text(text)).collect();
+        let embeddings = embed_batch(&model, &tokenizer, texts.iter().map(|x| x.as_str()).collect())?;
+        for (i, (key, text, start, end)) in batch.iter().enumerate() {
+            chunks.entry(key.clone()).or_default().push(SummaryChunk {
+                text: text.clone(),
+                start_token: *start,
+                end_token: *end,
+                embedding: embeddings.get(i)?,
+            });
+        }
+    }
+    Ok(ChunkIndex { chunks, window_tokens, overlap_tokens: options.overlap_tokens })
+}
+
+/// Receives a query embedding and scores every item by the similarity of its chunks
+///
+/// @param `index` - the chunk index
+/// @param `exclude` - items that should never be recommended
+/// @param `input_embedding` - the embedding of the query
+/// @param `tags_input` - the tags to filter by, each tag separated by a comma, or NONE
+/// @param `num_recommendations` - the number of recommendations
+/// @param `aggregation` - how the similarities of an item's chunks are combined
+///
+/// @return `Ok()` with the recommendations, best first [OR] `Err()` if an embedding couldn't be read
+pub(crate) fn get_chunked_recommendations(
+    index: &ChunkIndex,
+    exclude: &[Data],
+    input_embedding: &Tensor,
+    tags_input: &str,
+    num_recommendations: usize,
+    aggregation: ChunkAggregation,
+) -> Result<Vec<ChunkedRecommendation>, ()> {
+    let input_vector = input_embedding.to_vec1::<f32>().map_err(|_| ())?;
+    let mut scored: Vec<((Data, f32), usize)> = Vec::new();
+    for (key, chunks) in index.chunks.iter() {
+        if exclude.contains(key) || !through_tag_filter(&key.tags, tags_input) || chunks.is_empty() {
+            continue;
+        }
+        let mut similarities: Vec<(usize, f32)> = Vec::with_capacity(chunks.len());
+        for (i, chunk) in chunks.iter().enumerate() {
+            similarities.push((i, cosine_similarity(&input_vector, &chunk.embedding.to_vec1::<f32>().map_err(|_| ())?)));
+        }
+        similarities.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        let best_chunk = similarities[0].0;
+        let take = match aggregation {
+            ChunkAggregation::Max => 1,
+            ChunkAggregation::Mean => similarities.len(),
+            ChunkAggregation::TopKMean(k) => k.clamp(1, similarities.len()),
+        };
+        let score = similarities.iter().take(take).map(|(_, similarity)| similarity).sum::<f32>() / take as f32;
+        scored.push(((key.clone(), score), best_chunk));
+    }
+    scored.sort_by(|a, b| rank_order(&a.0, &b.0));
+
+    Ok(scored
+        .into_iter()
+        .take(num_recommendations)
+        .map(|((key, score), chunk)| ChunkedRecommendation {
+            chunk_text: index.chunks[&key][chunk].text.clone(),
+            name: key.name,
+            score,
+            chunk,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle::Device;
+
+    /// Splits on whitespace, every word is [UNK] but keeps its offsets
+    const TOKENIZER: &str = r#"{"version": "1.0", "truncation": null, "padding": null, "added_tokens": [], "normalizer": null,
+        "pre_tokenizer": {"type": "Whitespace"}, "post_processor": null, "decoder": null,
+        "model": {"type": "WordLevel", "unk_token": "[UNK]", "vocab": {"[UNK]": 0}}}"#;
+
+    #[test]
+    fn summaries_are_split_into_overlapping_windows() {
+        let tokenizer = TOKENIZER.parse::<Tokenizer>().unwrap();
+        let text = "one two  three four five six seven";
+
+        let windows = chunk_summary(&tokenizer, text, 3, 1).unwrap();
+        let expected = [("one two  three", 0, 3), ("three four five", 2, 5), ("five six seven", 4, 7)];
+        assert_eq!(windows, expected.iter().map(|(text, start, end)| (text.to_string(), *start, *end)).collect::<Vec<_>>());
+
+        // Without overlap the windows only meet, and the last one can be shorter
+        let windows = chunk_summary(&tokenizer, text, 3, 0).unwrap();
+        let spans: Vec<(usize, usize)> = windows.iter().map(|(_, start, end)| (*start, *end)).collect();
+        assert_eq!(spans, vec![(0, 3), (3, 6), (6, 7)]);
+        assert_eq!(windows[2].0, "seven");
+    }
+
+    #[test]
+    fn short_and_blank_summaries_are_a_single_window() {
+        let tokenizer = TOKENIZER.parse::<Tokenizer>().unwrap();
+        assert_eq!(chunk_summary(&tokenizer, "one two three", 3, 1).unwrap(), vec![("one two three".to_string(), 0, 3)]);
+        assert_eq!(chunk_summary(&tokenizer, "  ", 3, 1).unwrap(), vec![("  ".to_string(), 0, 0)]);
+    }
+
+    /// Against the query [1, 0], Item 1's chunks score 1, 0 and 0, Item 2's 0.8 three times and Item 3's 0, 0.9 and 0.9
+    fn index() -> ChunkIndex {
+        let chunk = |text: &str, embedding: &[f32]| SummaryChunk {
+            text: text.to_string(),
+            start_token: 0,
+            end_token: 0,
+            embedding: Tensor::new(embedding, &Device::Cpu).unwrap(),
+        };
+        let item = |id: i32, chunks: Vec<SummaryChunk>| {
+            (Data { id, name: format!("Item {}", id), summary: String::new(), tags: Vec::new() }, chunks)
+        };
+        let close = [0.9, 0.19_f32.sqrt()];
+        let chunks = [
+            item(1, vec![chunk("1a", &[1.0, 0.0]), chunk("1b", &[0.0, 1.0]), chunk("1c", &[0.0, 1.0])]),
+            item(2, vec![chunk("2a", &[0.8, 0.6]), chunk("2b", &[0.8, 0.6]), chunk("2c", &[0.8, 0.6])]),
+            item(3, vec![chunk("3a", &[0.0, 1.0]), chunk("3b", &close), chunk("3c", &close)]),
+        ];
+        ChunkIndex { chunks: chunks.into_iter().collect(), window_tokens: 3, overlap_tokens: 1 }
+    }
+
+    fn ranked(aggregation: ChunkAggregation) -> Vec<(String, f32, String)> {
+        let query = Tensor::new(&[1.0_f32, 0.0], &Device::Cpu).unwrap();
+        get_chunked_recommendations(&index(), &[], &query, "NONE", 3, aggregation)
+            .unwrap()
+            .into_iter()
+            .map(|recommendation| (recommendation.name, (recommendation.score * 1000.0).round() / 1000.0, recommendation.chunk_text))
+            .collect()
+    }
+
+    fn expected(ranked: &[(i32, f32, &str)]) -> Vec<(String, f32, String)> {
+        ranked.iter().map(|(id, score, chunk)| (format!("Item {}", id), *score, chunk.to_string())).collect()
+    }
+
+    #[test]
+    fn chunk_similarities_are_aggregated_by_max_mean_or_top_k_mean() {
+        assert_eq!(ranked(ChunkAggregation::Max), expected(&[(1, 1.0, "1a"), (3, 0.9, "3b"), (2, 0.8, "2a")]));
+        assert_eq!(ranked(ChunkAggregation::Mean), expected(&[(2, 0.8, "2a"), (3, 0.6, "3b"), (1, 0.333, "1a")]));
+        assert_eq!(ranked(ChunkAggregation::TopKMean(2)), expected(&[(3, 0.9, "3b"), (2, 0.8, "2a"), (1, 0.5, "1a")]));
+
+        // k is kept between 1 and the number of chunks
+        assert_eq!(ranked(ChunkAggregation::TopKMean(0)), ranked(ChunkAggregation::Max));
+        assert_eq!(ranked(ChunkAggregation::TopKMean(10)), ranked(ChunkAggregation::Mean));
+    }
+}
@@ -0,0 +1,186 @@
+use super::types::{ConstraintStatus, Data, TagConstraint, TagConstraints};
+use std::collections::HashMap;
+
+fn primary_tag(item: &Data) -> Option<String> {
+    item.tags.first().map(|tag| tag.trim().to_lowercase())
+}
+
+fn has_tag(item: &Data, tag: &str) -> bool {
+    item.tags.iter().any(|item_tag| item_tag.trim().to_lowercase() == tag)
+}
+
+/// Picks `num_recommendations` items from a ranked list while respecting the tag constraints. Items are taken
+/// in rank order, skipping any that would break a maximum or would leave too few places to meet the minimums.
+/// If the minimums can't all be met, the remaining places are filled in rank order.
+///
+/// @param `ranked` - the candidates, best first
+/// @param `constraints` - the tag constraints
+/// @param `num_recommendations` - how many items to pick
+///
+/// @return the picked items in rank order and the status of every constraint
+pub(crate) fn apply_tag_constraints(
+    ranked: Vec<(Data, f32)>,
+    constraints: &TagConstraints,
+    num_recommendations: usize,
+) -> (Vec<(Data, f32)>, Vec<ConstraintStatus>) {
+    // Minimums are lowered to the number of candidates that have the tag, so one that can't be met doesn't hold the others back
+    let minimums: Vec<(String, usize)> = constraints
+        .min_per_tag
+        .iter()
+        .map(|(tag, count)| {
+            let tag = tag.trim().to_lowercase();
+            let available = ranked.iter().filter(|(item, _)| has_tag(item, &tag)).count();
+            (tag, (*count).min(available))
+        })
+        .collect();
+    let under_cap = |counts: &HashMap<String, usize>, item: &Data| match (constraints.max_per_primary_tag, primary_tag(item)) {
+        (Some(max), Some(tag)) => counts.get(&tag).copied().unwrap_or(0) < max,
+        _ => true,
+    };
+    // How many more items are needed to meet every minimum, counted per tag so an item with two required tags may be counted twice
+    let still_needed = |picked: &[&(Data, f32)]| -> usize {
+        minimums
+            .iter()
+            .map(|(tag, count)| count.saturating_sub(picked.iter().filter(|(item, _)| has_tag(item, tag)).count()))
+            .sum()
+    };
+
+    let mut picked: Vec<usize> = Vec::new();
+    let mut primary_counts: HashMap<String, usize> = HashMap::new();
+    for (i, (item, _)) in ranked.iter().enumerate() {
+        if picked.len() >= num_recommendations {
+            break;
+        }
+        if !under_cap(&primary_counts, item) {
+            continue;
+        }
+        let mut with_item: Vec<&(Data, f32)> = picked.iter().map(|j| &ranked[*j]).collect();
+        with_item.push(&ranked[i]);
+        if with_item.len() + still_needed(&with_item) > num_recommendations {
+            continue;
+        }
+        if let Some(tag) = primary_tag(item) {
+            *primary_counts.entry(tag).or_insert(0) += 1;
+        }
+        picked.push(i);
+    }
+
+    // The minimums couldn't all be met, so fill the remaining places with the best items that are still under the cap
+    if picked.len() < num_recommendations {
+        for (i, (item, _)) in ranked.iter().enumerate() {
+            if picked.len() >= num_recommendations {
+                break;
+            }
+            if picked.contains(&i) || !under_cap(&primary_counts, item) {
+                continue;
+            }
+            if let Some(tag) = primary_tag(item) {
+                *primary_counts.entry(tag).or_insert(0) += 1;
+            }
+            picked.push(i);
+        }
+    }
+    picked.sort();
+
+    // A constraint is binding if the unconstrained top items would have broken it (as far as it can be met)
+    let unconstrained: Vec<&Data> = ranked.iter().take(num_recommendations).map(|(item, _)| item).collect();
+    let chosen: Vec<&Data> = picked.iter().map(|i| &ranked[*i].0).collect();
+    let mut statuses: Vec<ConstraintStatus> = Vec::new();
+    if let Some(max) = constraints.max_per_primary_tag {
+        let breaks = |items: &[&Data]| {
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            for tag in items.iter().filter_map(|item| primary_tag(item)) {
+                *counts.entry(tag).or_insert(0) += 1;
+            }
+            counts.values().any(|count| *count > max)
+        };
+        statuses.push(ConstraintStatus {
+            constraint: TagConstraint::MaxPerPrimaryTag(max),
+            binding: breaks(&unconstrained),
+            satisfied: !breaks(&chosen),
+        });
+    }
+    for ((tag, reachable), (original_tag, count)) in minimums.iter().zip(constraints.min_per_tag.iter()) {
+        let count_in = |items: &[&Data]| items.iter().filter(|item| has_tag(item, tag)).count();
+        statuses.push(ConstraintStatus {
+            constraint: TagConstraint::MinPerTag(original_tag.clone(), *count),
+            binding: count_in(&unconstrained) < *reachable,
+            satisfied: count_in(&chosen) >= *count,
+        });
+    }
+
+    let mut ranked: Vec<Option<(Data, f32)>> = ranked.into_iter().map(Some).collect();
+    let result = picked.into_iter().filter_map(|i| ranked[i].take()).collect();
+    (result, statuses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranked(items: &[(i32, &str)]) -> Vec<(Data, f32)> {
+        items
+            .iter()
+            .enumerate()
+            .map(|(rank, (id, tag))| {
+                let data = Data { id: *id, name: format!("Item {}", id), summary: String::new(), tags: vec![tag.to_string()] };
+                (data, 1.0 - rank as f32 * 0.1)
+            })
+            .collect()
+    }
+
+    fn ids(picked: &[(Data, f32)]) -> Vec<i32> {
+        picked.iter().map(|(item, _)| item.id).collect()
+    }
+
+    #[test]
+    fn a_quota_caps_a_primary_tag() {
+        let candidates = ranked(&[(1, "Drama"), (2, "drama"), (3, "Drama"), (4, "Comedy"), (5, "Horror")]);
+        let constraints = TagConstraints { max_per_primary_tag: Some(2), min_per_tag: Vec::new() };
+
+        let (picked, statuses) = apply_tag_constraints(candidates, &constraints, 4);
+        assert_eq!(ids(&picked), vec![1, 2, 4, 5]);
+        assert_eq!(
+            statuses,
+            vec![ConstraintStatus { constraint: TagConstraint::MaxPerPrimaryTag(2), binding: true, satisfied: true }]
+        );
+    }
+
+    #[test]
+    fn a_minimum_pulls_items_up() {
+        let candidates = ranked(&[(1, "Drama"), (2, "Drama"), (3, "Drama"), (4, "Comedy")]);
+        let constraints = TagConstraints { max_per_primary_tag: None, min_per_tag: vec![("comedy".to_string(), 1)] };
+
+        let (picked, statuses) = apply_tag_constraints(candidates, &constraints, 2);
+        assert_eq!(ids(&picked), vec![1, 4]);
+        assert_eq!(
+            statuses,
+            vec![ConstraintStatus { constraint: TagConstraint::MinPerTag("comedy".to_string(), 1), binding: true, satisfied: true }]
+        );
+    }
+
+    #[test]
+    fn a_minimum_that_cannot_be_met_is_reported_and_does_not_leave_places_empty() {
+        let candidates = ranked(&[(1, "Drama"), (2, "Drama"), (3, "Horror"), (4, "Drama")]);
+        let constraints = TagConstraints {
+            max_per_primary_tag: None,
+            min_per_tag: vec![("Horror".to_string(), 2), ("Western".to_string(), 1)],
+        };
+
+        let (picked, statuses) = apply_tag_constraints(candidates, &constraints, 3);
+        // The one Horror item is pulled up, the Western minimum can't hold any place back
+        assert_eq!(ids(&picked), vec![1, 2, 3]);
+        assert_eq!(
+            statuses,
+            vec![
+                ConstraintStatus { constraint: TagConstraint::MinPerTag("Horror".to_string(), 2), binding: false, satisfied: false },
+                ConstraintStatus { constraint: TagConstraint::MinPerTag("Western".to_string(), 1), binding: false, satisfied: false },
+            ]
+        );
+
+        let candidates = ranked(&[(1, "Drama"), (2, "Drama"), (3, "Horror"), (4, "Drama")]);
+        let (picked, statuses) = apply_tag_constraints(candidates, &constraints, 2);
+        assert_eq!(ids(&picked), vec![1, 3]);
+        assert!(statuses[0].binding && !statuses[0].satisfied);
+    }
+}
@@ -0,0 +1,115 @@
+use super::pagination::rank_order;
+use super::recommendation::score_items;
+use super::types::{CrossEncoder, Data};
+use super::utils::cosine_similarity;
+use anyhow::{Error as E, Result};
+use candle::{Device, Module, Tensor, D};
+use candle_nn::{linear, VarBuilder};
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use std::collections::HashMap;
+use std::path::Path;
+use tokenizers::Tokenizer;
+
+/// Receives a directory with the config.json, tokenizer.json and model.safetensors (or pytorch_model.bin) of a
+/// BERT sequence classification checkpoint and loads it as a cross-encoder
+///
+/// @param `dir` - the path of the directory
+///
+/// @return `Ok()` with the cross-encoder [OR] `Err()` if a file is missing or the weights don't fit the config
+pub(crate) fn read_cross_encoder(dir: &str) -> Result<CrossEncoder> {
+    let device = Device::Cpu;
+    let dir = Path::new(dir);
+    let config_json = std::fs::read_to_string(dir.join("config.json"))?;
+    let config: Config = serde_json::from_str(&config_json)?;
+    let raw_config: serde_json::Value = serde_json::from_str(&config_json)?;
+    let hidden_size = raw_config["hidden_size"].as_u64().ok_or(E::msg("config.json has no hidden_size"))? as usize;
+    let num_labels = raw_config["id2label"].as_object().map_or(1, |labels| labels.len().max(1));
+    let tokenizer = Tokenizer::from_file(dir.join("tokenizer.json")).map_err(E::msg)?;
+
+    let vb = if dir.join("model.safetensors").exists() {
+        unsafe { VarBuilder::from_mmaped_safetensors(&[dir.join("model.safetensors")], DTYPE, &device)? }
+    } else {
+        VarBuilder::from_pth(dir.join("pytorch_model.bin"), DTYPE, &device)?
+    };
+    let model = BertModel::load(vb.clone(), &config)?;
+    let pooler = match vb.contains_tensor("bert.pooler.dense.weight") {
+        true => linear(hidden_size, hidden_size, vb.pp("bert.pooler.dense"))?,
+        false => linear(hidden_size, hidden_size, vb.pp("pooler.dense"))?,
+    };
+    let classifier = linear(hidden_size, num_labels, vb.pp("classifier"))?;
+    Ok(CrossEncoder { model, pooler, classifier, tokenizer })
+}
+
+/// Receives a query and a summary and scores how relevant the summary is to the query, between 0 and 1.
+/// Checkpoints with one label are scored with the sigmoid of the logit and checkpoints with several labels
+/// with the probability of the last label.
+///
+/// @param `encoder` - the cross-encoder
+/// @param `query` - the query
+/// @param `summary` - the summary
+///
+/// @return `Ok()` with the relevance [OR] `Err()` if the pair couldn't be tokenized or run through the model
+pub(crate) fn cross_encoder_score(encoder: &CrossEncoder, query: &str, summary: &str) -> Result<f32> {
+    let device = &encoder.model.device;
+    let encoding = encoder.tokenizer.encode((query, summary), true).map_err(E::msg)?;
+    let token_ids = Tensor::new(encoding.get_ids(), device)?.unsqueeze(0)?;
+    let token_type_ids = Tensor::new(encoding.get_type_ids(), device)?.unsqueeze(0)?;
+
+    let sequence_output = encoder.model.forward(&token_ids, &token_type_ids)?;
+    let cls = sequence_output.get(0)?.get(0)?.unsqueeze(0)?;
+    let pooled = encoder.pooler.forward(&cls)?.tanh()?;
+    let logits = encoder.classifier.forward(&pooled)?.squeeze(0)?;
+
+    let logits = logits.to_vec1::<f32>()?;
+    match logits.as_slice() {
+        [logit] => Ok(1.0 / (1.0 + (-logit).exp())),
+        _ => {
+            let probabilities = candle_nn::ops::softmax(&Tensor::new(logits.as_slice(), device)?, D::Minus1)?.to_vec1::<f32>()?;
+            probabilities.last().copied().ok_or(E::msg("The classifier has no labels"))
+        },
+    }
+}
+
+/// Finds candidates with the embeddings and re-ranks them with the cross-encoder. Each pair is run through the
+/// cross-encoder on its own: candle's `BertModel` takes no attention mask, so in a padded batch the tokens of the
+/// shorter pairs would attend to the padding and their scores would depend on the rest of the batch.
+///
+/// @param `data` - the model
+/// @param `encoder` - the cross-encoder
+/// @param `exclude` - items that should never be recommended
+/// @param `input_embedding` - the embedding of the query
+/// @param `query` - the text of the query
+/// @param `tags_input` - the tags to filter by, each tag separated by a comma, or NONE
+/// @param `num_candidates` - the number of candidates found with the embeddings, at least `num_recommendations` are used
+/// @param `num_recommendations` - the number of recommendations
+///
+/// @return `Ok()` with (Item name, relevance) tuples, only for the candidates that were re-ranked [OR] `Err()`
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn get_cross_encoder_recommendations(
+    data: &HashMap<Data, Option<Tensor>>,
+    encoder: &CrossEncoder,
+    exclude: &[Data],
+    input_embedding: &Tensor,
+    query: &str,
+    tags_input: &str,
+    num_candidates: usize,
+    num_recommendations: usize,
+) -> Result<Vec<(String, f32)>, ()> {
+    let input_vector = input_embedding.to_vec1::<f32>().map_err(|_| ())?;
+    let mut candidates = score_items(data, exclude, tags_input, |map_embedding| {
+        Ok(cosine_similarity(&input_vector, &map_embedding.to_vec1::<f32>().map_err(|_| ())?))
+    })?;
+    candidates.sort_by(rank_order);
+    candidates.truncate(num_candidates.max(num_recommendations));
+
+    let mut reranked: Vec<(Data, f32)> = Vec::with_capacity(candidates.len());
+    for (key, _) in candidates {
+        let relevance = cross_encoder_score(encoder, query, &key.summary).map_err(|_| ())?;
+        reranked.push((key, relevance));
+    }
+    reranked.sort_by(rank_order);
+    reranked.truncate(num_recommendations);
+
+    // Only the candidates that were scored, a placeholder score wouldn't be on the cross-encoder's scale
+    Ok(reranked.into_iter().map(|(key, relevance)| (key.name, relevance)).collect())
+}
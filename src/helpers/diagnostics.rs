@@ -0,0 +1,112 @@
+use super::constraints::apply_tag_constraints;
+use super::pagination::rank_order;
+use super::recommendation::{diversify, expand_with_hierarchy, score_items, through_tag_filter};
+use super::reranking::apply_tag_boosts;
+use super::types::{Data, QueryOptions, RemovalReason, WhyNotReport};
+use super::utils::cosine_similarity;
+use anyhow::{Error as E, Result};
+use candle::Tensor;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Receives a query and an item and finds out where the item ranks for the query and, if it isn't recommended,
+/// which rule kept it out. The rules are the ones `get_recommendations_with` applies, in the same order: the self-exclusion,
+/// the tag filter, the tag boosts, the score floor (on the boosted score), the diversity re-ranking and the tag constraints.
+///
+/// @param `data` - the model
+/// @param `exclude` - the items that are never recommended, like the item the query is based on
+/// @param `input_embedding` - the embedding of the query
+/// @param `target` - the item to diagnose
+/// @param `tags_input` - the tags to filter by, each tag separated by a comma, or NONE
+/// @param `num_recommendations` - the number of recommendations
+/// @param `options` - how the recommendations are ranked
+///
+/// @return `Ok()` with the report [OR] `Err()` if an item has no embedding or the target isn't in the model
+pub(crate) fn why_not(
+    data: &HashMap<Data, Option<Tensor>>,
+    exclude: &[Data],
+    input_embedding: &Tensor,
+    target: &Data,
+    tags_input: &str,
+    num_recommendations: usize,
+    options: &QueryOptions,
+) -> Result<WhyNotReport> {
+    let (tags_input, tag_boosts) = expand_with_hierarchy(tags_input, options);
+    let tags_input = tags_input.as_str();
+    let min_score = options.min_score;
+    let input_vector = input_embedding.to_vec1::<f32>()?;
+    let similarity = |map_embedding: &Tensor| Ok(cosine_similarity(&input_vector, &map_embedding.to_vec1::<f32>().map_err(|_| ())?));
+    let no_embedding = |_| E::msg("Every item needs an embedding");
+
+    // Where the item ranks ignoring every rule
+    let everything = score_items(data, &[], "NONE", similarity).map_err(no_embedding)?;
+    let target_entry = everything.iter().find(|(key, _)| key == target).cloned().ok_or(E::msg("The item isn't in the model"))?;
+    let rank = 1 + everything
+        .iter()
+        .filter(|entry| entry.0 != *target && !exclude.contains(&entry.0) && rank_order(entry, &target_entry) == Ordering::Less)
+        .count();
+
+    let mut boosted_target = [target_entry.clone()];
+    apply_tag_boosts(&mut boosted_target, &tag_boosts, options.boost_mode);
+    let score = boosted_target[0].1;
+
+    // The same steps as the recommendations, keeping the list after each one
+    let mut unboosted = score_items(data, exclude, tags_input, similarity).map_err(no_embedding)?;
+    let mut boosted = unboosted.clone();
+    apply_tag_boosts(&mut boosted, &tag_boosts, options.boost_mode);
+    if let Some(min_score) = min_score {
+        unboosted.retain(|(_, score)| *score >= min_score);
+        boosted.retain(|(_, score)| *score >= min_score);
+    }
+    unboosted.sort_by(rank_order);
+    boosted.sort_by(rank_order);
+    let diversified = diversify(data, boosted.clone(), num_recommendations, options.diversity).map_err(no_embedding)?;
+    let (recommended, _) = apply_tag_constraints(diversified.clone(), &options.tag_constraints, num_recommendations);
+
+    let in_top = |ranked: &[(Data, f32)]| ranked.iter().take(num_recommendations).any(|(key, _)| key == target);
+    let final_rank = recommended.iter().position(|(key, _)| key == target).map(|i| i + 1);
+    let cutoff_score = match recommended.len() >= num_recommendations {
+        true => recommended.last().map(|(_, score)| *score),
+        false => None,
+    };
+
+    let removed_by = if final_rank.is_some() {
+        None
+    } else if exclude.contains(target) {
+        Some(RemovalReason::SelfExclusion)
+    } else if !through_tag_filter(&target.tags, tags_input) {
+        let missing_tags: Vec<String> = tags_input
+            .split(',')
+            .filter(|alternatives| !through_tag_filter(&target.tags, alternatives))
+            .map(|alternatives| alternatives.trim().to_string())
+            .collect();
+        Some(RemovalReason::TagFilter { missing_tags })
+    } else if in_top(&unboosted) && !in_top(&boosted) {
+        Some(RemovalReason::TagBoosts)
+    } else if min_score.is_some_and(|min_score| score < min_score) {
+        Some(RemovalReason::ScoreFloor { min_score: min_score.unwrap_or_default() })
+    } else if in_top(&boosted) && !in_top(&diversified) {
+        Some(RemovalReason::Diversity)
+    } else if in_top(&diversified) {
+        Some(RemovalReason::TagConstraints)
+    } else {
+        Some(RemovalReason::Cutoff)
+    };
+
+    let gap = match (final_rank, cutoff_score) {
+        (None, Some(cutoff_score)) => Some(cutoff_score - score),
+        _ => None,
+    };
+
+    Ok(WhyNotReport {
+        id: target.id,
+        name: target.name.clone(),
+        similarity: target_entry.1,
+        score,
+        rank,
+        final_rank,
+        removed_by,
+        cutoff_score,
+        gap,
+    })
+}
@@ -0,0 +1,105 @@
+use super::bert::BertModel;
+use super::types::Architecture;
+use anyhow::{Error as E, Result};
+use candle::{Device, Module, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::distilbert::{self, DistilBertModel};
+use candle_transformers::models::jina_bert;
+
+/// The model the embeddings are made with
+pub(crate) enum Encoder {
+    Bert(BertModel),
+    DistilBert(DistilBertModel),
+    JinaBert(jina_bert::BertModel),
+    QuantizedBert(BertModel),
+}
+
+impl Encoder {
+    pub(crate) fn device(&self) -> &Device {
+        match self {
+            Encoder::Bert(model) => &model.device,
+            Encoder::DistilBert(model) => &model.device,
+            Encoder::JinaBert(model) => &model.device,
+            Encoder::QuantizedBert(model) => &model.device,
+        }
+    }
+
+    /// Runs a batch of tokens through the model
+    ///
+    /// @param `token_ids` - the (number of texts, number of tokens) token ids
+    /// @param `attention_mask` - the (number of texts, number of tokens) attention mask, 1 for real tokens and 0 for padding
+    ///
+    /// @return `Ok()` with the (number of texts, number of tokens, hidden size) token embeddings [OR] `Err()`
+    pub(crate) fn forward(&self, token_ids: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+        match self {
+            Encoder::Bert(model) | Encoder::QuantizedBert(model) => model.forward(token_ids, attention_mask),
+            Encoder::DistilBert(model) => {
+                // DistilBERT masks out the positions that are set, so the mask is flipped and made to broadcast over the heads
+                let padding = attention_mask.eq(&attention_mask.zeros_like()?)?.unsqueeze(1)?.unsqueeze(1)?;
+                Ok(model.forward(token_ids, &padding)?)
+            },
+            Encoder::JinaBert(model) => {
+                // JinaBERT doesn't take a mask, so each text is run on its own without its padding, which is then put back
+                // as zeros for the pooling to leave out
+                let (_, seq_len) = token_ids.dims2()?;
+                let lengths = attention_mask.sum(1)?.to_vec1::<u32>()?;
+                let rows = lengths
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, length)| {
+                        let row = model.forward(&token_ids.get(i)?.narrow(0, 0, length as usize)?.unsqueeze(0)?)?.squeeze(0)?;
+                        Ok(row.pad_with_zeros(0, 0, seq_len - length as usize)?)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Tensor::stack(&rows, 0)?)
+            },
+        }
+    }
+}
+
+/// Picks the architecture of a model from its config.json: `model_type` "distilbert" is DistilBERT, ALiBi position
+/// embeddings or a JinaBert class are JinaBERT and everything else is BERT
+pub(crate) fn detect_architecture(config: &serde_json::Value) -> Architecture {
+    let is_jina = config["position_embedding_type"].as_str() == Some("alibi")
+        || config["architectures"]
+            .as_array()
+            .is_some_and(|architectures| architectures.iter().any(|x| x.as_str().is_some_and(|x| x.starts_with("JinaBert"))));
+    match config["model_type"].as_str() {
+        Some("distilbert") => Architecture::DistilBert,
+        _ if is_jina => Architecture::JinaBert,
+        _ => Architecture::Bert,
+    }
+}
+
+/// Loads the model from its config and weights
+///
+/// @param `config_json` - the contents of config.json
+/// @param `vb` - the weights
+/// @param `architecture` - the architecture, or `Auto` to pick it from the config
+/// @param `approximate_gelu` - whether BERT uses the tanh approximation of Gelu
+///
+/// @return `Ok()` with the model [OR] `Err()` if the config doesn't fit the architecture or the weights don't fit the config
+pub(crate) fn load_encoder(config_json: &str, vb: VarBuilder, architecture: Architecture, approximate_gelu: bool) -> Result<Encoder> {
+    let raw_config: serde_json::Value = serde_json::from_str(config_json)?;
+    let architecture = match architecture {
+        Architecture::Auto => detect_architecture(&raw_config),
+        architecture => architecture,
+    };
+    match architecture {
+        Architecture::Auto | Architecture::Bert => Ok(Encoder::Bert(BertModel::load(config_json, vb, approximate_gelu)?)),
+        Architecture::DistilBert => {
+            let config: distilbert::Config = serde_json::from_str(config_json)?;
+            Ok(Encoder::DistilBert(DistilBertModel::load(vb, &config)?))
+        },
+        Architecture::JinaBert => {
+            let config: jina_bert::Config = serde_json::from_str(config_json)
+                .map_err(|e| E::msg(format!("config.json isn't a JinaBERT config: {}", e)))?;
+            let vb = match vb.contains_tensor("bert.embeddings.word_embeddings.weight") {
+                true => vb.pp("bert"),
+                false => vb,
+            };
+            Ok(Encoder::JinaBert(jina_bert::BertModel::new(vb, &config)?))
+        },
+    }
+}
+
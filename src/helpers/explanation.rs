@@ -0,0 +1,183 @@
+use super::lookup::normalize_name;
+use super::recommendation::create_document_embeddings;
+use super::types::{Data, Explanation};
+use super::utils::cosine_similarity;
+use anyhow::{Error as E, Result};
+use candle::Tensor;
+use std::collections::{HashMap, HashSet};
+
+/// Words that are never shown as keywords because almost every summary has them
+const KEYWORD_STOP_WORDS: &[&str] = &[
+    "the", "and", "for", "are", "but", "not", "you", "all", "any", "can", "her", "was", "one", "our", "out", "his",
+    "has", "have", "had", "him", "its", "who", "how", "man", "new", "now", "old", "see", "two", "way", "did", "get",
+    "she", "they", "them", "their", "there", "then", "than", "that", "this", "these", "those", "with", "from", "into",
+    "onto", "upon", "about", "after", "before", "when", "where", "which", "while", "what", "will", "would", "been",
+    "being", "were", "also", "only", "over", "under", "more", "most", "some", "such", "each", "other", "own", "must",
+    "just", "very", "even", "both", "because", "through", "during", "between", "against", "himself", "herself",
+    "themselves", "itself", "soon", "finds", "find", "takes", "take", "make", "makes", "becomes", "become",
+];
+
+/// The most keywords listed in one explanation
+const MAX_KEYWORDS: usize = 5;
+
+/// The number of nearest neighbours of the seed and of each recommendation that are compared for shared neighbours
+const NEIGHBOURHOOD_SIZE: usize = 10;
+
+/// Receives the recommended items and explains why each one was recommended
+///
+/// @param `data` - the model
+/// @param `recommended` - the recommended items, in order
+/// @param `query_text` - the description, or the summary of the seed item for item queries
+/// @param `query_embedding` - the embedding the items were compared with
+/// @param `seed` - the item the query is based on, if it is an item query
+/// @param `tags_input` - the tags that were filtered by, each tag separated by a comma, or NONE
+/// @param `tag_boosts` - the tags that were boosted
+///
+/// @return `Ok()` with one explanation per recommended item, in the same order [OR] `Err()` if an item has no embedding
+/// or the sentences couldn't be embedded
+pub(crate) fn explain_recommendations(
+    data: &HashMap<Data, Option<Tensor>>,
+    recommended: &[Data],
+    query_text: &str,
+    query_embedding: &[f32],
+    seed: Option<&Data>,
+    tags_input: &str,
+    tag_boosts: &[(String, f32)],
+) -> Result<Vec<Explanation>> {
+    if recommended.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Tags that were asked for, whether as a filter, a (positive) boost or by being a tag of the seed
+    let mut wanted_tags: HashSet<String> = HashSet::new();
+    if tags_input != "NONE" {
+        wanted_tags.extend(tags_input.split([',', '|']).map(|x| x.trim().to_lowercase()));
+    }
+    wanted_tags.extend(tag_boosts.iter().filter(|(_, boost)| *boost > 0.0).flat_map(|(tag, _)| tag.split('|').map(|x| x.trim().to_lowercase())));
+    if let Some(seed) = seed {
+        wanted_tags.extend(seed.tags.iter().map(|x| x.trim().to_lowercase()));
+    }
+
+    // How rare each word is over all summaries, so that the rarest shared words are listed first
+    let mut document_frequency: HashMap<String, usize> = HashMap::new();
+    for key in data.keys() {
+        for word in keywords(&key.summary) {
+            *document_frequency.entry(word).or_insert(0) += 1;
+        }
+    }
+    let query_words = keywords(query_text);
+
+    // Every sentence of every recommended item is embedded in one pass
+    let sentences: Vec<Vec<String>> = recommended.iter().map(|item| split_sentences(&item.summary)).collect();
+    let all_sentences: Vec<&str> = sentences.iter().flatten().map(|x| x.as_str()).collect();
+    let sentence_embeddings = create_document_embeddings(&all_sentences)?;
+    let mut sentence_embeddings = sentence_embeddings.iter();
+
+    let vectors: Vec<(&Data, Vec<f32>)> = data
+        .iter()
+        .map(|(key, value)| match value {
+            Some(embedding) => Ok((key, embedding.to_vec1::<f32>()?)),
+            None => Err(E::msg(format!("Item {} has no embedding", key.id))),
+        })
+        .collect::<Result<_>>()?;
+    let seed_neighbours: Vec<&Data> = match seed {
+        Some(seed) => neighbourhood(&vectors, seed),
+        None => Vec::new(),
+    };
+
+    let mut explanations: Vec<Explanation> = Vec::new();
+    for (item, item_sentences) in recommended.iter().zip(sentences.iter()) {
+        let matched_tags: Vec<String> = item.tags.iter().filter(|tag| wanted_tags.contains(&tag.trim().to_lowercase())).cloned().collect();
+
+        let summary_words = keywords(&item.summary);
+        let mut shared_words: Vec<String> = query_words.intersection(&summary_words).cloned().collect();
+        shared_words.sort_by(|a, b| {
+            let df_a = document_frequency.get(a).copied().unwrap_or(0);
+            let df_b = document_frequency.get(b).copied().unwrap_or(0);
+            df_a.cmp(&df_b).then(a.cmp(b))
+        });
+        shared_words.truncate(MAX_KEYWORDS);
+
+        let mut best_sentence: Option<(String, f32)> = None;
+        for sentence in item_sentences {
+            let embedding = sentence_embeddings.next().ok_or(E::msg("Missing sentence embedding"))?;
+            let similarity = cosine_similarity(query_embedding, &embedding.to_vec1::<f32>()?);
+            if best_sentence.as_ref().is_none_or(|(_, best)| similarity > *best) {
+                best_sentence = Some((sentence.clone(), similarity));
+            }
+        }
+
+        let mut shared_neighbours: Vec<String> = Vec::new();
+        if let Some(seed) = seed {
+            let item_neighbours = neighbourhood(&vectors, item);
+            shared_neighbours = seed_neighbours
+                .iter()
+                .filter(|neighbour| **neighbour != item && *neighbour != &seed && item_neighbours.contains(neighbour))
+                .map(|neighbour| neighbour.name.clone())
+                .collect();
+        }
+
+        let (best_sentence, best_sentence_similarity) = match best_sentence {
+            Some((sentence, similarity)) => (Some(sentence), similarity),
+            None => (None, 0.0),
+        };
+        explanations.push(Explanation {
+            matched_tags,
+            keywords: shared_words,
+            best_sentence,
+            best_sentence_similarity,
+            shared_neighbours,
+        });
+    }
+    Ok(explanations)
+}
+
+/// The distinct words of a text that could be keywords, normalized the same way as names
+fn keywords(text: &str) -> HashSet<String> {
+    normalize_name(text)
+        .split_whitespace()
+        .filter(|word| word.len() > 2 && !KEYWORD_STOP_WORDS.contains(word) && !word.chars().all(|c| c.is_ascii_digit()))
+        .map(|word| word.to_string())
+        .collect()
+}
+
+/// Splits a summary into sentences at ., ! and ? followed by whitespace. Blank sentences are left out.
+pub(crate) fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        current.push(c);
+        if matches!(c, '.' | '!' | '?') && chars.peek().is_none_or(|next| next.is_whitespace()) {
+            if !current.trim().is_empty() {
+                sentences.push(current.trim().to_string());
+            }
+            current.clear();
+        }
+    }
+    if !current.trim().is_empty() {
+        sentences.push(current.trim().to_string());
+    }
+    sentences
+}
+
+/// The `NEIGHBOURHOOD_SIZE` items most similar to `item`, leaving out the item itself
+fn neighbourhood<'a>(vectors: &[(&'a Data, Vec<f32>)], item: &Data) -> Vec<&'a Data> {
+    let embedding = match vectors.iter().find(|(key, _)| *key == item) {
+        Some((_, embedding)) => embedding,
+        None => return Vec::new(),
+    };
+    let mut neighbours: Vec<(&Data, f32)> = vectors
+        .iter()
+        .filter(|(key, _)| *key != item)
+        .map(|(key, other)| (*key, cosine_similarity(embedding, other)))
+        .collect();
+    let closest_first = |a: &(&Data, f32), b: &(&Data, f32)| b.1.total_cmp(&a.1).then(a.0.id.cmp(&b.0.id));
+    // Only the closest NEIGHBOURHOOD_SIZE are sorted, not the whole catalog
+    if neighbours.len() > NEIGHBOURHOOD_SIZE {
+        neighbours.select_nth_unstable_by(NEIGHBOURHOOD_SIZE, closest_first);
+        neighbours.truncate(NEIGHBOURHOOD_SIZE);
+    }
+    neighbours.sort_by(closest_first);
+    neighbours.into_iter().map(|(key, _)| key).collect()
+}
@@ -0,0 +1,154 @@
+use super::pagination::rank_order;
+use super::recommendation::score_items;
+use super::types::{Data, NegativeAction};
+use super::utils::cosine_similarity;
+use anyhow::{Error as E, Result};
+use candle::Tensor;
+use std::collections::HashMap;
+
+/// Combines the embeddings of a feedback query Rocchio style into a single query vector:
+/// `alpha * description + beta * mean(positive) - gamma * mean(negative)`
+///
+/// @param `description` - the embedding of the description, if there is one
+/// @param `positive` - the embeddings of the positive examples
+/// @param `negative` - the embeddings of the negative examples
+/// @param `weights` - alpha, beta and gamma
+///
+/// @return `Ok()` with the query vector [OR] `Err()` if there is nothing positive to build the query from
+pub(crate) fn rocchio(description: Option<&Tensor>, positive: &[Tensor], negative: &[Tensor], (alpha, beta, gamma): (f32, f32, f32)) -> Result<Vec<f32>> {
+    let mut terms: Vec<(Vec<f32>, f32)> = Vec::new();
+    if let Some(description) = description {
+        terms.push((description.to_vec1::<f32>()?, alpha));
+    }
+    for embedding in positive {
+        terms.push((embedding.to_vec1::<f32>()?, beta / positive.len() as f32));
+    }
+    if terms.is_empty() {
+        return Err(E::msg("A feedback query needs a description or at least one positive example"));
+    }
+    for embedding in negative {
+        terms.push((embedding.to_vec1::<f32>()?, -gamma / negative.len() as f32));
+    }
+
+    let mut query = vec![0.0; terms[0].0.len()];
+    for (vector, weight) in terms {
+        for (q, x) in query.iter_mut().zip(vector.iter()) {
+            *q += weight * x;
+        }
+    }
+    Ok(query)
+}
+
+/// Receives a query vector and the negative examples and finds recommendations, applying
+/// `negative_action` to items at least `negative_threshold` similar to any negative example.
+///
+/// @param `data` - the model
+/// @param `exclude` - the items used as examples, which are never recommended
+/// @param `query` - the query vector from `rocchio`
+/// @param `negative` - the embeddings of the negative examples
+/// @param `negative_threshold` - how similar an item has to be to a negative example to be affected
+/// @param `negative_action` - what happens to those items
+/// @param `tags_input` - the tags to filter by, each tag separated by a comma, or NONE
+/// @param `num_recommendations` - the number of recommendations
+///
+/// @return `Ok()` with (Item name, score) tuples, best first and only for items that are left [OR] `Err()`
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn get_feedback_recommendations(
+    data: &HashMap<Data, Option<Tensor>>,
+    exclude: &[Data],
+    query: &[f32],
+    negative: &[Tensor],
+    negative_threshold: f32,
+    negative_action: NegativeAction,
+    tags_input: &str,
+    num_recommendations: usize,
+) -> Result<Vec<(String, f32)>, ()> {
+    let negative_vectors: Vec<Vec<f32>> = negative
+        .iter()
+        .map(|embedding| embedding.to_vec1::<f32>().map_err(|_| ()))
+        .collect::<Result<_, ()>>()?;
+
+    // Score every item against the query
+    let scored = score_items(data, exclude, tags_input, |map_embedding| {
+        let map_vector = map_embedding.to_vec1::<f32>().map_err(|_| ())?;
+        Ok(cosine_similarity(query, &map_vector))
+    })?;
+
+    let mut ranked: Vec<(Data, f32)> = Vec::with_capacity(scored.len());
+    for (key, similarity) in scored {
+        // Then check how close it is to the nearest negative example
+        let map_vector = data[&key].as_ref().ok_or(())?.to_vec1::<f32>().map_err(|_| ())?;
+        let negative_similarity = negative_vectors
+            .iter()
+            .map(|negative_vector| cosine_similarity(negative_vector, &map_vector))
+            .fold(f32::MIN, f32::max);
+        let score = if negative_similarity >= negative_threshold {
+            match negative_action {
+                NegativeAction::Keep => similarity,
+                NegativeAction::Demote(amount) => similarity - amount,
+                NegativeAction::Drop => continue,
+            }
+        } else {
+            similarity
+        };
+        ranked.push((key, score));
+    }
+    // Demoted items can score below 0 and are still ranked rather than replaced with placeholders
+    ranked.sort_by(rank_order);
+    ranked.truncate(num_recommendations);
+    Ok(ranked.into_iter().map(|(key, score)| (key.name, score)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle::Device;
+
+    fn tensor(vector: &[f32]) -> Tensor {
+        Tensor::new(vector, &Device::Cpu).unwrap()
+    }
+
+    #[test]
+    fn rocchio_weights_each_term() {
+        let description = tensor(&[1.0, 0.0]);
+        let positive = [tensor(&[0.0, 1.0]), tensor(&[0.0, 3.0])];
+        let negative = [tensor(&[2.0, 2.0])];
+
+        // 1 * (1, 0) + 0.5 * mean((0, 1), (0, 3)) - 0.25 * (2, 2)
+        let query = rocchio(Some(&description), &positive, &negative, (1.0, 0.5, 0.25)).unwrap();
+        assert_eq!(query, vec![0.5, 0.5]);
+        // Without a description only the examples count
+        let query = rocchio(None, &positive, &[], (1.0, 0.5, 0.25)).unwrap();
+        assert_eq!(query, vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn rocchio_needs_a_description_or_a_positive_example() {
+        assert!(rocchio(None, &[], &[], (1.0, 0.75, 0.25)).is_err());
+        assert!(rocchio(None, &[], &[tensor(&[1.0, 0.0])], (1.0, 0.75, 0.25)).is_err());
+    }
+
+    #[test]
+    fn negative_actions_apply_from_the_threshold() {
+        let item = |id: i32, embedding: &[f32]| (Data { id, name: format!("Item {}", id), summary: String::new(), tags: Vec::new() }, Some(tensor(embedding)));
+        // Against the negative example (1, 0) Item 1 is exactly 0.6 similar, the others are below it
+        let data: HashMap<Data, Option<Tensor>> = [item(1, &[3.0, 4.0]), item(2, &[1.0, 3.0]), item(3, &[-1.0, 2.0])].into_iter().collect();
+        let negative = [tensor(&[1.0, 0.0])];
+        let recommend = |threshold: f32, action: NegativeAction| -> Vec<(String, f32)> {
+            get_feedback_recommendations(&data, &[], &[0.0, 1.0], &negative, threshold, action, "NONE", 3).unwrap()
+        };
+        let ids = |ranked: &[(String, f32)]| -> Vec<String> { ranked.iter().map(|(name, _)| name.clone()).collect() };
+
+        let kept = recommend(0.6, NegativeAction::Keep);
+        assert_eq!(ids(&kept), vec!["Item 2", "Item 3", "Item 1"]);
+        assert!((kept[2].1 - 0.8).abs() < 1e-6);
+
+        let demoted = recommend(0.6, NegativeAction::Demote(0.9));
+        assert_eq!(ids(&demoted), vec!["Item 2", "Item 3", "Item 1"]);
+        assert!((demoted[2].1 + 0.1).abs() < 1e-6);
+
+        assert_eq!(ids(&recommend(0.6, NegativeAction::Drop)), vec!["Item 2", "Item 3"]);
+        // Just above its similarity Item 1 is left alone
+        assert_eq!(ids(&recommend(0.61, NegativeAction::Drop)), vec!["Item 2", "Item 3", "Item 1"]);
+    }
+}
@@ -0,0 +1,159 @@
+use super::pagination::rank_order;
+use super::recommendation::{query_encoder, score_items};
+use super::types::{Args, Data, MultiVectorIndex, TokenMatrix};
+use super::utils::{cosine_similarity, embed_tokens, mean_pool, normalize_l2, run_batch, split_tokens};
+use anyhow::Result;
+use candle::Tensor;
+use clap::Parser;
+use std::collections::HashMap;
+
+/// The scale between a token embedding value and its compressed byte
+const SCALE: f32 = 127.0;
+
+/// Compresses L2 normalized token embeddings to one byte per value
+pub(crate) fn compress(tokens: &[Vec<f32>]) -> TokenMatrix {
+    let dimensions = tokens.first().map_or(0, |token| token.len());
+    let values: Vec<i8> = tokens
+        .iter()
+        .flatten()
+        .map(|x| (x * SCALE).round().clamp(-SCALE, SCALE) as i8)
+        .collect();
+    TokenMatrix { num_tokens: tokens.len(), dimensions, values }
+}
+
+/// Receives the model and keeps the embedding of every token of every summary
+///
+/// @param `data` - the model
+/// @param `batch_size` - the number of summaries run through the model at once
+///
+/// @return `Ok()` with the index [OR] `Err()` if the model couldn't be loaded or the summaries couldn't be embedded
+pub(crate) fn build_multi_vector_index(data: &HashMap<Data, Option<Tensor>>, batch_size: usize) -> Result<MultiVectorIndex> {
+    let args = Args::parse();
+    let (model, mut tokenizer) = args.build_model_and_tokenizer()?;
+    if let Some(pp) = tokenizer.get_padding_mut() {
+        pp.strategy = tokenizers::PaddingStrategy::BatchLongest
+    }
+
+    let keys: Vec<&Data> = data.keys().collect();
+    let mut items: HashMap<Data, TokenMatrix> = HashMap::new();
+    for batch in keys.chunks(batch_size.max(1)) {
+        let summaries: Vec<String> = batch.iter().map(|key| args.document_text(&key.summary)).collect();
+        for (key, tokens) in batch.iter().zip(embed_tokens(&model, &tokenizer, summaries.iter().map(|x| x.as_str()).collect())?) {
+            items.insert((*key).clone(), compress(&tokens));
+        }
+    }
+    Ok(MultiVectorIndex { items })
+}
+
+/// Receives a query and returns both its pooled embedding, for the first stage, and its token embeddings, for late interaction.
+/// The query is embedded with the same model as every other query, which is only loaded once.
+///
+/// @param `query` - the description
+///
+/// @return `Ok()` with the pooled embedding and the token embeddings [OR] `Err()`
+pub(crate) fn embed_query_tokens(query: &str) -> Result<(Tensor, Vec<Vec<f32>>)> {
+    let encoder = query_encoder()?;
+    let query = encoder.args.query_text(query);
+    // Both come from the same forward pass
+    let (embeddings, attention_mask) = run_batch(&encoder.model, &encoder.tokenizer, vec![query.as_str()])?;
+    let pooled = normalize_l2(&mean_pool(&embeddings, &attention_mask)?)?.get(0)?;
+    let tokens = split_tokens(&embeddings, &attention_mask)?.into_iter().next().unwrap_or_default();
+    Ok((pooled, tokens))
+}
+
+/// MaxSim: every query token is matched with its most similar token of the summary, and the similarities are averaged
+/// over the query tokens so that the score is between -1 and 1 like the cosine similarity
+pub(crate) fn max_sim(query_tokens: &[Vec<f32>], document: &TokenMatrix) -> f32 {
+    if query_tokens.is_empty() || document.num_tokens == 0 {
+        return 0.0;
+    }
+    let total: f32 = query_tokens
+        .iter()
+        .map(|query_token| {
+            document
+                .values
+                .chunks(document.dimensions)
+                .map(|token| query_token.iter().zip(token.iter()).map(|(q, d)| q * (*d as f32)).sum::<f32>() / SCALE)
+                .fold(f32::MIN, f32::max)
+        })
+        .sum();
+    total / query_tokens.len() as f32
+}
+
+/// Finds candidates with the pooled embeddings and re-ranks them with late interaction
+///
+/// @param `data` - the model
+/// @param `index` - the token embeddings of the summaries
+/// @param `exclude` - items that should never be recommended
+/// @param `input_embedding` - the pooled embedding of the query
+/// @param `query_tokens` - the token embeddings of the query
+/// @param `tags_input` - the tags to filter by, each tag separated by a comma, or NONE
+/// @param `num_candidates` - the number of candidates found with the pooled embeddings, at least `num_recommendations` are used
+/// @param `num_recommendations` - the number of recommendations
+///
+/// @return `Ok()` with (Item name, MaxSim score) tuples [OR] `Err()` if an item has no embedding or a candidate isn't in the index
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn get_late_interaction_recommendations(
+    data: &HashMap<Data, Option<Tensor>>,
+    index: &MultiVectorIndex,
+    exclude: &[Data],
+    input_embedding: &Tensor,
+    query_tokens: &[Vec<f32>],
+    tags_input: &str,
+    num_candidates: usize,
+    num_recommendations: usize,
+) -> Result<Vec<(String, f32)>, ()> {
+    let input_vector = input_embedding.to_vec1::<f32>().map_err(|_| ())?;
+    let mut candidates = score_items(data, exclude, tags_input, |map_embedding| {
+        Ok(cosine_similarity(&input_vector, &map_embedding.to_vec1::<f32>().map_err(|_| ())?))
+    })?;
+    candidates.sort_by(rank_order);
+    candidates.truncate(num_candidates.max(num_recommendations));
+
+    let mut reranked: Vec<(Data, f32)> = Vec::with_capacity(candidates.len());
+    for (key, _) in candidates {
+        let tokens = index.items.get(&key).ok_or(())?;
+        let score = max_sim(query_tokens, tokens);
+        reranked.push((key, score));
+    }
+    reranked.sort_by(rank_order);
+    reranked.truncate(num_recommendations);
+
+    // Only the candidates that were scored, a placeholder score wouldn't be comparable with MaxSim
+    Ok(reranked.into_iter().map(|(key, score)| (key.name, score)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle::Device;
+
+    #[test]
+    fn only_scored_candidates_are_returned() {
+        // (id, pooled embedding, token embeddings)
+        let items: [(i32, [f32; 2], Vec<Vec<f32>>); 3] = [
+            (1, [1.0, 0.0], vec![vec![1.0, 0.0]]),
+            (2, [0.8, 0.6], vec![vec![-1.0, 0.0], vec![0.0, 1.0]]),
+            (3, [0.0, 1.0], vec![vec![0.0, 1.0]]),
+        ];
+        let mut data: HashMap<Data, Option<Tensor>> = HashMap::new();
+        let mut index = MultiVectorIndex { items: HashMap::new() };
+        for (id, pooled, tokens) in items {
+            let key = Data { id, name: format!("Item {}", id), summary: String::new(), tags: Vec::new() };
+            data.insert(key.clone(), Some(Tensor::new(&pooled, &Device::Cpu).unwrap()));
+            index.items.insert(key, compress(&tokens));
+        }
+        let query = Tensor::new(&[1.0f32, 0.0], &Device::Cpu).unwrap();
+        let query_tokens = vec![vec![-1.0, 0.0]];
+
+        // Only three items can be scored, so no more than three come back
+        let ranked = get_late_interaction_recommendations(&data, &index, &[], &query, &query_tokens, "NONE", 2, 5).unwrap();
+        assert_eq!(ranked.len(), 3);
+
+        // Item 3 isn't a candidate, and the tokens of Item 2 match the query better than those of Item 1
+        let ranked = get_late_interaction_recommendations(&data, &index, &[], &query, &query_tokens, "NONE", 2, 2).unwrap();
+        let names: Vec<&str> = ranked.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["Item 2", "Item 1"]);
+        assert!((ranked[0].1 - 1.0).abs() < 1e-6 && (ranked[1].1 + 1.0).abs() < 1e-6, "{:?}", ranked);
+    }
+}
@@ -0,0 +1,194 @@
+use super::types::{Data, ItemLookup};
+use candle::Tensor;
+use std::collections::HashMap;
+use strsim::normalized_levenshtein;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// A candidate needs at least this score to be resolved to without the user choosing it
+const MATCH_THRESHOLD: f64 = 0.6;
+/// The best candidate needs to beat the second best by this much to be resolved to
+const MATCH_MARGIN: f64 = 0.1;
+/// Candidates below this score aren't suggested
+const SUGGESTION_THRESHOLD: f64 = 0.35;
+/// The maximum number of suggestions returned when the query is ambiguous
+const MAX_SUGGESTIONS: usize = 5;
+/// Two words count as the same word if they are at least this similar, so that typos still match
+const TOKEN_THRESHOLD: f64 = 0.75;
+
+/// Lowercases a name, strips accents, folds compatibility forms (full width letters, ligatures) and replaces
+/// punctuation with spaces so that "Amélie" matches "amelie", "ﬁnding" matches "finding" and "Spider-Man" matches "spider man"
+pub(crate) fn normalize_name(name: &str) -> String {
+    let stripped: String = name
+        .nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { ' ' })
+        .collect::<String>()
+        .to_lowercase();
+    stripped.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// How similar a query is to a name, between 0 and 1. Both are expected to already be normalized.
+/// The score is the average of the normalized edit distance of the whole strings and how well their words overlap.
+pub(crate) fn name_similarity(query: &str, name: &str) -> f64 {
+    // Words that were joined or split, like "spiderman" and "spider man", still count as an exact match
+    if query.replace(' ', "") == name.replace(' ', "") {
+        return 1.0;
+    }
+    let edit = normalized_levenshtein(query, name);
+
+    let query_tokens: Vec<&str> = query.split(' ').filter(|token| !token.is_empty()).collect();
+    let name_tokens: Vec<&str> = name.split(' ').filter(|token| !token.is_empty()).collect();
+    if query_tokens.is_empty() || name_tokens.is_empty() {
+        return edit;
+    }
+    let overlap = (token_coverage(&query_tokens, &name_tokens) + token_coverage(&name_tokens, &query_tokens)) / 2.0;
+
+    (edit + overlap) / 2.0
+}
+
+/// The fraction of `from` words that have a close match in `to`
+fn token_coverage(from: &[&str], to: &[&str]) -> f64 {
+    let matched = from
+        .iter()
+        .filter(|token| to.iter().any(|other| normalized_levenshtein(token, other) >= TOKEN_THRESHOLD))
+        .count();
+    matched as f64 / from.len() as f64
+}
+
+/// Receives the name of an item and finds it in the model, allowing for typos, missing words,
+/// accents and punctuation. An exact match (after normalizing) always wins, otherwise the best match
+/// is used if it is good enough and clearly better than the rest.
+///
+/// @param `data` - the model
+/// @param `item_name` - the name the user typed
+///
+/// @return `Found` with the item [OR] `Collision` with every item that has exactly that name [OR]
+/// `Ambiguous` with ranked suggestions [OR] `NotFound`
+pub(crate) fn lookup_item(data: &HashMap<Data, Option<Tensor>>, item_name: &str) -> ItemLookup {
+    let query = normalize_name(item_name);
+    if query.is_empty() {
+        return ItemLookup::NotFound;
+    }
+
+    let mut candidates: Vec<(Data, f64)> = data
+        .keys()
+        .map(|key| (key.clone(), name_similarity(&query, &normalize_name(&key.name))))
+        .filter(|(_, score)| *score >= SUGGESTION_THRESHOLD)
+        .collect();
+    // Highest score first, ties broken by id so the suggestions are always in the same order
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.id.cmp(&b.0.id)));
+
+    // Exact matches are already ordered by id because they all have the same score
+    let exact: Vec<&(Data, f64)> = candidates.iter().filter(|(_, score)| *score >= 1.0).collect();
+    if exact.len() == 1 {
+        return ItemLookup::Found(exact[0].0.clone());
+    }
+    if exact.len() > 1 {
+        return ItemLookup::Collision(exact.into_iter().map(|(key, _)| key.clone()).collect());
+    }
+    match candidates.as_slice() {
+        [] => return ItemLookup::NotFound,
+        [(best, score)] if *score >= MATCH_THRESHOLD => return ItemLookup::Found(best.clone()),
+        [(best, score), (_, second), ..] if *score >= MATCH_THRESHOLD && score - second >= MATCH_MARGIN => {
+            return ItemLookup::Found(best.clone())
+        },
+        _ => {},
+    }
+
+    candidates.truncate(MAX_SUGGESTIONS);
+    ItemLookup::Ambiguous(candidates.into_iter().map(|(key, score)| (key, score as f32)).collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a lookup is expected to return, by id
+    #[derive(Debug, PartialEq)]
+    enum Expected {
+        Found(i32),
+        Collision(Vec<i32>),
+        Ambiguous(Vec<i32>),
+        NotFound,
+    }
+
+    fn simplify(lookup: ItemLookup) -> Expected {
+        match lookup {
+            ItemLookup::Found(item) => Expected::Found(item.id),
+            ItemLookup::Collision(items) => Expected::Collision(items.iter().map(|item| item.id).collect()),
+            ItemLookup::Ambiguous(suggestions) => Expected::Ambiguous(suggestions.iter().map(|(item, _)| item.id).collect()),
+            ItemLookup::NotFound => Expected::NotFound,
+        }
+    }
+
+    fn catalog() -> HashMap<Data, Option<Tensor>> {
+        ["The Dark Knight", "Amélie", "Inception", "Toy Story", "Toy Story 2", "Star Wars", "Star Trek", "Heat", "Heat", "Finding Nemo", "Spider-Man"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (Data { id: i as i32 + 1, name: name.to_string(), summary: String::new(), tags: Vec::new() }, None))
+            .collect()
+    }
+
+    #[test]
+    fn names_are_normalized() {
+        let cases = [
+            ("The Dark Knight", "the dark knight"),
+            ("  THE DARK-KNIGHT! ", "the dark knight"),
+            ("Amélie", "amelie"),
+            ("Ｉｎｃｅｐｔｉｏｎ", "inception"),
+            ("ﬁnding Nemo", "finding nemo"),
+            ("...", ""),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(normalize_name(name), expected, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn name_similarity_is_exact_for_joined_words_and_low_for_other_words() {
+        // (query, name, lowest score, highest score)
+        let cases = [
+            ("spiderman", "spider man", 1.0, 1.0),
+            ("incepton", "inception", MATCH_THRESHOLD, 1.0),
+            ("star", "star wars", SUGGESTION_THRESHOLD, MATCH_THRESHOLD),
+            ("heat", "heist", 0.0, SUGGESTION_THRESHOLD),
+            ("zzz", "heat", 0.0, 0.0),
+        ];
+        for (query, name, low, high) in cases {
+            let score = name_similarity(query, name);
+            assert!((low..=high).contains(&score), "{:?} and {:?} scored {}", query, name, score);
+        }
+    }
+
+    #[test]
+    fn lookups_resolve_collide_suggest_or_fail() {
+        let data = catalog();
+        let cases = [
+            // Exact after normalizing
+            ("the dark knight", Expected::Found(1)),
+            ("THE DARK KNIGHT!", Expected::Found(1)),
+            ("amelie", Expected::Found(2)),
+            ("Ｉｎｃｅｐｔｉｏｎ", Expected::Found(3)),
+            ("ﬁnding nemo", Expected::Found(10)),
+            ("spiderman", Expected::Found(11)),
+            ("heat", Expected::Collision(vec![8, 9])),
+            // Above MATCH_THRESHOLD and alone
+            ("incepton", Expected::Found(3)),
+            // Above MATCH_THRESHOLD and more than MATCH_MARGIN ahead of "Toy Story 2"
+            ("toy stor", Expected::Found(4)),
+            // Above MATCH_THRESHOLD but tied, so within MATCH_MARGIN
+            ("heet", Expected::Ambiguous(vec![8, 9])),
+            // Between SUGGESTION_THRESHOLD and MATCH_THRESHOLD
+            ("star", Expected::Ambiguous(vec![6, 7])),
+            ("dark", Expected::Ambiguous(vec![1])),
+            // Below SUGGESTION_THRESHOLD
+            ("zzz", Expected::NotFound),
+            ("   ", Expected::NotFound),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(simplify(lookup_item(&data, query)), expected, "{:?}", query);
+        }
+    }
+}
@@ -2,3 +2,22 @@ pub(crate) mod types;
 pub(crate) mod pre_recommendation;
 pub(crate) mod recommendation;
 pub(crate) mod utils;
+pub(crate) mod sqlite;
+pub(crate) mod validation;
+pub(crate) mod lookup;
+pub(crate) mod feedback;
+pub(crate) mod pagination;
+pub(crate) mod reranking;
+pub(crate) mod constraints;
+pub(crate) mod query_analysis;
+pub(crate) mod tags;
+pub(crate) mod auto_tagging;
+pub(crate) mod tag_audit;
+pub(crate) mod explanation;
+pub(crate) mod diagnostics;
+pub(crate) mod chunking;
+pub(crate) mod late_interaction;
+pub(crate) mod cross_encoder;
+pub(crate) mod bert;
+pub(crate) mod encoder;
+pub(crate) mod quantization;
@@ -0,0 +1,140 @@
+use super::types::{Data, Page, PageRequest};
+use anyhow::{Error as E, Result};
+use std::cmp::Ordering;
+
+/// The order recommendations are ranked in: highest score first, then smallest id
+pub(crate) fn rank_order(a: &(Data, f32), b: &(Data, f32)) -> Ordering {
+    b.1.total_cmp(&a.1).then(a.0.id.cmp(&b.0.id))
+}
+
+/// A cursor is the exact score (as its bits, so that it survives the round trip) and the id of the last item on a page
+fn encode_cursor(score: f32, id: i32) -> String {
+    format!("{:08x}:{}", score.to_bits(), id)
+}
+
+fn decode_cursor(cursor: &str) -> Result<(f32, i32)> {
+    let (bits, id) = cursor.split_once(':').ok_or_else(|| E::msg("Invalid cursor"))?;
+    let bits = u32::from_str_radix(bits, 16).map_err(|_| E::msg("Invalid cursor"))?;
+    let id = id.parse::<i32>().map_err(|_| E::msg("Invalid cursor"))?;
+    Ok((f32::from_bits(bits), id))
+}
+
+/// Receives every scored item and cuts out the requested page.
+///
+/// @param `scored` - the items that passed the filters with their scores, in any order
+/// @param `request` - the score floor and which page to return
+///
+/// @return `Ok()` with the page [OR] `Err()` if the cursor is invalid
+pub(crate) fn paginate(mut scored: Vec<(Data, f32)>, request: &PageRequest) -> Result<Page> {
+    if let Some(min_score) = request.min_score {
+        scored.retain(|(_, score)| *score >= min_score);
+    }
+    scored.sort_by(rank_order);
+    let total = scored.len();
+
+    let start = match &request.cursor {
+        Some(cursor) => {
+            let (score, id) = decode_cursor(cursor)?;
+            let last = (Data { id, name: String::new(), summary: String::new(), tags: Vec::new() }, score);
+            // The first item that ranks after the last item of the previous page
+            scored.partition_point(|item| rank_order(item, &last) != Ordering::Greater)
+        },
+        None => request.offset.min(total),
+    };
+    let end = (start + request.limit).min(total);
+
+    let page: Vec<(Data, f32)> = scored.drain(start..end).collect();
+    let next_cursor = match page.last() {
+        Some((key, score)) if end < total => Some(encode_cursor(*score, key.id)),
+        _ => None,
+    };
+    Ok(Page {
+        items: page.into_iter().map(|(key, score)| (key.name, score)).collect(),
+        total,
+        next_cursor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scored(scores: &[(i32, f32)]) -> Vec<(Data, f32)> {
+        scores
+            .iter()
+            .map(|(id, score)| (Data { id: *id, name: format!("Item {}", id), summary: String::new(), tags: Vec::new() }, *score))
+            .collect()
+    }
+
+    /// Ties on 0.8 and 0.5 are listed out of id order on purpose
+    fn catalog() -> Vec<(Data, f32)> {
+        scored(&[(5, 0.8), (2, 0.9), (9, 0.5), (1, 0.8), (7, 0.2), (3, 0.5), (4, 0.8), (8, 0.1)])
+    }
+
+    /// Every page of a request, following offsets or cursors
+    fn walk(items: Vec<(Data, f32)>, min_score: Option<f32>, limit: usize, by_cursor: bool) -> Vec<(String, f32)> {
+        let mut request = PageRequest { min_score, limit, offset: 0, cursor: None };
+        let mut walked: Vec<(String, f32)> = Vec::new();
+        loop {
+            let page = paginate(items.clone(), &request).unwrap();
+            assert!(page.items.len() <= limit);
+            walked.extend(page.items);
+            match page.next_cursor {
+                Some(cursor) if by_cursor => request.cursor = Some(cursor),
+                Some(_) => request.offset += limit,
+                None => return walked,
+            }
+        }
+    }
+
+    fn names(items: &[(String, f32)]) -> Vec<&str> {
+        items.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    #[test]
+    fn ties_are_broken_by_id() {
+        let mut items = catalog();
+        items.sort_by(rank_order);
+        let ids: Vec<i32> = items.iter().map(|(key, _)| key.id).collect();
+        assert_eq!(ids, vec![2, 1, 4, 5, 3, 9, 7, 8]);
+    }
+
+    #[test]
+    fn walking_every_page_has_no_gaps_or_duplicates() {
+        let everything = paginate(catalog(), &PageRequest { limit: 100, ..PageRequest::default() }).unwrap();
+        assert_eq!(everything.total, 8);
+        assert_eq!(everything.next_cursor, None);
+        let expected = names(&everything.items);
+        assert_eq!(expected, vec!["Item 2", "Item 1", "Item 4", "Item 5", "Item 3", "Item 9", "Item 7", "Item 8"]);
+
+        for limit in 1..=9 {
+            for by_cursor in [false, true] {
+                let walked = walk(catalog(), None, limit, by_cursor);
+                assert_eq!(names(&walked), expected, "limit {}, by cursor {}", limit, by_cursor);
+            }
+        }
+    }
+
+    #[test]
+    fn the_score_floor_applies_to_every_page() {
+        for by_cursor in [false, true] {
+            let walked = walk(catalog(), Some(0.5), 2, by_cursor);
+            assert_eq!(names(&walked), vec!["Item 2", "Item 1", "Item 4", "Item 5", "Item 3", "Item 9"]);
+        }
+        let first = paginate(catalog(), &PageRequest { min_score: Some(0.5), limit: 2, offset: 0, cursor: None }).unwrap();
+        assert_eq!(first.total, 6);
+
+        // Past the end there is an empty page and no cursor
+        let past = paginate(catalog(), &PageRequest { min_score: Some(0.5), limit: 2, offset: 10, cursor: None }).unwrap();
+        assert!(past.items.is_empty());
+        assert_eq!(past.next_cursor, None);
+    }
+
+    #[test]
+    fn invalid_cursors_are_rejected() {
+        for cursor in ["", "nonsense", "3f800000", "zz:1", "3f800000:x", ":4"] {
+            let request = PageRequest { cursor: Some(cursor.to_string()), ..PageRequest::default() };
+            assert!(paginate(catalog(), &request).is_err(), "{:?} was accepted", cursor);
+        }
+    }
+}
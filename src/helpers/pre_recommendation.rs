@@ -1,6 +1,8 @@
-use super::types::Data;
+use super::lookup::lookup_item;
+use super::types::{Data, ItemLookup, SkippedLine};
 use std::collections::HashMap;
 use std::fs::File;
+use std::io::{BufRead, BufReader};
 use serde_json::from_reader;
 
 /// Receives the path of a JSON file as a &String. The function tries to open
@@ -33,71 +35,221 @@ pub(crate) fn extract_data(file_name: &String) -> Result<HashMap<Data, Option<Te
         vector_to_map.insert(data.clone(), None);
     }
 
-    return Ok(vector_to_map);
+    Ok(vector_to_map)
 }
 
+/// Receives the path of a JSON file and deserializes it into the list of items exactly as it is
+/// in the file, keeping duplicates so that they can be reported.
+///
+/// @param `file_name` - a String containing the file path of the JSON file
+///
+/// @return `Ok()` with the items [OR] `Err()` if the file didn't open or didn't deserialize
+pub(crate) fn read_items(file_name: &String) -> Result<Vec<Data>> {
+    let file = File::open(file_name)?;
+    Ok(from_reader(BufReader::new(file))?)
+}
+
+/// Receives the path of a JSON Lines file (one Data object per line) as a &String and
+/// streams it into the model. Records are embedded in batches of `batch_size` as they are
+/// read, with as many batches embedded at the same time as there are workers, so only that
+/// many batches of raw records are held in memory at a time.
+/// Lines that are empty are ignored and lines that don't deserialize are skipped and reported.
+///
+/// @param `file_name` - a String containing the file path of the JSON Lines file
+/// @param `batch_size` - the number of records to embed in each forward pass
+///
+/// @return `Ok()` with the model and the lines that were skipped [OR] `Err()` if the file didn't
+/// open, couldn't be read or the embeddings couldn't be created
+#[allow(clippy::type_complexity)]
+pub(crate) fn extract_data_jsonl(file_name: &String, batch_size: usize) -> Result<(HashMap<Data, Option<Tensor>>, Vec<SkippedLine>)> {
+    let file = File::open(file_name)?;
+    let reader = BufReader::new(file);
+
+    let args = Args::parse();
+    let (model, mut tokenizer) = args.build_model_and_tokenizer()?;
+    if let Some(pp) = tokenizer.get_padding_mut() {
+        pp.strategy = tokenizers::PaddingStrategy::BatchLongest
+    }
+
+    let mut nodes: HashMap<Data, Option<Tensor>> = HashMap::new();
+    let batch_size = batch_size.max(1);
+    let skipped = read_jsonl(reader, batch_size * args.workers(), |batch| {
+        insert_batch(&model, &tokenizer, &args, &mut nodes, batch, batch_size)
+    })?;
+
+    Ok((nodes, skipped))
+}
+
+/// Reads a JSON Lines file and hands the records to `on_batch` every time `buffer_size` of them have been read,
+/// and once more at the end with the rest. Lines that are empty are ignored and lines that don't deserialize are skipped.
+///
+/// @param `reader` - the file
+/// @param `buffer_size` - the number of records handed to `on_batch` at a time
+/// @param `on_batch` - called with the records read so far, which it is expected to drain
+///
+/// @return `Ok()` with the lines that were skipped [OR] `Err()` if the file couldn't be read or `on_batch` failed
+fn read_jsonl(reader: impl BufRead, buffer_size: usize, mut on_batch: impl FnMut(&mut Vec<Data>) -> Result<()>) -> Result<Vec<SkippedLine>> {
+    let mut skipped: Vec<SkippedLine> = Vec::new();
+    let buffer_size = buffer_size.max(1);
+    let mut batch: Vec<Data> = Vec::with_capacity(buffer_size);
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Data>(&line) {
+            Ok(data) => batch.push(data),
+            Err(e) => skipped.push(SkippedLine { line_number: index + 1, error: e.to_string() }),
+        }
+        if batch.len() >= buffer_size {
+            on_batch(&mut batch)?;
+        }
+    }
+    if !batch.is_empty() {
+        on_batch(&mut batch)?;
+    }
+
+    Ok(skipped)
+}
+
+/// Embeds every item in `batch` (with the document template of `args`) in batches of `batch_size` spread over the workers,
+/// moves them into `nodes` and leaves `batch` empty
+fn insert_batch(model: &Encoder, tokenizer: &Tokenizer, args: &Args, nodes: &mut HashMap<Data, Option<Tensor>>, batch: &mut Vec<Data>, batch_size: usize) -> Result<()> {
+    let summaries: Vec<String> = batch.iter().map(|data| args.document_text(&data.summary)).collect();
+    let summaries: Vec<&str> = summaries.iter().map(|x| x.as_str()).collect();
+    let embeddings = embed_parallel(model, tokenizer, &summaries, batch_size, args.workers())?;
+    for (data, embedding) in batch.drain(..).zip(embeddings) {
+        nodes.insert(data, Some(embedding));
+    }
+    Ok(())
+}
+
+use super::encoder::Encoder;
 use super::types::Args;
 use super::utils::*;
 use anyhow::{Error as E, Result};
 use candle::Tensor;
 use clap::Parser;
+use tokenizers::Tokenizer;
 
 pub(crate) fn insert_embeddings(data: &mut HashMap<Data, Option<Tensor>>) -> Result<()> {
     let args = Args::parse();
 
     let (model, mut tokenizer) = args.build_model_and_tokenizer()?;
-    let device = &model.device;
 
     if let Some(pp) = tokenizer.get_padding_mut() {
         pp.strategy = tokenizers::PaddingStrategy::BatchLongest
     }
 
-    // Tokenize the data
-    let mut summaries: Vec<&str> = Vec::new();
-    for (key, _value) in data.iter() {
-        summaries.push(key.summary.as_str());
-    }
-    let tokens = tokenizer
-        .encode_batch(summaries.to_vec(), true)
-        .map_err(E::msg)?;
-
-    // Convert the tokens to tensors
-    let token_ids = tokens
-        .iter()
-        .map(|tokens| {
-            let tokens = tokens.get_ids().to_vec();
-            Ok(Tensor::new(tokens.as_slice(), device)?)
-        })
-        .collect::<Result<Vec<_>>>()?;
-
-    let token_ids = Tensor::stack(&token_ids, 0)?;
-    let token_type_ids = token_ids.zeros_like()?;
-    // println!("running inference on batch {:?}", token_ids.shape());
+    // Sorted so that every run puts the same items in the same batches
+    let mut keys: Vec<Data> = data.keys().cloned().collect();
+    keys.sort_by(|a, b| a.id.cmp(&b.id).then(a.name.cmp(&b.name)));
 
     // Get the embeddings
-    let embeddings = model.forward(&token_ids, &token_type_ids)?;
-    // println!("generated embeddings {:?}", embeddings.shape());
-
-    // Pool the embeddings
-    let (_n_sentence, n_tokens, _hidden_size) = embeddings.dims3()?;
-    let embeddings = (embeddings.sum(1)? / (n_tokens as f64))?;
-    let embeddings = normalize_l2(&embeddings)?;
-    // println!("pooled embeddings {:?}", embeddings.shape());
+    let summaries: Vec<String> = keys.iter().map(|key| args.document_text(&key.summary)).collect();
+    let summaries: Vec<&str> = summaries.iter().map(|x| x.as_str()).collect();
+    let embeddings = embed_parallel(&model, &tokenizer, &summaries, args.batch_size, args.workers())?;
 
     // Insert embeddings into data
-    for (i, (_key, value)) in data.iter_mut().enumerate() {
-        *value = Some(embeddings.get(i)?);
+    for (key, embedding) in keys.into_iter().zip(embeddings) {
+        data.insert(key, Some(embedding));
     }
 
     Ok(())
 }
 
-pub(crate) fn find_embedding(data: &HashMap<Data, Option<Tensor>>, item_name: &String) -> Result<Tensor> {
-    let item_name_cleaned = item_name.trim().to_lowercase();
-    for (key, value) in data.iter() {
-        if key.name.to_lowercase() == item_name_cleaned {
-            return Ok(value.clone().unwrap());
-        }
+/// Receives the name of an item and returns the item it resolves to along with its embedding.
+/// The name doesn't have to match exactly, see `lookup_item`. When several items have exactly
+/// that name, the one with the smallest id is used so that the same item is picked every time.
+///
+/// @param `data` - the model
+/// @param `item_name` - the name of the item
+///
+/// @return `Ok()` with the item and its embedding [OR] `Err()` if the name didn't resolve to an item
+pub(crate) fn find_embedding(data: &HashMap<Data, Option<Tensor>>, item_name: &str) -> Result<(Data, Tensor)> {
+    let item = match lookup_item(data, item_name) {
+        ItemLookup::Found(item) => item,
+        ItemLookup::Collision(mut items) => items.remove(0),
+        _ => return Err(E::msg("Item not found")),
+    };
+    embedding_of(data, item)
+}
+
+/// Receives the id of an item and returns the item along with its embedding
+///
+/// @param `data` - the model
+/// @param `id` - the id of the item
+///
+/// @return `Ok()` with the item and its embedding [OR] `Err()` if no item or more than one item has that id
+pub(crate) fn find_embedding_by_id(data: &HashMap<Data, Option<Tensor>>, id: i32) -> Result<(Data, Tensor)> {
+    let mut matches = data.keys().filter(|key| key.id == id);
+    let item = match (matches.next(), matches.next()) {
+        (Some(item), None) => item.clone(),
+        (Some(_), Some(_)) => return Err(E::msg("More than one item has this id")),
+        _ => return Err(E::msg("Item not found")),
+    };
+    embedding_of(data, item)
+}
+
+fn embedding_of(data: &HashMap<Data, Option<Tensor>>, item: Data) -> Result<(Data, Tensor)> {
+    match data.get(&item) {
+        Some(Some(embedding)) => Ok((item, embedding.clone())),
+        _ => Err(E::msg("Item has no embedding")),
     }
-    Err(E::msg("Item not found"))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::recommendation::get_recommendations;
+    use candle::Device;
+
+    #[test]
+    fn malformed_and_blank_lines_are_skipped_with_their_line_numbers() {
+        let path = std::env::temp_dir().join(format!("reco_forge_skipped_lines_{}.jsonl", std::process::id()));
+        let lines = [
+            r#"{"id": 1, "name": "Heat", "summary": "A heist.", "tags": ["Crime"]}"#,
+            "",
+            r#"{"id": 2, "name": "Alien", "summary": "#,
+            r#"{"id": 3, "name": "Up", "summary": "A balloon.", "tags": ["Animation"]}"#,
+            "   ",
+            r#"{"id": "four", "name": "Big", "summary": "A wish.", "tags": []}"#,
+            r#"{"id": 5, "name": "Jaws", "summary": "A shark.", "tags": ["Thriller"]}"#,
+        ];
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let mut batches: Vec<Vec<i32>> = Vec::new();
+        let file = File::open(&path).unwrap();
+        let skipped = read_jsonl(BufReader::new(file), 2, |batch| {
+            batches.push(batch.drain(..).map(|data| data.id).collect());
+            Ok(())
+        })
+        .unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(batches, vec![vec![1, 3], vec![5]]);
+        let line_numbers: Vec<usize> = skipped.iter().map(|line| line.line_number).collect();
+        assert_eq!(line_numbers, vec![3, 6]);
+        assert!(skipped.iter().all(|line| !line.error.is_empty()));
+    }
+
+    #[test]
+    fn a_name_collision_uses_the_smallest_id_and_only_excludes_it() {
+        let item = |id: i32, name: &str, embedding: &[f32]| {
+            let data = Data { id, name: name.to_string(), summary: String::new(), tags: Vec::new() };
+            (data, Some(Tensor::new(embedding, &Device::Cpu).unwrap()))
+        };
+        let data: HashMap<Data, Option<Tensor>> =
+            [item(9, "Heat", &[0.9, 0.1]), item(4, "Heat", &[1.0, 0.0]), item(7, "Up", &[0.3, 1.0])].into_iter().collect();
+
+        let (seed, embedding) = find_embedding(&data, "heat").unwrap();
+        assert_eq!(seed.id, 4);
+        assert_eq!(embedding.to_vec1::<f32>().unwrap(), vec![1.0, 0.0]);
+
+        // The other "Heat" is still the closest recommendation
+        let recommendations = get_recommendations(&data, &[seed], &embedding, "NONE", 2).unwrap();
+        let names: Vec<&str> = recommendations.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["Heat", "Up"]);
+    }
+}
@@ -0,0 +1,249 @@
+use super::bert::{self, Activation, BertEmbeddings, BertLayer, BertModel};
+use super::encoder::{detect_architecture, Encoder};
+use super::pre_recommendation::read_items;
+use super::types::{Architecture, Args, Quantization, QuantizationReport};
+use super::utils::{cosine_similarity, embed_batch};
+use anyhow::{Error as E, Result};
+use candle::quantized::{gguf_file, GgmlDType, QTensor};
+use candle::{DType, Device, Tensor};
+use candle_nn::Embedding;
+use candle_transformers::quantized_nn::{self, layer_norm};
+use candle_transformers::quantized_var_builder::VarBuilder;
+use clap::Parser;
+use std::collections::HashSet;
+use std::time::Instant;
+
+/// The number of texts embedded at once while comparing the models
+const COMPARISON_BATCH_SIZE: usize = 32;
+
+/// The metadata keys the BERT config is saved under in the GGUF file
+const HIDDEN_SIZE: &str = "bert.hidden_size";
+const INTERMEDIATE_SIZE: &str = "bert.intermediate_size";
+const NUM_HIDDEN_LAYERS: &str = "bert.num_hidden_layers";
+const NUM_ATTENTION_HEADS: &str = "bert.num_attention_heads";
+const LAYER_NORM_EPS: &str = "bert.layer_norm_eps";
+const HIDDEN_ACT: &str = "bert.hidden_act";
+const QUANTIZATION: &str = "reco_forge.quantization";
+
+impl Quantization {
+    fn dtype(&self) -> GgmlDType {
+        match self {
+            Quantization::Q8_0 => GgmlDType::Q8_0,
+            Quantization::Q6K => GgmlDType::Q6K,
+            Quantization::Q5_0 => GgmlDType::Q5_0,
+            Quantization::Q5_1 => GgmlDType::Q5_1,
+            Quantization::Q4K => GgmlDType::Q4K,
+            Quantization::Q4_0 => GgmlDType::Q4_0,
+            Quantization::Q4_1 => GgmlDType::Q4_1,
+        }
+    }
+}
+
+fn embedding(vb: &VarBuilder, name: &str) -> Result<Embedding> {
+    let weights = vb.get_no_shape(&format!("{}.weight", name))?.dequantize(vb.device())?;
+    let (_, hidden_size) = weights.dims2()?;
+    Ok(Embedding::new(weights, hidden_size))
+}
+
+fn linear(in_dim: usize, out_dim: usize, vb: VarBuilder) -> Result<bert::Linear> {
+    Ok(Box::new(quantized_nn::linear(in_dim, out_dim, vb)?))
+}
+
+/// Loads a BERT model saved by `quantize_weights`. The linear layers run on the quantized weights while the embeddings
+/// and layer norms are kept in f32, so it only differs from the f32 model by the quantization.
+///
+/// @param `path` - the path of the GGUF file
+/// @param `approximate_gelu` - whether to use the tanh approximation of Gelu, like the f32 model
+///
+/// @return `Ok()` with the model and the quantization type it was saved with [OR] `Err()` if the file couldn't be read
+/// or wasn't written by `quantize_weights`
+pub(crate) fn load_quantized_bert(path: &str, approximate_gelu: bool) -> Result<(BertModel, String)> {
+    let device = Device::Cpu;
+    let content = gguf_file::Content::read(&mut std::fs::File::open(path)?)?;
+    let metadata = |key: &str| content.metadata.get(key).ok_or(E::msg(format!("{} has no {}, was it written by quantize?", path, key)));
+    let number = |key: &str| -> Result<usize> { Ok(metadata(key)?.to_u32()? as usize) };
+    let (hidden, intermediate, eps) = (number(HIDDEN_SIZE)?, number(INTERMEDIATE_SIZE)?, metadata(LAYER_NORM_EPS)?.to_f64()?);
+    let activation = Activation::from_config(Some(metadata(HIDDEN_ACT)?.to_string()?), approximate_gelu)?;
+    let quantization = metadata(QUANTIZATION)?.to_string()?.clone();
+
+    let vb = VarBuilder::from_gguf(path, &device)?;
+    let embeddings_vb = vb.pp("embeddings");
+    let embeddings = BertEmbeddings {
+        word_embeddings: embedding(&embeddings_vb, "word_embeddings")?,
+        position_embeddings: embedding(&embeddings_vb, "position_embeddings")?,
+        token_type_embeddings: embedding(&embeddings_vb, "token_type_embeddings")?,
+        layer_norm: layer_norm(hidden, eps, embeddings_vb.pp("LayerNorm"))?,
+    };
+    let mut layers: Vec<BertLayer> = Vec::new();
+    for i in 0..number(NUM_HIDDEN_LAYERS)? {
+        let vb = vb.pp(format!("encoder.layer.{}", i));
+        layers.push(BertLayer {
+            query: linear(hidden, hidden, vb.pp("attention.self.query"))?,
+            key: linear(hidden, hidden, vb.pp("attention.self.key"))?,
+            value: linear(hidden, hidden, vb.pp("attention.self.value"))?,
+            attention_output: linear(hidden, hidden, vb.pp("attention.output.dense"))?,
+            attention_layer_norm: layer_norm(hidden, eps, vb.pp("attention.output.LayerNorm"))?,
+            intermediate: linear(hidden, intermediate, vb.pp("intermediate.dense"))?,
+            output: linear(intermediate, hidden, vb.pp("output.dense"))?,
+            output_layer_norm: layer_norm(hidden, eps, vb.pp("output.LayerNorm"))?,
+        });
+    }
+    let model = BertModel {
+        embeddings,
+        layers,
+        num_attention_heads: number(NUM_ATTENTION_HEADS)?,
+        activation,
+        device,
+    };
+    Ok((model, quantization))
+}
+
+/// Reads the quantization type a GGUF file written by `quantize_weights` was saved with, without loading the weights
+pub(crate) fn read_quantization(path: &str) -> Result<String> {
+    let content = gguf_file::Content::read(&mut std::fs::File::open(path)?)?;
+    match content.metadata.get(QUANTIZATION) {
+        Some(value) => Ok(value.to_string()?.clone()),
+        None => Err(E::msg(format!("{} has no {}, was it written by quantize?", path, QUANTIZATION))),
+    }
+}
+
+/// Quantizes the weights of the model set by the arguments and writes them into a GGUF file. The weight matrices of the
+/// linear layers are quantized while the embeddings, layer norms and biases are kept in f32. Matrices whose rows don't
+/// split into whole quantization blocks are kept in f32 too.
+///
+/// @param `quantization` - the quantization type
+/// @param `output` - the path of the GGUF file
+///
+/// @return `Ok()` with the number of matrices that were quantized [OR] `Err()` if the model isn't a BERT model or
+/// couldn't be read, or the file couldn't be written
+pub(crate) fn quantize_weights(quantization: Quantization, output: &String) -> Result<usize> {
+    let args = Args::parse();
+    let config: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(args.model_file("config.json")?)?)?;
+    if detect_architecture(&config) != Architecture::Bert {
+        return Err(E::msg("Only BERT models can be quantized"));
+    }
+    let number = |key: &str| config[key].as_u64().map(|x| x as u32).ok_or(E::msg(format!("config.json has no {}", key)));
+    let hidden_act = Activation::from_config(config["hidden_act"].as_str(), false)?.name();
+    let metadata = [
+        ("general.architecture", gguf_file::Value::String("bert".to_string())),
+        (HIDDEN_SIZE, gguf_file::Value::U32(number("hidden_size")?)),
+        (INTERMEDIATE_SIZE, gguf_file::Value::U32(number("intermediate_size")?)),
+        (NUM_HIDDEN_LAYERS, gguf_file::Value::U32(number("num_hidden_layers")?)),
+        (NUM_ATTENTION_HEADS, gguf_file::Value::U32(number("num_attention_heads")?)),
+        (LAYER_NORM_EPS, gguf_file::Value::F64(config["layer_norm_eps"].as_f64().unwrap_or(1e-12))),
+        (HIDDEN_ACT, gguf_file::Value::String(hidden_act.to_string())),
+        (QUANTIZATION, gguf_file::Value::String(quantization.to_string())),
+    ];
+
+    let weights = args.weights_file()?;
+    let tensors: Vec<(String, Tensor)> = match weights.extension().and_then(|x| x.to_str()) {
+        Some("safetensors") => candle::safetensors::load(&weights, &Device::Cpu)?.into_iter().collect(),
+        _ => candle::pickle::read_all(&weights)?,
+    };
+    let dtype = quantization.dtype();
+    let mut quantized = 0;
+    let mut qtensors: Vec<(String, QTensor)> = Vec::new();
+    for (name, tensor) in tensors {
+        // Checkpoints saved from BertFor... classes have a bert. prefix, and the pooler and heads aren't needed
+        let name = name.strip_prefix("bert.").unwrap_or(&name).to_string();
+        if !name.starts_with("embeddings.") && !name.starts_with("encoder.") {
+            continue;
+        }
+        let tensor = tensor.to_dtype(DType::F32)?;
+        let is_linear = name.starts_with("encoder.") && name.ends_with(".weight") && tensor.rank() == 2;
+        let fits_blocks = tensor.dims().last().is_some_and(|x| x % dtype.block_size() == 0);
+        let qtensor = match is_linear && fits_blocks {
+            true => {
+                quantized += 1;
+                QTensor::quantize(&tensor, dtype)?
+            },
+            false => QTensor::quantize(&tensor, GgmlDType::F32)?,
+        };
+        qtensors.push((name, qtensor));
+    }
+    qtensors.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let metadata: Vec<(&str, &gguf_file::Value)> = metadata.iter().map(|(key, value)| (*key, value)).collect();
+    let qtensors: Vec<(&str, &QTensor)> = qtensors.iter().map(|(name, qtensor)| (name.as_str(), qtensor)).collect();
+    let mut file = std::fs::File::create(output)?;
+    gguf_file::write(&mut file, &metadata, &qtensors)?;
+    Ok(quantized)
+}
+
+/// Embeds `texts` in batches, returning the embeddings and how long it took in seconds
+fn timed_embeddings(model: &Encoder, tokenizer: &tokenizers::Tokenizer, texts: &[String]) -> Result<(Vec<Vec<f32>>, f64)> {
+    let start = Instant::now();
+    let mut embeddings: Vec<Vec<f32>> = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(COMPARISON_BATCH_SIZE) {
+        let batch_embeddings = embed_batch(model, tokenizer, batch.iter().map(|x| x.as_str()).collect())?;
+        embeddings.extend(batch_embeddings.to_vec2::<f32>()?);
+    }
+    Ok((embeddings, start.elapsed().as_secs_f64()))
+}
+
+/// The `num_neighbours` most similar embeddings to the one at `i`, by index
+fn neighbours(embeddings: &[Vec<f32>], i: usize, num_neighbours: usize) -> HashSet<usize> {
+    let mut similarities: Vec<(usize, f32)> = embeddings
+        .iter()
+        .enumerate()
+        .filter(|(j, _)| *j != i)
+        .map(|(j, other)| (j, cosine_similarity(&embeddings[i], other)))
+        .collect();
+    similarities.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    similarities.into_iter().take(num_neighbours).map(|(j, _)| j).collect()
+}
+
+/// Embeds a sample of the items with both the f32 model and a quantized one and compares them
+///
+/// @param `file_name` - the JSON file with the items
+/// @param `quantized_path` - the GGUF file written by `quantize_weights`
+/// @param `sample_size` - the number of items, taken from the start of the file
+/// @param `num_neighbours` - the number of nearest neighbours compared for each item
+///
+/// @return `Ok()` with the report [OR] `Err()` if a model or the file couldn't be read or the items couldn't be embedded
+pub(crate) fn compare_quantized(file_name: &String, quantized_path: &String, sample_size: usize, num_neighbours: usize) -> Result<QuantizationReport> {
+    let args = Args::parse();
+    let items = read_items(file_name)?;
+    let texts: Vec<String> = items.iter().take(sample_size).map(|item| args.document_text(&item.summary)).collect();
+    if texts.is_empty() {
+        return Err(E::msg("The file has no items"));
+    }
+
+    let (f32_model, mut tokenizer) = args.build_model_and_tokenizer_with(None)?;
+    if let Some(pp) = tokenizer.get_padding_mut() {
+        pp.strategy = tokenizers::PaddingStrategy::BatchLongest
+    }
+    let (quantized_model, quantization) = load_quantized_bert(quantized_path, args.approximate_gelu)?;
+    let quantized_model = Encoder::QuantizedBert(quantized_model);
+    let (f32_embeddings, f32_seconds) = timed_embeddings(&f32_model, &tokenizer, &texts)?;
+    let (quantized_embeddings, quantized_seconds) = timed_embeddings(&quantized_model, &tokenizer, &texts)?;
+
+    let similarities: Vec<f32> = f32_embeddings.iter().zip(quantized_embeddings.iter()).map(|(a, b)| cosine_similarity(a, b)).collect();
+    let num_neighbours = num_neighbours.min(texts.len() - 1);
+    let neighbour_overlap = match num_neighbours {
+        0 => 1.0,
+        _ => {
+            let overlaps: f32 = (0..texts.len())
+                .map(|i| {
+                    let expected = neighbours(&f32_embeddings, i, num_neighbours);
+                    let found = neighbours(&quantized_embeddings, i, num_neighbours);
+                    expected.intersection(&found).count() as f32 / num_neighbours as f32
+                })
+                .sum();
+            overlaps / texts.len() as f32
+        },
+    };
+
+    Ok(QuantizationReport {
+        quantization,
+        num_items: texts.len(),
+        mean_similarity: similarities.iter().sum::<f32>() / similarities.len() as f32,
+        min_similarity: similarities.iter().copied().fold(f32::MAX, f32::min),
+        num_neighbours,
+        neighbour_overlap,
+        f32_seconds,
+        quantized_seconds,
+        f32_bytes: std::fs::metadata(args.weights_file()?)?.len(),
+        quantized_bytes: std::fs::metadata(quantized_path)?.len(),
+    })
+}
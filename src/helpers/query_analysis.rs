@@ -0,0 +1,192 @@
+use super::lookup::normalize_name;
+use super::recommendation::create_input_embeddings;
+use super::types::{AnalyzerOptions, Data, QueryInterpretation, TagMatch, TagMatchKind};
+use super::utils::cosine_similarity;
+use anyhow::Result;
+use candle::Tensor;
+use std::collections::HashMap;
+
+/// Synonyms that are used whenever the tag they point to is in the model
+const BUILT_IN_SYNONYMS: &[(&str, &str)] = &[
+    ("sci fi", "science fiction"),
+    ("scifi", "science fiction"),
+    ("sf", "science fiction"),
+    ("rpg", "role playing rpg"),
+    ("role playing", "role playing rpg"),
+    ("rom com", "romance"),
+    ("romantic", "romance"),
+    ("funny", "comedy"),
+    ("scary", "horror"),
+    ("animated", "animation"),
+    ("cartoon", "animation"),
+    ("fps", "shooter"),
+    ("shooting", "shooter"),
+    ("documentaries", "documentary"),
+];
+
+/// Words that are never matched to a tag by embedding because they say nothing about the kind of item
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "the", "and", "or", "of", "about", "with", "for", "in", "on", "to", "like", "some", "that", "is",
+    "movie", "movies", "film", "films", "game", "games", "show", "shows", "something", "me",
+];
+
+/// The longest phrase, in words, that is matched against tag names and synonyms
+const MAX_PHRASE_WORDS: usize = 4;
+
+/// Every tag in the model keyed by its normalized name. Items can spell a tag differently ("Sci-Fi", "sci fi"), so
+/// the tag is given as every spelling separated by a |, most common first, for the tag filter to match all of them.
+pub(crate) fn tag_spellings(data: &HashMap<Data, Option<Tensor>>) -> HashMap<String, String> {
+    let mut spellings: HashMap<String, HashMap<String, usize>> = HashMap::new();
+    for key in data.keys() {
+        for tag in &key.tags {
+            *spellings.entry(normalize_name(tag)).or_default().entry(tag.trim().to_string()).or_insert(0) += 1;
+        }
+    }
+    spellings
+        .into_iter()
+        .filter(|(normalized, _)| !normalized.is_empty())
+        .map(|(normalized, counts)| {
+            // Most common spelling first, ties broken alphabetically so the order is always the same
+            let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+            counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            let alternatives: Vec<String> = counts.into_iter().map(|(spelling, _)| spelling).collect();
+            (normalized, alternatives.join("|"))
+        })
+        .collect()
+}
+
+/// The most common spelling of a tag given by `tag_spellings`
+fn main_spelling(tag: &str) -> &str {
+    tag.split('|').next().unwrap_or(tag)
+}
+
+/// Finds the tag a phrase names, allowing for a plural ("comedies" is "comedy", "thrillers" is "thriller")
+fn exact_tag<'a>(phrase: &str, tags: &'a HashMap<String, String>) -> Option<&'a String> {
+    let mut forms = vec![phrase.to_string()];
+    if let Some(stem) = phrase.strip_suffix("ies") {
+        forms.push(format!("{}y", stem));
+    }
+    if let Some(stem) = phrase.strip_suffix('s') {
+        forms.push(stem.to_string());
+    }
+    forms.iter().find_map(|form| tags.get(form))
+}
+
+/// Receives a description and picks out the phrases that name tags in the model, directly, through a synonym
+/// or (optionally) through embedding similarity. Longer phrases are matched first and every word is used at most once.
+///
+/// @param `data` - the model
+/// @param `query` - the description
+/// @param `options` - the extra synonyms, whether to match by embedding and how the tags are used
+///
+/// @return `Ok()` with the interpretation of the query [OR] `Err()` if the embeddings couldn't be created
+pub(crate) fn analyze_query(data: &HashMap<Data, Option<Tensor>>, query: &str, options: &AnalyzerOptions) -> Result<QueryInterpretation> {
+    let tags = tag_spellings(data);
+    let synonyms: HashMap<String, String> = BUILT_IN_SYNONYMS
+        .iter()
+        .map(|(phrase, tag)| (phrase.to_string(), tag.to_string()))
+        .chain(options.synonyms.iter().map(|(phrase, tag)| (normalize_name(phrase), normalize_name(tag))))
+        .filter(|(_, tag)| tags.contains_key(tag))
+        .collect();
+
+    let normalized = normalize_name(query);
+    let words: Vec<&str> = normalized.split(' ').filter(|word| !word.is_empty()).collect();
+    let mut used = vec![false; words.len()];
+    // (position of the first word, match)
+    let mut matches: Vec<(usize, TagMatch)> = Vec::new();
+
+    for length in (1..=MAX_PHRASE_WORDS.min(words.len())).rev() {
+        for start in 0..=(words.len() - length) {
+            if used[start..start + length].iter().any(|used| *used) {
+                continue;
+            }
+            let phrase = words[start..start + length].join(" ");
+            let found = match exact_tag(&phrase, &tags) {
+                Some(tag) => Some((tag.clone(), TagMatchKind::Exact)),
+                None => synonyms.get(&phrase).map(|tag| (tags[tag].clone(), TagMatchKind::Synonym)),
+            };
+            if let Some((tag, kind)) = found {
+                // A tag named twice ("funny comedies") is only matched once but both phrases are taken out
+                used[start..start + length].iter_mut().for_each(|used| *used = true);
+                if !matches.iter().any(|(_, other)| other.tag == tag) {
+                    matches.push((start, TagMatch { phrase, tag, kind, similarity: 1.0 }));
+                }
+            }
+        }
+    }
+
+    if options.use_embeddings && !tags.is_empty() {
+        // Words that are left over are compared to every tag name in one batch
+        let candidates: Vec<usize> = (0..words.len())
+            .filter(|i| !used[*i] && words[*i].len() > 2 && !STOP_WORDS.contains(&words[*i]))
+            .collect();
+        if !candidates.is_empty() {
+            let tag_names: Vec<&String> = tags.values().collect();
+            let mut inputs: Vec<&str> = tag_names.iter().map(|tag| main_spelling(tag)).collect();
+            inputs.extend(candidates.iter().map(|i| words[*i]));
+            let embeddings = create_input_embeddings(&inputs)?
+                .iter()
+                .map(|embedding| Ok(embedding.to_vec1::<f32>()?))
+                .collect::<Result<Vec<Vec<f32>>>>()?;
+            let (tag_embeddings, word_embeddings) = embeddings.split_at(tag_names.len());
+
+            for (i, word_embedding) in candidates.iter().zip(word_embeddings.iter()) {
+                let best = tag_names
+                    .iter()
+                    .zip(tag_embeddings.iter())
+                    .map(|(tag, tag_embedding)| (*tag, cosine_similarity(word_embedding, tag_embedding)))
+                    .filter(|(tag, _)| !matches.iter().any(|(_, other)| &other.tag == *tag))
+                    .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(a.0)));
+                if let Some((tag, similarity)) = best {
+                    if similarity >= options.embedding_threshold {
+                        used[*i] = true;
+                        matches.push((*i, TagMatch { phrase: words[*i].to_string(), tag: tag.clone(), kind: TagMatchKind::Embedding, similarity }));
+                    }
+                }
+            }
+        }
+    }
+    matches.sort_by_key(|(start, _)| *start);
+
+    let remaining: Vec<&str> = words.iter().zip(used.iter()).filter(|(_, used)| !**used).map(|(word, _)| *word).collect();
+    // If every word was a tag there is nothing left to search for, so the whole description is embedded
+    let remaining_text = if remaining.is_empty() { query.trim().to_string() } else { remaining.join(" ") };
+
+    Ok(QueryInterpretation {
+        query: query.to_string(),
+        tags: matches.into_iter().map(|(_, tag_match)| tag_match).collect(),
+        remaining_text,
+        tag_use: options.tag_use,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::recommendation::through_tag_filter;
+
+    fn catalog(tags: &[&str]) -> HashMap<Data, Option<Tensor>> {
+        tags.iter()
+            .enumerate()
+            .map(|(i, tag)| {
+                let data = Data { id: i as i32, name: format!("Item {}", i), summary: String::new(), tags: vec![tag.to_string()] };
+                (data, None)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_spelling_of_a_tag_gets_through_the_filter() {
+        let data = catalog(&["Sci-Fi", "sci fi", "Sci-Fi", "Sci Fi", "Drama"]);
+        let tags = tag_spellings(&data);
+        assert_eq!(tags["sci fi"], "Sci-Fi|Sci Fi|sci fi");
+        assert_eq!(tags["drama"], "Drama");
+
+        let interpretation = analyze_query(&data, "a sci-fi story", &AnalyzerOptions::default()).unwrap();
+        assert_eq!(interpretation.tags.len(), 1);
+        let tag = &interpretation.tags[0].tag;
+        let matched: Vec<i32> = data.keys().filter(|key| through_tag_filter(&key.tags, tag)).map(|key| key.id).collect();
+        assert_eq!(matched.len(), 4);
+        assert!(!matched.contains(&4));
+    }
+}
@@ -1,94 +1,100 @@
 use super::types::Data;
-use super::types::Recommendations;
-use crate::helpers::{types::Args, utils::normalize_l2};
-use anyhow::{Error as E, Result};
+use super::pagination::{paginate, rank_order};
+use super::constraints::apply_tag_constraints;
+use super::explanation::explain_recommendations;
+use super::reranking::{apply_tag_boosts, mmr};
+use super::tags::expand_tags_input;
+use super::types::{BlendedRecommendation, Diversity, Page, PageRequest, QueryOptions, RankedRecommendations, Recommendations, SeedCombination};
+use crate::helpers::{encoder::Encoder, types::Args, utils::{cosine_similarity, embed_batch}};
+use anyhow::Result;
 use candle::Tensor;
 use clap::Parser;
 use std::collections::HashMap;
+use std::sync::OnceLock;
+use tokenizers::Tokenizer;
 
-/// Receives the input of what the user wants suggested as a String.
-/// The function will return the embedding of the String in question.
-///
-/// @param `description_input` - a String containing what the user wants suggested
+/// The model and tokenizer queries are embedded with, and the arguments they were loaded from
+pub(crate) struct QueryEncoder {
+    pub(crate) args: Args,
+    pub(crate) model: Encoder,
+    pub(crate) tokenizer: Tokenizer,
+}
+
+/// Loaded the first time a query is embedded and reused by every query after it
+static QUERY_ENCODER: OnceLock<QueryEncoder> = OnceLock::new();
+
+/// Returns the model queries are embedded with, loading it if no query has been embedded yet
 ///
-/// @return `Ok()` with a Tensor (embedding) generated from the string [OR] `Err()`
-pub(crate) fn create_input_embedding(description_input: &String) -> Result<Option<Tensor>> {
+/// @return `Ok()` with the model, tokenizer and arguments [OR] `Err()` if the model couldn't be loaded
+pub(crate) fn query_encoder() -> Result<&'static QueryEncoder> {
+    if let Some(encoder) = QUERY_ENCODER.get() {
+        return Ok(encoder);
+    }
     let args = Args::parse();
 
     let (model, mut tokenizer) = args.build_model_and_tokenizer()?;
-    let device = &model.device;
 
     if let Some(pp) = tokenizer.get_padding_mut() {
         pp.strategy = tokenizers::PaddingStrategy::BatchLongest
     }
+    Ok(QUERY_ENCODER.get_or_init(|| QueryEncoder { args, model, tokenizer }))
+}
 
-    // Tokenize the data
-    let mut summaries: Vec<&str> = Vec::new();
-    summaries.push(&description_input);
-    let tokens = tokenizer
-        .encode_batch(summaries.to_vec(), true)
-        .map_err(E::msg)?;
+/// Receives the input of what the user wants suggested as a String.
+/// The function will return the embedding of the String in question.
+///
+/// @param `description_input` - a String containing what the user wants suggested
+///
+/// @return `Ok()` with a Tensor (embedding) generated from the string [OR] `Err()`
+pub(crate) fn create_input_embedding(description_input: &str) -> Result<Option<Tensor>> {
+    let encoder = query_encoder()?;
 
-    // Convert the tokens to tensors
-    let token_ids = tokens
-        .iter()
-        .map(|tokens| {
-            let tokens = tokens.get_ids().to_vec();
-            Ok(Tensor::new(tokens.as_slice(), device)?)
-        })
-        .collect::<Result<Vec<_>>>()?;
+    // Get the embedding, embedded the same way as every other query
+    let query = encoder.args.query_text(description_input);
+    let summaries: Vec<&str> = vec![query.as_str()];
+    let embeddings = embed_batch(&encoder.model, &encoder.tokenizer, summaries)?;
 
-    let token_ids = Tensor::stack(&token_ids, 0)?;
-    let token_type_ids = token_ids.zeros_like()?;
-    // println!("running inference on batch {:?}", token_ids.shape());
+    Ok(Some(embeddings.get(0)?))
+}
 
-    // Get the embeddings
-    let embeddings = model.forward(&token_ids, &token_type_ids)?;
-    // println!("generated embeddings {:?}", embeddings.shape());
+/// Receives several queries and returns their embeddings, loading the model only once.
+///
+/// @param `inputs` - the texts to embed
+///
+/// @return `Ok()` with one embedding per input, in the same order [OR] `Err()`
+pub(crate) fn create_input_embeddings(inputs: &[&str]) -> Result<Vec<Tensor>> {
+    embed_texts(inputs, false)
+}
 
-    // Pool the embeddings
-    let (_n_sentence, n_tokens, _hidden_size) = embeddings.dims3()?;
-    let embeddings = (embeddings.sum(1)? / (n_tokens as f64))?;
-    let embeddings = normalize_l2(&embeddings)?;
-    // println!("pooled embeddings {:?}", embeddings.shape());
+/// The same as `create_input_embeddings` but the texts are embedded as summaries, with the document template
+pub(crate) fn create_document_embeddings(inputs: &[&str]) -> Result<Vec<Tensor>> {
+    embed_texts(inputs, true)
+}
+
+fn embed_texts(inputs: &[&str], documents: bool) -> Result<Vec<Tensor>> {
+    if inputs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let encoder = query_encoder()?;
 
-    Ok(Some(embeddings.get(0).unwrap()))
+    let texts: Vec<String> = inputs
+        .iter()
+        .map(|input| match documents {
+            true => encoder.args.document_text(input),
+            false => encoder.args.query_text(input),
+        })
+        .collect();
+    let embeddings = embed_batch(&encoder.model, &encoder.tokenizer, texts.iter().map(|x| x.as_str()).collect())?;
+    (0..inputs.len()).map(|i| Ok(embeddings.get(i)?)).collect()
 }
 
 pub(crate) fn get_recommendations(
     data: &HashMap<Data, Option<Tensor>>,
-    raw_input: Option<&String>,
+    exclude: &[Data],
     input_embedding: &Tensor,
-    tags_input: &String,
+    tags_input: &str,
     num_recommendations: usize,
 ) -> Result<Vec<(String, f32)>, ()> {
-    // Closure to filter out recommendations based on tags
-    let tags_to_match = tags_input
-        .split(',')
-        .collect::<Vec<&str>>()
-        .iter()
-        .map(|x| x.trim().to_lowercase())
-        .collect::<Vec<String>>();
-    let through_filter = |tags: &Vec<String>| -> bool {
-        if tags_input == &String::from("NONE") {
-            true
-        } else {
-            let mut through_filter = true;
-            for tag_to_match in tags_to_match.iter() {
-                if !tags
-                    .iter()
-                    .map(|x| x.to_lowercase())
-                    .collect::<Vec<String>>()
-                    .contains(&String::from(tag_to_match))
-                {
-                    through_filter = false;
-                    break;
-                }
-            }
-            through_filter
-        }
-    };
-
     // Precompute the dot product of the input with itself
     let a_dot_a_wrapped = input_embedding * input_embedding;
     if a_dot_a_wrapped.is_err() {
@@ -101,40 +107,481 @@ pub(crate) fn get_recommendations(
         .to_scalar::<f32>()
         .unwrap();
 
-    // Clean the input if it is an item because we want to make sure not to include the same item in the recommendations
-    let input_cleaned = match raw_input {
-        Some(input) => Some(input.trim().to_lowercase()),
-        None => None,
-    };
-
-    // Compare the input with all the embeddings in the data and store the recommendations
-    let mut recommendations = Recommendations::new(num_recommendations);
-    for (key, value) in data.iter() {
-        // Skip case
-        if (input_cleaned.is_some() && input_cleaned.as_ref() == Some(&key.name.to_lowercase()))
-            || !through_filter(&key.tags)
-        {
-            continue;
-        }
-        let map_embedding_wrapped = value.clone();
-        if map_embedding_wrapped.is_none() {
-            return Err(());
-        }
-        let map_embedding = map_embedding_wrapped.unwrap();
-        let b_dot_b = (&map_embedding * &map_embedding)
+    // Compare the input with all the embeddings in the data
+    let scored = score_items(data, exclude, tags_input, |map_embedding| {
+        let b_dot_b = (map_embedding * map_embedding)
             .unwrap()
             .sum_all()
             .unwrap()
             .to_scalar::<f32>()
             .unwrap();
-        let a_dot_b = (input_embedding * &map_embedding)
+        let a_dot_b = (input_embedding * map_embedding)
             .unwrap()
             .sum_all()
             .unwrap()
             .to_scalar::<f32>()
             .unwrap();
-        let similarity = &a_dot_b / (&a_dot_a * &b_dot_b).sqrt();
-        recommendations.insert_or_skip(key.name.clone(), similarity);
+        Ok(a_dot_b / (a_dot_a * b_dot_b).sqrt())
+    })?;
+
+    // Store the recommendations
+    let mut recommendations = Recommendations::new(num_recommendations);
+    for (key, similarity) in scored {
+        recommendations.insert_or_skip(key.name, similarity);
+    }
+    Ok(recommendations.get_recommendations())
+}
+
+/// The same as `get_recommendations` but the ranking can be changed per query with `options`
+///
+/// @param `data` - the model
+/// @param `exclude` - items that should never be recommended, like the item the query is based on
+/// @param `input_embedding` - the embedding of the query
+/// @param `tags_input` - the tags to filter by, each tag separated by a comma, or NONE
+/// @param `num_recommendations` - the number of recommendations
+/// @param `options` - how the recommendations are ranked
+/// @param `query_text` - the description, or the summary of the item the query is based on, used for the explanations.
+/// For item queries that item is expected to be the first item in `exclude`.
+///
+/// @return `Ok()` with the recommendations, the status of the tag constraints and (if asked for) the explanations [OR] `Err()`
+pub(crate) fn get_recommendations_with(
+    data: &HashMap<Data, Option<Tensor>>,
+    exclude: &[Data],
+    input_embedding: &Tensor,
+    tags_input: &str,
+    num_recommendations: usize,
+    options: &QueryOptions,
+    query_text: &str,
+) -> Result<RankedRecommendations, ()> {
+    let (tags_input, tag_boosts) = expand_with_hierarchy(tags_input, options);
+    let tags_input = tags_input.as_str();
+
+    let input_vector = input_embedding.to_vec1::<f32>().map_err(|_| ())?;
+    let mut scored = score_items(data, exclude, tags_input, |map_embedding| {
+        Ok(cosine_similarity(&input_vector, &map_embedding.to_vec1::<f32>().map_err(|_| ())?))
+    })?;
+    apply_tag_boosts(&mut scored, &tag_boosts, options.boost_mode);
+    if let Some(min_score) = options.min_score {
+        scored.retain(|(_, score)| *score >= min_score);
+    }
+    scored.sort_by(rank_order);
+
+    let ranked = diversify(data, scored, num_recommendations, options.diversity)?;
+
+    // Then pick the recommendations while respecting the tag constraints
+    let (mut ranked, constraints) = apply_tag_constraints(ranked, &options.tag_constraints, num_recommendations);
+
+    ranked.truncate(num_recommendations);
+    let explanations = if options.explain {
+        let recommended: Vec<Data> = ranked.iter().map(|(key, _)| key.clone()).collect();
+        explain_recommendations(data, &recommended, query_text, &input_vector, exclude.first(), tags_input, &tag_boosts).map_err(|_| ())?
+    } else {
+        Vec::new()
+    };
+
+    // Only real items are returned, so the floor holds and every item has its explanation
+    Ok(RankedRecommendations {
+        items: ranked.into_iter().map(|(key, score)| (key.name, score)).collect(),
+        constraints,
+        explanations,
+    })
+}
+
+/// With a hierarchy, a parent tag is given its children as alternatives in the filter and the boosts
+///
+/// @param `tags_input` - the tags to filter by, each tag separated by a comma, or NONE
+/// @param `options` - the boosts and the hierarchy
+///
+/// @return the tags to filter by and the boosts, with the children of every parent tag added
+pub(crate) fn expand_with_hierarchy(tags_input: &str, options: &QueryOptions) -> (String, Vec<(String, f32)>) {
+    match &options.tag_hierarchy {
+        Some(hierarchy) => (
+            expand_tags_input(hierarchy, tags_input),
+            options.tag_boosts.iter().map(|(tag, boost)| (expand_tags_input(hierarchy, tag), *boost)).collect(),
+        ),
+        None => (tags_input.to_string(), options.tag_boosts.clone()),
+    }
+}
+
+/// Re-ranks the top of a ranked list for diversity, the rest stays in order behind it
+///
+/// @param `data` - the model
+/// @param `scored` - the items ranked by score
+/// @param `num_recommendations` - the number of recommendations, the pool is never smaller than this
+/// @param `diversity` - whether and how to re-rank
+///
+/// @return `Ok()` with the re-ranked items [OR] `Err()` if an item has no embedding
+pub(crate) fn diversify(data: &HashMap<Data, Option<Tensor>>, mut scored: Vec<(Data, f32)>, num_recommendations: usize, diversity: Diversity) -> Result<Vec<(Data, f32)>, ()> {
+    match diversity {
+        Diversity::Off => Ok(scored),
+        Diversity::Mmr { lambda, pool_size } => {
+            let rest = scored.split_off(pool_size.max(num_recommendations).min(scored.len()));
+            let pool_len = scored.len();
+            let mut reranked = mmr(data, scored, lambda, pool_len)?;
+            reranked.extend(rest);
+            Ok(reranked)
+        },
+    }
+}
+
+/// The same as `get_recommendations` but returns one page of the ranked items instead of the top ones,
+/// leaving out items below the score floor.
+///
+/// @param `data` - the model
+/// @param `exclude` - items that should never be recommended, like the item the query is based on
+/// @param `input_embedding` - the embedding of the query
+/// @param `tags_input` - the tags to filter by, each tag separated by a comma, or NONE
+/// @param `request` - the score floor and which page to return
+///
+/// @return `Ok()` with the page [OR] `Err()` if an item couldn't be scored or the cursor is invalid
+pub(crate) fn get_recommendations_page(
+    data: &HashMap<Data, Option<Tensor>>,
+    exclude: &[Data],
+    input_embedding: &Tensor,
+    tags_input: &str,
+    request: &PageRequest,
+) -> Result<Page, ()> {
+    let input_vector = input_embedding.to_vec1::<f32>().map_err(|_| ())?;
+    let scored = score_items(data, exclude, tags_input, |map_embedding| {
+        Ok(cosine_similarity(&input_vector, &map_embedding.to_vec1::<f32>().map_err(|_| ())?))
+    })?;
+    paginate(scored, request).map_err(|_| ())
+}
+
+/// Receives several seed items with their embeddings and weights and finds recommendations based on all of them.
+/// Every seed is left out of the recommendations, and there are fewer than `num_recommendations` if fewer items are left.
+///
+/// @param `data` - the model
+/// @param `seeds` - the seed items, their embeddings and their weights (which must be greater than 0)
+/// @param `combination` - how the seeds are combined
+/// @param `tags_input` - the tags to filter by, each tag separated by a comma, or NONE
+/// @param `num_recommendations` - the number of recommendations
+///
+/// @return `Ok()` with (Item name, score) tuples [OR] `Err()`
+pub(crate) fn get_recommendations_multi(
+    data: &HashMap<Data, Option<Tensor>>,
+    seeds: &[(Data, Tensor, f32)],
+    combination: SeedCombination,
+    tags_input: &str,
+    num_recommendations: usize,
+) -> Result<Vec<(String, f32)>, ()> {
+    if seeds.is_empty() || seeds.iter().any(|(_, _, weight)| *weight <= 0.0) {
+        return Err(());
+    }
+    let exclude: Vec<Data> = seeds.iter().map(|(seed, _, _)| seed.clone()).collect();
+
+    match combination {
+        SeedCombination::Centroid => {
+            let mut centroid = (&seeds[0].1 * seeds[0].2 as f64).map_err(|_| ())?;
+            for (_, embedding, weight) in &seeds[1..] {
+                centroid = (centroid + (embedding * *weight as f64).map_err(|_| ())?).map_err(|_| ())?;
+            }
+            let centroid = centroid.to_vec1::<f32>().map_err(|_| ())?;
+            let scored = score_items(data, &exclude, tags_input, |map_embedding| {
+                Ok(cosine_similarity(&centroid, &map_embedding.to_vec1::<f32>().map_err(|_| ())?))
+            })?;
+            Ok(top_ranked(scored, num_recommendations))
+        },
+        SeedCombination::MaxSimilarity => {
+            // Weights are scaled so that the most important seed keeps its similarity as is
+            let max_weight = seeds.iter().map(|(_, _, weight)| *weight).fold(f32::MIN, f32::max);
+            let seed_vectors: Vec<(Vec<f32>, f32)> = seeds
+                .iter()
+                .map(|(_, embedding, weight)| Ok((embedding.to_vec1::<f32>().map_err(|_| ())?, weight / max_weight)))
+                .collect::<Result<_, ()>>()?;
+            let scored = score_items(data, &exclude, tags_input, |map_embedding| {
+                let map_vector = map_embedding.to_vec1::<f32>().map_err(|_| ())?;
+                Ok(seed_vectors
+                    .iter()
+                    .map(|(seed_vector, weight)| weight * cosine_similarity(seed_vector, &map_vector))
+                    .fold(f32::MIN, f32::max))
+            })?;
+            Ok(top_ranked(scored, num_recommendations))
+        },
+        SeedCombination::RoundRobin => {
+            // Each seed's k-th recommendation gets the turn (k + 1) / weight and the turns are merged in order,
+            // so a seed with twice the weight adds two items for every one the other seed adds
+            let mut turns: Vec<(f32, usize, Data, f32)> = Vec::new();
+            for (seed_index, (_, embedding, weight)) in seeds.iter().enumerate() {
+                let seed_vector = embedding.to_vec1::<f32>().map_err(|_| ())?;
+                let mut scored = score_items(data, &exclude, tags_input, |map_embedding| {
+                    Ok(cosine_similarity(&seed_vector, &map_embedding.to_vec1::<f32>().map_err(|_| ())?))
+                })?;
+                scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.id.cmp(&b.0.id)));
+                for (rank, (key, similarity)) in scored.into_iter().take(num_recommendations).enumerate() {
+                    turns.push(((rank + 1) as f32 / weight, seed_index, key, similarity));
+                }
+            }
+            turns.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+            let mut ranked: Vec<(String, f32)> = Vec::new();
+            let mut added: Vec<Data> = Vec::new();
+            for (_, _, key, similarity) in turns {
+                if added.contains(&key) {
+                    continue;
+                }
+                ranked.push((key.name.clone(), similarity));
+                added.push(key);
+            }
+            ranked.truncate(num_recommendations);
+            Ok(ranked)
+        },
+    }
+}
+
+/// The best `num_recommendations` of the scored items as (Item name, score) tuples, in `rank_order`
+fn top_ranked(mut scored: Vec<(Data, f32)>, num_recommendations: usize) -> Vec<(String, f32)> {
+    scored.sort_by(rank_order);
+    scored.truncate(num_recommendations);
+    scored.into_iter().map(|(key, score)| (key.name, score)).collect()
+}
+
+/// Receives a seed item and a description and finds recommendations that are like the item but
+/// steered by the description. The score of each item is `(1 - text_weight) * similarity to the item
+/// + text_weight * similarity to the description`, so each result can be traced back to either term.
+///
+/// @param `data` - the model
+/// @param `seed` - the seed item, which is left out of the recommendations
+/// @param `item_embedding` - the embedding of the seed item
+/// @param `text_embedding` - the embedding of the description
+/// @param `text_weight` - how much the description counts, between 0 and 1
+/// @param `tags_input` - the tags to filter by, each tag separated by a comma, or NONE
+/// @param `num_recommendations` - the number of recommendations
+///
+/// @return `Ok()` with the recommendations, best first [OR] `Err()`
+pub(crate) fn get_blended_recommendations(
+    data: &HashMap<Data, Option<Tensor>>,
+    seed: &Data,
+    item_embedding: &Tensor,
+    text_embedding: &Tensor,
+    text_weight: f32,
+    tags_input: &str,
+    num_recommendations: usize,
+) -> Result<Vec<BlendedRecommendation>, ()> {
+    if !(0.0..=1.0).contains(&text_weight) {
+        return Err(());
+    }
+    let item_vector = item_embedding.to_vec1::<f32>().map_err(|_| ())?;
+    let text_vector = text_embedding.to_vec1::<f32>().map_err(|_| ())?;
+
+    let mut scored = score_items(data, std::slice::from_ref(seed), tags_input, |map_embedding| {
+        let map_vector = map_embedding.to_vec1::<f32>().map_err(|_| ())?;
+        Ok((
+            (1.0 - text_weight) * cosine_similarity(&item_vector, &map_vector),
+            text_weight * cosine_similarity(&text_vector, &map_vector),
+        ))
+    })?;
+    scored.sort_by(|a, b| (b.1 .0 + b.1 .1).total_cmp(&(a.1 .0 + a.1 .1)).then(a.0.id.cmp(&b.0.id)));
+
+    Ok(scored
+        .into_iter()
+        .take(num_recommendations)
+        .map(|(key, (item_score, text_score))| BlendedRecommendation {
+            name: key.name,
+            score: item_score + text_score,
+            item_score,
+            text_score,
+        })
+        .collect())
+}
+
+/// Whether an item's tags contain every tag in `tags_input` (ignoring case). `tags_input` is a comma separated
+/// list of tags, or NONE to let every item through. A tag can be given alternatives separated by a |
+/// ("action|superhero") and is then matched by any of them.
+pub(crate) fn through_tag_filter(tags: &[String], tags_input: &str) -> bool {
+    if tags_input == "NONE" {
+        return true;
+    }
+    let tags: Vec<String> = tags.iter().map(|x| x.trim().to_lowercase()).collect();
+    tags_input
+        .split(',')
+        .all(|alternatives| alternatives.split('|').map(|x| x.trim().to_lowercase()).any(|tag_to_match| tags.contains(&tag_to_match)))
+}
+
+/// Receives the model and a function that scores an embedding, and scores every item that isn't
+/// excluded and makes it through the tag filter.
+///
+/// @param `data` - the model
+/// @param `exclude` - items that should never be recommended, like the items the query is based on
+/// @param `tags_input` - the tags to filter by, each tag separated by a comma, or NONE
+/// @param `score` - the function that gives an item's embedding a score, higher is better (it can also return
+/// the parts the score is made of)
+///
+/// @return `Ok()` with every remaining item and its score, in no particular order [OR] `Err()` if an item
+/// has no embedding or couldn't be scored
+pub(crate) fn score_items<F, T>(
+    data: &HashMap<Data, Option<Tensor>>,
+    exclude: &[Data],
+    tags_input: &str,
+    mut score: F,
+) -> Result<Vec<(Data, T)>, ()>
+where
+    F: FnMut(&Tensor) -> Result<T, ()>,
+{
+    let mut scored: Vec<(Data, T)> = Vec::new();
+    for (key, value) in data.iter() {
+        // Skip case, if the input is based on items we don't want to include those same items in the recommendations
+        if exclude.contains(key) || !through_tag_filter(&key.tags, tags_input) {
+            continue;
+        }
+        let map_embedding = match value {
+            Some(map_embedding) => map_embedding,
+            None => return Err(()),
+        };
+        scored.push((key.clone(), score(map_embedding)?));
+    }
+    Ok(scored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::diagnostics::why_not;
+    use crate::helpers::types::{RemovalReason, TagHierarchy};
+    use candle::Device;
+
+    fn item(id: i32, tag: &str, embedding: &[f32]) -> (Data, Option<Tensor>) {
+        let data = Data { id, name: format!("Item {}", id), summary: String::new(), tags: vec![tag.to_string()] };
+        (data, Some(Tensor::new(embedding, &Device::Cpu).unwrap()))
+    }
+
+    /// Checks the names exactly and the scores up to float rounding
+    fn assert_items(items: &[(String, f32)], expected: &[(&str, f32)]) {
+        assert_eq!(items.len(), expected.len(), "{:?}", items);
+        for ((name, score), (expected_name, expected_score)) in items.iter().zip(expected) {
+            assert_eq!(name, expected_name);
+            assert!((score - expected_score).abs() < 1e-5, "{} scored {} instead of {}", name, score, expected_score);
+        }
+    }
+
+    #[test]
+    fn the_tag_hierarchy_expands_filters_and_boosts() {
+        let data: HashMap<Data, Option<Tensor>> =
+            [item(1, "Action", &[1.0, 0.0]), item(2, "Superhero", &[0.6, 0.8]), item(3, "Drama", &[0.8, 0.6])].into_iter().collect();
+        let query = Tensor::new(&[1.0f32, 0.0], &Device::Cpu).unwrap();
+        let hierarchy = TagHierarchy { children: [("Action".to_string(), vec!["Superhero".to_string()])].into_iter().collect() };
+        let items = |options: &QueryOptions, tags_input: &str| get_recommendations_with(&data, &[], &query, tags_input, 3, options, "").unwrap().items;
+
+        assert_items(&items(&QueryOptions::default(), "Action"), &[("Item 1", 1.0)]);
+        let with_hierarchy = QueryOptions { tag_hierarchy: Some(hierarchy), ..QueryOptions::default() };
+        assert_items(&items(&with_hierarchy, "Action"), &[("Item 1", 1.0), ("Item 2", 0.6)]);
+
+        // Boosting the parent tag also lifts the child above the closer Drama item
+        let boosted = QueryOptions { tag_boosts: vec![("action".to_string(), 0.5)], ..with_hierarchy };
+        assert_items(&items(&boosted, "NONE"), &[("Item 1", 1.5), ("Item 2", 1.1), ("Item 3", 0.8)]);
+    }
+
+    #[test]
+    fn the_score_floor_applies_to_boosted_scores_and_why_not_agrees() {
+        let data: HashMap<Data, Option<Tensor>> =
+            [item(1, "Action", &[1.0, 0.0]), item(2, "Superhero", &[0.6, 0.8]), item(3, "Drama", &[0.8, 0.6])].into_iter().collect();
+        let query = Tensor::new(&[1.0f32, 0.0], &Device::Cpu).unwrap();
+        let options = QueryOptions { tag_boosts: vec![("superhero".to_string(), 0.3)], min_score: Some(0.85), ..QueryOptions::default() };
+
+        // Item 3 is below the floor and nothing takes its place
+        let ranked = get_recommendations_with(&data, &[], &query, "NONE", 3, &options, "").unwrap();
+        assert_items(&ranked.items, &[("Item 1", 1.0), ("Item 2", 0.9)]);
+
+        let target = |id: i32| data.keys().find(|key| key.id == id).unwrap().clone();
+        let report = why_not(&data, &[], &query, &target(2), "NONE", 3, &options).unwrap();
+        assert_eq!(report.final_rank, Some(2));
+        let report = why_not(&data, &[], &query, &target(3), "NONE", 3, &options).unwrap();
+        assert_eq!(report.removed_by, Some(RemovalReason::ScoreFloor { min_score: 0.85 }));
+    }
+
+    #[test]
+    fn asking_for_no_recommendations_returns_none() {
+        let data: HashMap<Data, Option<Tensor>> = [item(1, "Action", &[1.0, 0.0]), item(2, "Drama", &[0.8, 0.6])].into_iter().collect();
+        let query = Tensor::new(&[1.0f32, 0.0], &Device::Cpu).unwrap();
+
+        assert_eq!(get_recommendations(&data, &[], &query, "NONE", 0), Ok(Vec::new()));
+        let ranked = get_recommendations_with(&data, &[], &query, "NONE", 0, &QueryOptions::default(), "").unwrap();
+        assert!(ranked.items.is_empty());
+    }
+
+    /// Two seeds at right angles, an item near each one, one between them and one opposite the first
+    #[allow(clippy::type_complexity)]
+    fn seeded_catalog() -> (HashMap<Data, Option<Tensor>>, Vec<(Data, Tensor)>) {
+        let data: HashMap<Data, Option<Tensor>> = [
+            item(1, "Seed", &[1.0, 0.0]),
+            item(2, "Seed", &[0.0, 1.0]),
+            item(3, "Drama", &[0.96, 0.28]),
+            item(4, "Drama", &[0.28, 0.96]),
+            item(5, "Drama", &[0.6, 0.8]),
+            item(6, "Drama", &[-1.0, 0.0]),
+        ]
+        .into_iter()
+        .collect();
+        let mut seeds: Vec<(Data, Tensor)> =
+            data.iter().filter(|(key, _)| key.id <= 2).map(|(key, value)| (key.clone(), value.clone().unwrap())).collect();
+        seeds.sort_by_key(|(key, _)| key.id);
+        (data, seeds)
+    }
+
+    fn multi(combination: SeedCombination, weights: [f32; 2], num_recommendations: usize) -> Vec<(String, f32)> {
+        let (data, seeds) = seeded_catalog();
+        let seeds: Vec<(Data, Tensor, f32)> = seeds.into_iter().zip(weights).map(|((key, embedding), weight)| (key, embedding, weight)).collect();
+        get_recommendations_multi(&data, &seeds, combination, "NONE", num_recommendations).unwrap()
+    }
+
+    #[test]
+    fn centroid_seeds_are_averaged_with_their_weights() {
+        // The centroid is (1, 1), so Item 5 is closest and Items 3 and 4 tie
+        let middle = 1.4 / 2f32.sqrt();
+        let near = 1.24 / 2f32.sqrt();
+        assert_items(&multi(SeedCombination::Centroid, [1.0, 1.0], 3), &[("Item 5", middle), ("Item 3", near), ("Item 4", near)]);
+        // The centroid is (3, 1), which pulls the first seed's neighbour ahead
+        let norm = 10f32.sqrt();
+        assert_items(&multi(SeedCombination::Centroid, [3.0, 1.0], 3), &[("Item 3", 3.16 / norm), ("Item 5", 2.6 / norm), ("Item 4", 1.8 / norm)]);
+    }
+
+    #[test]
+    fn max_similarity_keeps_the_best_weighted_seed() {
+        assert_items(&multi(SeedCombination::MaxSimilarity, [1.0, 1.0], 4), &[("Item 3", 0.96), ("Item 4", 0.96), ("Item 5", 0.8), ("Item 6", 0.0)]);
+        // The second seed counts half, so its neighbour scores 0.48
+        assert_items(&multi(SeedCombination::MaxSimilarity, [2.0, 1.0], 3), &[("Item 3", 0.96), ("Item 5", 0.6), ("Item 4", 0.48)]);
+    }
+
+    #[test]
+    fn round_robin_takes_turns_by_weight() {
+        // Each seed's best item, then Item 5 which both seeds rank second, then the first seed's last item
+        assert_items(&multi(SeedCombination::RoundRobin, [1.0, 1.0], 4), &[("Item 3", 0.96), ("Item 4", 0.96), ("Item 5", 0.6), ("Item 6", -1.0)]);
+        // With twice the weight the first seed adds its second item before the second seed adds its first
+        assert_items(&multi(SeedCombination::RoundRobin, [2.0, 1.0], 3), &[("Item 3", 0.96), ("Item 5", 0.6), ("Item 4", 0.96)]);
+    }
+
+    #[test]
+    fn multi_seed_queries_need_seeds_with_positive_weights() {
+        let (data, seeds) = seeded_catalog();
+        assert_eq!(get_recommendations_multi(&data, &[], SeedCombination::Centroid, "NONE", 3), Err(()));
+        let zero: Vec<(Data, Tensor, f32)> = seeds.into_iter().map(|(key, embedding)| (key, embedding, 0.0)).collect();
+        assert_eq!(get_recommendations_multi(&data, &zero, SeedCombination::RoundRobin, "NONE", 3), Err(()));
+    }
+
+    #[test]
+    fn blended_scores_add_up_from_the_weighted_terms() {
+        let data: HashMap<Data, Option<Tensor>> =
+            [item(1, "Seed", &[1.0, 0.0]), item(2, "Drama", &[0.6, 0.8]), item(3, "Drama", &[0.8, 0.6]), item(4, "Drama", &[0.0, 1.0])].into_iter().collect();
+        let seed = data.keys().find(|key| key.id == 1).unwrap().clone();
+        let item_embedding = Tensor::new(&[1.0f32, 0.0], &Device::Cpu).unwrap();
+        let text_embedding = Tensor::new(&[0.0f32, 1.0], &Device::Cpu).unwrap();
+        let blend = |text_weight: f32| get_blended_recommendations(&data, &seed, &item_embedding, &text_embedding, text_weight, "NONE", 3);
+
+        // (name, similarity to the seed, similarity to the description)
+        let expected = [("Item 3", 0.8, 0.6), ("Item 2", 0.6, 0.8), ("Item 4", 0.0, 1.0)];
+        let blended = blend(0.25).unwrap();
+        assert_eq!(blended.len(), expected.len());
+        for (recommendation, (name, item_similarity, text_similarity)) in blended.iter().zip(expected) {
+            assert_eq!(recommendation.name, name);
+            assert!((recommendation.item_score - 0.75 * item_similarity).abs() < 1e-6, "{:?}", recommendation);
+            assert!((recommendation.text_score - 0.25 * text_similarity).abs() < 1e-6, "{:?}", recommendation);
+            assert!((recommendation.score - (recommendation.item_score + recommendation.text_score)).abs() < 1e-6, "{:?}", recommendation);
+        }
+
+        // All on the description, the order follows it alone
+        let names: Vec<String> = blend(1.0).unwrap().into_iter().map(|recommendation| recommendation.name).collect();
+        assert_eq!(names, vec!["Item 4", "Item 2", "Item 3"]);
+        assert_eq!(blend(1.5), Err(()));
+        assert_eq!(blend(-0.1), Err(()));
     }
-    return Ok(recommendations.get_recommendations());
 }
@@ -0,0 +1,149 @@
+use super::types::{BoostMode, Data};
+use super::utils::cosine_similarity;
+use candle::Tensor;
+use std::collections::HashMap;
+
+/// Re-ranks candidates with Maximal Marginal Relevance. Each next item is the candidate with the highest
+/// `lambda * relevance - (1 - lambda) * max similarity to the items already picked`, using the stored item embeddings
+/// to measure how redundant two items are.
+///
+/// @param `data` - the model, used to look up the embeddings of the candidates
+/// @param `candidates` - the candidates with their relevance, already ranked best first
+/// @param `lambda` - how much relevance counts compared to diversity, between 0 and 1
+/// @param `num_recommendations` - how many items to pick
+///
+/// @return `Ok()` with the picked items and their original relevance, in the order they were picked [OR] `Err()` if
+/// a candidate has no embedding
+pub(crate) fn mmr(
+    data: &HashMap<Data, Option<Tensor>>,
+    candidates: Vec<(Data, f32)>,
+    lambda: f32,
+    num_recommendations: usize,
+) -> Result<Vec<(Data, f32)>, ()> {
+    let mut remaining: Vec<(Data, f32, Vec<f32>)> = Vec::with_capacity(candidates.len());
+    for (key, relevance) in candidates {
+        let vector = match data.get(&key) {
+            Some(Some(embedding)) => embedding.to_vec1::<f32>().map_err(|_| ())?,
+            _ => return Err(()),
+        };
+        remaining.push((key, relevance, vector));
+    }
+
+    // The highest similarity of every remaining candidate to any picked item, updated after each pick
+    let mut redundancy: Vec<f32> = vec![f32::MIN; remaining.len()];
+    let mut picked: Vec<(Data, f32)> = Vec::new();
+    while picked.len() < num_recommendations && !remaining.is_empty() {
+        // Candidates are ranked best first, so on ties the more relevant one is kept
+        let mut best = 0;
+        let mut best_score = f32::MIN;
+        for (i, (_, relevance, _)) in remaining.iter().enumerate() {
+            let penalty = if picked.is_empty() { 0.0 } else { redundancy[i] };
+            let score = lambda * relevance - (1.0 - lambda) * penalty;
+            if score > best_score {
+                best = i;
+                best_score = score;
+            }
+        }
+
+        let (key, relevance, vector) = remaining.remove(best);
+        redundancy.remove(best);
+        for (i, (_, _, other)) in remaining.iter().enumerate() {
+            redundancy[i] = redundancy[i].max(cosine_similarity(&vector, other));
+        }
+        picked.push((key, relevance));
+    }
+    Ok(picked)
+}
+
+/// Applies tag boosts to the scores of the items. A tag can be given alternatives separated by a | and each
+/// boost applies at most once per item, even if the item has the tag in more than one casing or spelling.
+///
+/// @param `scored` - the items and their scores
+/// @param `tag_boosts` - (tag, boost) pairs
+/// @param `boost_mode` - whether boosts are added to the score or multiply it by (1 + boost). Multiplying is done on
+/// the score shifted by 1, so that a positive boost raises a negative cosine similarity instead of lowering it
+pub(crate) fn apply_tag_boosts(scored: &mut [(Data, f32)], tag_boosts: &[(String, f32)], boost_mode: BoostMode) {
+    if tag_boosts.is_empty() {
+        return;
+    }
+    let tag_boosts: Vec<(Vec<String>, f32)> = tag_boosts
+        .iter()
+        .map(|(alternatives, boost)| (alternatives.split('|').map(|tag| tag.trim().to_lowercase()).collect(), *boost))
+        .collect();
+    for (key, score) in scored.iter_mut() {
+        let tags: Vec<String> = key.tags.iter().map(|tag| tag.trim().to_lowercase()).collect();
+        for (_, boost) in tag_boosts.iter().filter(|(alternatives, _)| alternatives.iter().any(|tag| tags.contains(tag))) {
+            match boost_mode {
+                BoostMode::Additive => *score += boost,
+                BoostMode::Multiplicative => *score = (*score + 1.0) * (1.0 + boost) - 1.0,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle::Device;
+
+    fn item(id: i32, tags: &[&str], embedding: &[f32]) -> (Data, Option<Tensor>) {
+        let data = Data { id, name: format!("Item {}", id), summary: String::new(), tags: tags.iter().map(|tag| tag.to_string()).collect() };
+        (data, Some(Tensor::new(embedding, &Device::Cpu).unwrap()))
+    }
+
+    fn picked_ids(picked: &[(Data, f32)]) -> Vec<i32> {
+        picked.iter().map(|(key, _)| key.id).collect()
+    }
+
+    /// Items 1 and 2 are near duplicates, Item 3 is different and less relevant
+    #[allow(clippy::type_complexity)]
+    fn candidates() -> (HashMap<Data, Option<Tensor>>, Vec<(Data, f32)>) {
+        let data: HashMap<Data, Option<Tensor>> =
+            [item(1, &[], &[1.0, 0.0]), item(2, &[], &[0.99, 0.14]), item(3, &[], &[0.0, 1.0])].into_iter().collect();
+        let mut candidates: Vec<(Data, f32)> = data.keys().map(|key| (key.clone(), [0.9, 0.89, 0.5][key.id as usize - 1])).collect();
+        candidates.sort_by_key(|(key, _)| key.id);
+        (data, candidates)
+    }
+
+    #[test]
+    fn mmr_with_lambda_one_keeps_the_relevance_order() {
+        let (data, candidates) = candidates();
+        let picked = mmr(&data, candidates, 1.0, 3).unwrap();
+        assert_eq!(picked_ids(&picked), vec![1, 2, 3]);
+        // The original relevance is kept
+        assert_eq!(picked.iter().map(|(_, relevance)| *relevance).collect::<Vec<f32>>(), vec![0.9, 0.89, 0.5]);
+    }
+
+    #[test]
+    fn mmr_with_a_low_lambda_skips_near_duplicates() {
+        for lambda in [0.0, 0.5] {
+            let (data, candidates) = candidates();
+            assert_eq!(picked_ids(&mmr(&data, candidates, lambda, 3).unwrap()), vec![1, 3, 2], "lambda {}", lambda);
+        }
+        let (data, candidates) = candidates();
+        assert_eq!(picked_ids(&mmr(&data, candidates, 0.0, 2).unwrap()), vec![1, 3]);
+    }
+
+    #[test]
+    fn mmr_needs_every_embedding() {
+        let (mut data, candidates) = candidates();
+        data.insert(candidates[2].0.clone(), None);
+        assert_eq!(mmr(&data, candidates, 0.5, 3), Err(()));
+    }
+
+    #[test]
+    fn multiplicative_boosts_move_negative_scores_the_same_way_as_positive_ones() {
+        let boosted = |score: f32, boost: f32| {
+            let mut scored = vec![(item(1, &["Comedy"], &[1.0]).0, score)];
+            apply_tag_boosts(&mut scored, &[("comedy".to_string(), boost)], BoostMode::Multiplicative);
+            scored[0].1
+        };
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-6;
+
+        assert!(close(boosted(0.5, 0.5), 1.25));
+        assert!(close(boosted(0.5, -0.5), -0.25));
+        assert!(close(boosted(-0.5, 0.5), -0.25));
+        assert!(close(boosted(-0.5, -0.5), -0.75));
+        assert!(close(boosted(-0.5, 0.0), -0.5));
+    }
+}
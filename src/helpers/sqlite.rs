@@ -0,0 +1,547 @@
+use super::types::{Data, IndexMetadata, MultiVectorIndex, TokenMatrix};
+use super::utils::cosine_similarity;
+use anyhow::{Error as E, Result};
+use candle::{Device, Tensor};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+
+/// Receives the path of a SQLite database and a query. The query must return the columns
+/// id, name, summary and tags, in that order. The tags column can either be a JSON array
+/// of strings or a comma separated string, and NULL is treated as no tags.
+///
+/// @param `db_path` - a String containing the file path of the SQLite database
+/// @param `query` - the SQL query that selects the items
+///
+/// @return `Ok()` with the data (without embeddings) [OR] `Err()` if the database couldn't be
+/// opened or the query failed
+pub(crate) fn extract_data_sqlite(db_path: &String, query: &str) -> Result<HashMap<Data, Option<Tensor>>> {
+    let connection = Connection::open(db_path)?;
+    let mut statement = connection.prepare(query)?;
+    if statement.column_count() < 4 {
+        return Err(E::msg("Query must return the columns id, name, summary and tags"));
+    }
+
+    let rows = statement.query_map([], |row| {
+        let tags: Option<String> = row.get(3)?;
+        Ok(Data {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            summary: row.get(2)?,
+            tags: parse_tags(tags.as_deref()),
+        })
+    })?;
+
+    let mut nodes: HashMap<Data, Option<Tensor>> = HashMap::new();
+    for row in rows {
+        nodes.insert(row?, None);
+    }
+    Ok(nodes)
+}
+
+/// Turns the tags column into a vector of tags, accepting either a JSON array or a comma separated string
+fn parse_tags(tags: Option<&str>) -> Vec<String> {
+    let tags = match tags {
+        Some(tags) => tags.trim(),
+        None => return Vec::new(),
+    };
+    if tags.starts_with('[') {
+        if let Ok(parsed) = serde_json::from_str::<Vec<String>>(tags) {
+            return parsed;
+        }
+    }
+    tags.split(',')
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// Writes the embedding of every item into the `item_embeddings` table, replacing any rows with the same id.
+/// Embeddings are stored as little endian f32 blobs and how they were made is written into the `index_metadata` table.
+///
+/// @param `data` - the model
+/// @param `db_path` - a String containing the file path of the SQLite database
+/// @param `metadata` - how the embeddings were made
+///
+/// @return `Ok()` with the number of rows written [OR] `Err()`
+pub(crate) fn write_embeddings_sqlite(data: &HashMap<Data, Option<Tensor>>, db_path: &String, metadata: &IndexMetadata) -> Result<usize> {
+    let mut connection = Connection::open(db_path)?;
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS item_embeddings (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            dimensions INTEGER NOT NULL,
+            embedding BLOB NOT NULL
+        )",
+        [],
+    )?;
+
+    let transaction = connection.transaction()?;
+    let mut written = 0;
+    {
+        let mut statement = transaction.prepare(
+            "INSERT OR REPLACE INTO item_embeddings (id, name, dimensions, embedding) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for (key, value) in data.iter() {
+            let embedding = match value {
+                Some(embedding) => embedding.to_vec1::<f32>()?,
+                None => return Err(E::msg(format!("Item {} has no embedding", key.id))),
+            };
+            let bytes: Vec<u8> = embedding.iter().flat_map(|x| x.to_le_bytes()).collect();
+            statement.execute(params![key.id, key.name, embedding.len() as i64, bytes])?;
+            written += 1;
+        }
+    }
+    write_index_metadata(&transaction, metadata)?;
+    transaction.commit()?;
+    Ok(written)
+}
+
+/// Reads the embeddings saved with `write_embeddings_sqlite` back into the items of the model, matching them by id.
+/// The embeddings are only read if they were made the same way as queries are embedded now.
+///
+/// @param `data` - the model, whose embeddings are replaced
+/// @param `db_path` - a String containing the file path of the SQLite database
+/// @param `current` - how queries are embedded now
+///
+/// @return `Ok()` with the number of items that got an embedding [OR] `Err()` if the table couldn't be read, a row is
+/// malformed, an item has no saved embedding or the embeddings were made differently from `current`
+pub(crate) fn read_embeddings_sqlite(data: &mut HashMap<Data, Option<Tensor>>, db_path: &String, current: &IndexMetadata) -> Result<usize> {
+    let connection = Connection::open(db_path)?;
+    check_index_metadata(&connection, current)?;
+    let mut statement = connection.prepare("SELECT id, dimensions, embedding FROM item_embeddings")?;
+    let rows = statement.query_map([], |row| {
+        let id: i32 = row.get(0)?;
+        let dimensions: i64 = row.get(1)?;
+        let embedding: Vec<u8> = row.get(2)?;
+        Ok((id, dimensions as usize, embedding))
+    })?;
+
+    let mut by_id: HashMap<i32, Vec<f32>> = HashMap::new();
+    for row in rows {
+        let (id, dimensions, embedding) = row?;
+        if embedding.len() != dimensions * 4 {
+            return Err(E::msg(format!("The embedding of item {} has the wrong size", id)));
+        }
+        by_id.insert(id, embedding.chunks_exact(4).map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]])).collect());
+    }
+
+    for (key, value) in data.iter_mut() {
+        match by_id.get(&key.id) {
+            Some(embedding) => *value = Some(Tensor::new(embedding.as_slice(), &Device::Cpu)?),
+            None => return Err(E::msg(format!("Item {} has no saved embedding", key.id))),
+        }
+    }
+    Ok(data.len())
+}
+
+/// Precomputes the `num_neighbours` most similar items of every item and writes them into the
+/// `item_neighbours` table. Existing neighbours of the items in the model are replaced.
+/// Ties are broken by the smaller id so that the output is the same every run.
+///
+/// @param `data` - the model
+/// @param `db_path` - a String containing the file path of the SQLite database
+/// @param `num_neighbours` - how many neighbours to store for each item
+///
+/// @return `Ok()` with the number of rows written [OR] `Err()`
+pub(crate) fn write_neighbours_sqlite(data: &HashMap<Data, Option<Tensor>>, db_path: &String, num_neighbours: usize) -> Result<usize> {
+    let mut items: Vec<(i32, Vec<f32>)> = Vec::with_capacity(data.len());
+    for (key, value) in data.iter() {
+        match value {
+            Some(embedding) => items.push((key.id, embedding.to_vec1::<f32>()?)),
+            None => return Err(E::msg(format!("Item {} has no embedding", key.id))),
+        }
+    }
+
+    let mut connection = Connection::open(db_path)?;
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS item_neighbours (
+            id INTEGER NOT NULL,
+            neighbour_id INTEGER NOT NULL,
+            rank INTEGER NOT NULL,
+            similarity REAL NOT NULL,
+            PRIMARY KEY (id, rank)
+        )",
+        [],
+    )?;
+
+    let transaction = connection.transaction()?;
+    let mut written = 0;
+    {
+        let mut delete = transaction.prepare("DELETE FROM item_neighbours WHERE id = ?1")?;
+        let mut insert = transaction.prepare(
+            "INSERT INTO item_neighbours (id, neighbour_id, rank, similarity) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for (id, embedding) in items.iter() {
+            let mut neighbours: Vec<(i32, f32)> = items
+                .iter()
+                .filter(|(other_id, _)| other_id != id)
+                .map(|(other_id, other)| (*other_id, cosine_similarity(embedding, other)))
+                .collect();
+            neighbours.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+            neighbours.truncate(num_neighbours);
+
+            delete.execute(params![id])?;
+            for (rank, (neighbour_id, similarity)) in neighbours.iter().enumerate() {
+                insert.execute(params![id, neighbour_id, rank as i64 + 1, similarity])?;
+                written += 1;
+            }
+        }
+    }
+    transaction.commit()?;
+    Ok(written)
+}
+
+/// Writes the compressed token embeddings of every item into the `item_token_embeddings` table, replacing any rows with the same id.
+/// The token embeddings are stored as a blob of one signed byte per value, one token after another, and how they were made
+/// is written into the `index_metadata` table.
+///
+/// @param `index` - the token embeddings
+/// @param `db_path` - a String containing the file path of the SQLite database
+/// @param `metadata` - how the token embeddings were made
+///
+/// @return `Ok()` with the number of rows written [OR] `Err()`
+pub(crate) fn write_token_embeddings_sqlite(index: &MultiVectorIndex, db_path: &String, metadata: &IndexMetadata) -> Result<usize> {
+    let mut connection = Connection::open(db_path)?;
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS item_token_embeddings (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            num_tokens INTEGER NOT NULL,
+            dimensions INTEGER NOT NULL,
+            tokens BLOB NOT NULL
+        )",
+        [],
+    )?;
+
+    let transaction = connection.transaction()?;
+    let mut written = 0;
+    {
+        let mut statement = transaction.prepare(
+            "INSERT OR REPLACE INTO item_token_embeddings (id, name, num_tokens, dimensions, tokens) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for (key, tokens) in index.items.iter() {
+            let bytes: Vec<u8> = tokens.values.iter().map(|x| *x as u8).collect();
+            statement.execute(params![key.id, key.name, tokens.num_tokens as i64, tokens.dimensions as i64, bytes])?;
+            written += 1;
+        }
+    }
+    write_index_metadata(&transaction, metadata)?;
+    transaction.commit()?;
+    Ok(written)
+}
+
+/// Reads the token embeddings of the items in the model back from the `item_token_embeddings` table.
+/// Rows are matched to items by id and items without a row are left out of the index.
+///
+/// @param `data` - the model
+/// @param `db_path` - a String containing the file path of the SQLite database
+/// @param `current` - how queries are embedded now
+///
+/// @return `Ok()` with the index [OR] `Err()` if the table couldn't be read, a row is malformed or the token embeddings
+/// were made differently from `current`
+pub(crate) fn read_token_embeddings_sqlite(data: &HashMap<Data, Option<Tensor>>, db_path: &String, current: &IndexMetadata) -> Result<MultiVectorIndex> {
+    let connection = Connection::open(db_path)?;
+    check_index_metadata(&connection, current)?;
+    let mut statement = connection.prepare("SELECT id, num_tokens, dimensions, tokens FROM item_token_embeddings")?;
+    let rows = statement.query_map([], |row| {
+        let id: i32 = row.get(0)?;
+        let num_tokens: i64 = row.get(1)?;
+        let dimensions: i64 = row.get(2)?;
+        let tokens: Vec<u8> = row.get(3)?;
+        Ok((id, num_tokens as usize, dimensions as usize, tokens))
+    })?;
+
+    let mut by_id: HashMap<i32, TokenMatrix> = HashMap::new();
+    for row in rows {
+        let (id, num_tokens, dimensions, tokens) = row?;
+        if tokens.len() != num_tokens * dimensions {
+            return Err(E::msg(format!("The token embeddings of item {} have the wrong size", id)));
+        }
+        by_id.insert(id, TokenMatrix { num_tokens, dimensions, values: tokens.into_iter().map(|x| x as i8).collect() });
+    }
+
+    let mut index = MultiVectorIndex::default();
+    for key in data.keys() {
+        if let Some(tokens) = by_id.get(&key.id) {
+            index.items.insert(key.clone(), tokens.clone());
+        }
+    }
+    Ok(index)
+}
+
+/// Writes how the embeddings were made into the `index_metadata` table, one key per row
+fn write_index_metadata(connection: &Connection, metadata: &IndexMetadata) -> Result<()> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS index_metadata (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+    let mut statement = connection.prepare("INSERT OR REPLACE INTO index_metadata (key, value) VALUES (?1, ?2)")?;
+    statement.execute(params!["model_id", metadata.model_id])?;
+    statement.execute(params!["revision", metadata.revision])?;
+    statement.execute(params!["architecture", metadata.architecture])?;
+    statement.execute(params!["quantization", metadata.quantization])?;
+    statement.execute(params!["query_template", metadata.query_template])?;
+    statement.execute(params!["document_template", metadata.document_template])?;
+    Ok(())
+}
+
+/// Reads how the saved embeddings were made back from the `index_metadata` table
+///
+/// @param `db_path` - a String containing the file path of the SQLite database
+///
+/// @return `Ok()` with the metadata, or NONE for databases written before it was saved [OR] `Err()` if the table couldn't be read
+pub(crate) fn read_index_metadata(db_path: &String) -> Result<Option<IndexMetadata>> {
+    read_index_metadata_from(&Connection::open(db_path)?)
+}
+
+fn read_index_metadata_from(connection: &Connection) -> Result<Option<IndexMetadata>> {
+    let exists: Option<String> = connection
+        .query_row("SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'index_metadata'", [], |row| row.get(0))
+        .optional()?;
+    if exists.is_none() {
+        return Ok(None);
+    }
+    let value = |key: &str| -> Result<String> {
+        let value: Option<String> = connection
+            .query_row("SELECT value FROM index_metadata WHERE key = ?1", params![key], |row| row.get(0))
+            .optional()?;
+        value.ok_or(E::msg(format!("The index metadata has no {}", key)))
+    };
+    // Databases saved before the architecture and quantization were recorded were made with f32 BERT models
+    let value_or = |key: &str, default: &str| value(key).unwrap_or(default.to_string());
+    Ok(Some(IndexMetadata {
+        model_id: value("model_id")?,
+        revision: value("revision")?,
+        architecture: value_or("architecture", "bert"),
+        quantization: value_or("quantization", "none"),
+        query_template: value("query_template")?,
+        document_template: value("document_template")?,
+    }))
+}
+
+/// Fails if the saved embeddings were made differently from how queries are embedded now, since they
+/// wouldn't be comparable. Databases without metadata are trusted.
+fn check_index_metadata(connection: &Connection, current: &IndexMetadata) -> Result<()> {
+    match read_index_metadata_from(connection)? {
+        Some(saved) if saved != *current => Err(E::msg(format!(
+            "The embeddings were made with {} but queries would be embedded with {}. Pass the matching --model-id, --revision, --model-dir, --architecture, --quantized, --query-template and --document-template",
+            saved, current
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database file in the temp directory that is removed when dropped
+    struct TempDb(String);
+
+    impl TempDb {
+        fn new(name: &str) -> TempDb {
+            let path = std::env::temp_dir().join(format!("reco_forge_{}_{}.db", name, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            TempDb(path.to_string_lossy().to_string())
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn metadata() -> IndexMetadata {
+        IndexMetadata {
+            model_id: "sentence-transformers/all-MiniLM-L6-v2".to_string(),
+            revision: "refs/pr/21".to_string(),
+            architecture: "bert".to_string(),
+            quantization: "none".to_string(),
+            query_template: "{}".to_string(),
+            document_template: "{}".to_string(),
+        }
+    }
+
+    fn item(id: i32, embedding: &[f32]) -> (Data, Option<Tensor>) {
+        let data = Data { id, name: format!("Item {}", id), summary: format!("Summary {}", id), tags: Vec::new() };
+        (data, Some(Tensor::new(embedding, &Device::Cpu).unwrap()))
+    }
+
+    fn without_embeddings(data: &HashMap<Data, Option<Tensor>>) -> HashMap<Data, Option<Tensor>> {
+        data.keys().map(|key| (key.clone(), None)).collect()
+    }
+
+    #[test]
+    fn read_embeddings_gives_back_the_saved_embeddings() {
+        let db = TempDb::new("read_embeddings");
+        let data: HashMap<Data, Option<Tensor>> = [item(1, &[0.5, -1.25, 3.0]), item(2, &[f32::MIN_POSITIVE, 0.0, -0.0])].into_iter().collect();
+        write_embeddings_sqlite(&data, &db.0, &metadata()).unwrap();
+
+        let mut loaded = without_embeddings(&data);
+        assert_eq!(read_embeddings_sqlite(&mut loaded, &db.0, &metadata()).unwrap(), 2);
+        for (key, value) in data.iter() {
+            let expected = value.as_ref().unwrap().to_vec1::<f32>().unwrap();
+            assert_eq!(loaded[key].as_ref().unwrap().to_vec1::<f32>().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn read_embeddings_rejects_embeddings_made_differently() {
+        let db = TempDb::new("read_embeddings_mismatch");
+        let data: HashMap<Data, Option<Tensor>> = [item(1, &[1.0, 0.0])].into_iter().collect();
+        write_embeddings_sqlite(&data, &db.0, &metadata()).unwrap();
+
+        let quantized = IndexMetadata { quantization: "q8_0".to_string(), ..metadata() };
+        assert!(read_embeddings_sqlite(&mut without_embeddings(&data), &db.0, &quantized).is_err());
+        let distilled = IndexMetadata { architecture: "distil-bert".to_string(), ..metadata() };
+        assert!(read_embeddings_sqlite(&mut without_embeddings(&data), &db.0, &distilled).is_err());
+    }
+
+    #[test]
+    fn read_embeddings_fails_for_items_without_a_saved_embedding() {
+        let db = TempDb::new("read_embeddings_missing");
+        let data: HashMap<Data, Option<Tensor>> = [item(1, &[1.0, 0.0])].into_iter().collect();
+        write_embeddings_sqlite(&data, &db.0, &metadata()).unwrap();
+
+        let mut loaded = without_embeddings(&data);
+        loaded.insert(item(2, &[]).0, None);
+        assert!(read_embeddings_sqlite(&mut loaded, &db.0, &metadata()).is_err());
+    }
+
+    #[test]
+    fn metadata_without_architecture_and_quantization_is_f32_bert() {
+        let db = TempDb::new("old_metadata");
+        let connection = Connection::open(&db.0).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE index_metadata (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+                INSERT INTO index_metadata VALUES ('model_id', 'sentence-transformers/all-MiniLM-L6-v2'), ('revision', 'refs/pr/21'),
+                    ('query_template', '{}'), ('document_template', '{}');",
+            )
+            .unwrap();
+        assert_eq!(read_index_metadata(&db.0).unwrap(), Some(metadata()));
+    }
+
+    /// Creates a `products` table with the given (id, name, summary, tags) rows
+    fn catalog(db: &TempDb, rows: &[(i32, &str, &str, Option<&str>)]) {
+        let connection = Connection::open(&db.0).unwrap();
+        connection.execute("CREATE TABLE products (id INTEGER PRIMARY KEY, name TEXT, summary TEXT, tags TEXT)", []).unwrap();
+        for (id, name, summary, tags) in rows {
+            connection.execute("INSERT INTO products VALUES (?1, ?2, ?3, ?4)", params![id, name, summary, tags]).unwrap();
+        }
+    }
+
+    fn tags_by_id(data: &HashMap<Data, Option<Tensor>>) -> HashMap<i32, Vec<String>> {
+        data.keys().map(|key| (key.id, key.tags.clone())).collect()
+    }
+
+    #[test]
+    fn extract_data_requires_four_columns() {
+        let db = TempDb::new("extract_columns");
+        catalog(&db, &[(1, "Dune", "A desert planet", Some("sci-fi"))]);
+        assert!(extract_data_sqlite(&db.0, "SELECT id, name, summary FROM products").is_err());
+    }
+
+    #[test]
+    fn extract_data_reads_null_json_and_comma_separated_tags() {
+        let db = TempDb::new("extract_tags");
+        catalog(&db, &[
+            (1, "Dune", "A desert planet", None),
+            (2, "Alien", "A crew meets a creature", Some(r#"["sci-fi", "horror, space"]"#)),
+            (3, "Heat", "A heist in Los Angeles", Some(" crime, , thriller ")),
+        ]);
+        let data = extract_data_sqlite(&db.0, "SELECT id, name, summary, tags FROM products").unwrap();
+        assert_eq!(data.len(), 3);
+        assert!(data.values().all(|value| value.is_none()));
+
+        let tags = tags_by_id(&data);
+        assert_eq!(tags[&1], Vec::<String>::new());
+        assert_eq!(tags[&2], vec!["sci-fi", "horror, space"]);
+        assert_eq!(tags[&3], vec!["crime", "thriller"]);
+    }
+
+    #[test]
+    fn parse_tags_accepts_json_arrays_and_comma_separated_strings() {
+        assert_eq!(parse_tags(None), Vec::<String>::new());
+        assert_eq!(parse_tags(Some("  ")), Vec::<String>::new());
+        assert_eq!(parse_tags(Some(r#"["a, b", "c"]"#)), vec!["a, b", "c"]);
+        assert_eq!(parse_tags(Some("a, b ,c,")), vec!["a", "b", "c"]);
+        // Brackets that aren't a JSON array of strings are read as a comma separated string
+        assert_eq!(parse_tags(Some("[a, b]")), vec!["[a", "b]"]);
+    }
+
+    #[test]
+    fn write_embeddings_stores_little_endian_f32_blobs() {
+        let db = TempDb::new("write_embeddings");
+        let embedding = [0.5, -1.25, 3.0e-8, f32::MAX];
+        let data: HashMap<Data, Option<Tensor>> = [item(7, &embedding)].into_iter().collect();
+        assert_eq!(write_embeddings_sqlite(&data, &db.0, &metadata()).unwrap(), 1);
+
+        let connection = Connection::open(&db.0).unwrap();
+        let (name, dimensions, blob): (String, i64, Vec<u8>) = connection
+            .query_row("SELECT name, dimensions, embedding FROM item_embeddings WHERE id = 7", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap();
+        assert_eq!(name, "Item 7");
+        assert_eq!(dimensions, 4);
+        assert_eq!(blob, embedding.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn write_embeddings_writes_the_index_metadata() {
+        let db = TempDb::new("write_metadata");
+        let data: HashMap<Data, Option<Tensor>> = [item(1, &[1.0])].into_iter().collect();
+        let written = IndexMetadata { query_template: "query: {}".to_string(), document_template: "passage: {}".to_string(), ..metadata() };
+        write_embeddings_sqlite(&data, &db.0, &written).unwrap();
+
+        let connection = Connection::open(&db.0).unwrap();
+        let mut statement = connection.prepare("SELECT key, value FROM index_metadata ORDER BY key").unwrap();
+        let rows: Vec<(String, String)> = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().map(|row| row.unwrap()).collect();
+        let expected = [
+            ("architecture", "bert"),
+            ("document_template", "passage: {}"),
+            ("model_id", "sentence-transformers/all-MiniLM-L6-v2"),
+            ("quantization", "none"),
+            ("query_template", "query: {}"),
+            ("revision", "refs/pr/21"),
+        ];
+        assert_eq!(rows, expected.map(|(key, value)| (key.to_string(), value.to_string())));
+        assert_eq!(read_index_metadata(&db.0).unwrap(), Some(written));
+    }
+
+    fn neighbours_of(db: &TempDb, id: i32) -> Vec<(i32, i64)> {
+        let connection = Connection::open(&db.0).unwrap();
+        let mut statement = connection.prepare("SELECT neighbour_id, rank FROM item_neighbours WHERE id = ?1 ORDER BY rank").unwrap();
+        let rows = statement.query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    }
+
+    #[test]
+    fn write_neighbours_breaks_ties_by_the_smaller_id() {
+        let db = TempDb::new("neighbours_ties");
+        // Items 2, 3 and 4 are all as similar to item 1, item 5 is further away
+        let data: HashMap<Data, Option<Tensor>> =
+            [item(1, &[1.0, 0.0]), item(4, &[1.0, 1.0]), item(3, &[1.0, 1.0]), item(2, &[1.0, 1.0]), item(5, &[0.0, 1.0])].into_iter().collect();
+        assert_eq!(write_neighbours_sqlite(&data, &db.0, 3).unwrap(), 15);
+        assert_eq!(neighbours_of(&db, 1), vec![(2, 1), (3, 2), (4, 3)]);
+    }
+
+    #[test]
+    fn write_neighbours_replaces_the_rows_of_a_previous_run() {
+        let db = TempDb::new("neighbours_rerun");
+        let data: HashMap<Data, Option<Tensor>> = [item(1, &[1.0, 0.0]), item(2, &[0.9, 0.1]), item(3, &[0.0, 1.0])].into_iter().collect();
+        write_neighbours_sqlite(&data, &db.0, 2).unwrap();
+        write_neighbours_sqlite(&data, &db.0, 2).unwrap();
+        assert_eq!(neighbours_of(&db, 1), vec![(2, 1), (3, 2)]);
+
+        // Fewer neighbours on the next run leaves no rows of the previous one behind
+        write_neighbours_sqlite(&data, &db.0, 1).unwrap();
+        assert_eq!(neighbours_of(&db, 1), vec![(2, 1)]);
+        let connection = Connection::open(&db.0).unwrap();
+        let rows: i64 = connection.query_row("SELECT COUNT(*) FROM item_neighbours", [], |row| row.get(0)).unwrap();
+        assert_eq!(rows, 3);
+    }
+}
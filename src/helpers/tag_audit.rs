@@ -0,0 +1,158 @@
+use super::auto_tagging::{is_untagged, nearest_tagged, tag_votes};
+use super::tags::tag_vocabulary;
+use super::types::{AuditNeighbour, Data, TagAuditEntry, TagAuditOptions, TagAuditReport, TagChange};
+use anyhow::{Error as E, Result};
+use candle::Tensor;
+use std::collections::HashMap;
+
+/// Receives the model and compares every tagged item's tags with the tags of its nearest tagged neighbours,
+/// suggesting tags to add that most neighbours have and tags to remove that almost none of them have
+///
+/// @param `data` - the model
+/// @param `options` - the number of neighbours and the thresholds
+///
+/// @return `Ok()` with the report [OR] `Err()` if an item has no embedding
+pub(crate) fn audit_tags(data: &HashMap<Data, Option<Tensor>>, options: &TagAuditOptions) -> Result<TagAuditReport> {
+    let mut tagged: Vec<(&Data, Vec<f32>)> = Vec::new();
+    for (key, value) in data.iter() {
+        let embedding = match value {
+            Some(embedding) => embedding.to_vec1::<f32>()?,
+            None => return Err(E::msg(format!("Item {} has no embedding", key.id))),
+        };
+        if !is_untagged(key) {
+            tagged.push((key, embedding));
+        }
+    }
+    tagged.sort_by(|a, b| a.0.id.cmp(&b.0.id).then(a.0.name.cmp(&b.0.name)));
+    let spellings: HashMap<String, String> = tag_vocabulary(tagged.iter().map(|(key, _)| *key), None)
+        .into_iter()
+        .map(|info| (info.tag.trim().to_lowercase(), info.tag))
+        .collect();
+
+    let mut entries: Vec<TagAuditEntry> = Vec::new();
+    for (key, embedding) in tagged.iter() {
+        let neighbours = nearest_tagged(embedding, &tagged, options.num_neighbours, Some(*key));
+        let votes = tag_votes(&neighbours);
+        let has_tag = |neighbour: &Data, tag: &str| neighbour.tags.iter().any(|x| x.trim().to_lowercase() == tag);
+        let evidence = |tag: &str, with_tag: bool| -> Vec<AuditNeighbour> {
+            neighbours
+                .iter()
+                .filter(|(neighbour, _)| has_tag(neighbour, tag) == with_tag)
+                .take(options.max_evidence)
+                .map(|(neighbour, similarity)| AuditNeighbour { id: neighbour.id, name: neighbour.name.clone(), similarity: *similarity })
+                .collect()
+        };
+
+        let mut own_tags: Vec<String> = Vec::new();
+        for tag in key.tags.iter().map(|x| x.trim().to_lowercase()).filter(|x| !x.is_empty()) {
+            if !own_tags.contains(&tag) {
+                own_tags.push(tag);
+            }
+        }
+        let support = |tag: &String| votes.get(tag).copied().unwrap_or(0.0);
+        let agreement = own_tags.iter().map(support).sum::<f32>() / own_tags.len() as f32;
+
+        let mut add: Vec<TagChange> = votes
+            .iter()
+            .filter(|(tag, vote)| !own_tags.contains(tag) && **vote >= options.add_threshold)
+            .map(|(tag, vote)| TagChange { tag: spellings.get(tag).cloned().unwrap_or(tag.clone()), support: *vote, evidence: evidence(tag, true) })
+            .collect();
+        add.sort_by(|a, b| b.support.total_cmp(&a.support).then(a.tag.cmp(&b.tag)));
+
+        // Without neighbours there is nothing to disagree with
+        let mut remove: Vec<TagChange> = Vec::new();
+        if !votes.is_empty() {
+            remove = own_tags
+                .iter()
+                .filter(|tag| support(tag) < options.remove_threshold)
+                .map(|tag| TagChange { tag: spellings.get(tag).cloned().unwrap_or(tag.clone()), support: support(tag), evidence: evidence(tag, false) })
+                .collect();
+            remove.sort_by(|a, b| a.support.total_cmp(&b.support).then(a.tag.cmp(&b.tag)));
+        }
+
+        if !add.is_empty() || !remove.is_empty() {
+            entries.push(TagAuditEntry { id: key.id, name: key.name.clone(), tags: key.tags.clone(), agreement, add, remove });
+        }
+    }
+    entries.sort_by(|a, b| a.agreement.total_cmp(&b.agreement).then(a.id.cmp(&b.id)));
+    Ok(TagAuditReport { num_items: tagged.len(), entries })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle::Device;
+
+    fn item(id: i32, tags: &[&str], embedding: &[f32]) -> (Data, Option<Tensor>) {
+        let data = Data { id, name: format!("Item {}", id), summary: String::new(), tags: tags.iter().map(|tag| tag.to_string()).collect() };
+        (data, Some(Tensor::new(embedding, &Device::Cpu).unwrap()))
+    }
+
+    /// Items 1 to 3 are comedies, Item 4 sits among them but is tagged Horror and Item 5 is on its own.
+    /// Item 6 is untagged and is neither audited nor a neighbour.
+    fn catalog() -> HashMap<Data, Option<Tensor>> {
+        [
+            item(1, &["Comedy"], &[1.0, 0.0]),
+            item(2, &["Comedy"], &[0.96, 0.28]),
+            item(3, &["Comedy", "Romance"], &[0.8, 0.6]),
+            item(4, &["Horror"], &[1.0, 0.0]),
+            item(5, &["Horror"], &[0.0, 1.0]),
+            item(6, &[], &[1.0, 0.0]),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    fn options(add_threshold: f32, remove_threshold: f32) -> TagAuditOptions {
+        TagAuditOptions { num_neighbours: 3, add_threshold, remove_threshold, max_evidence: 2 }
+    }
+
+    fn changes(changes: &[TagChange]) -> Vec<&str> {
+        changes.iter().map(|change| change.tag.as_str()).collect()
+    }
+
+    #[test]
+    fn tags_the_neighbours_agree_on_are_added_and_tags_they_lack_are_removed() {
+        let report = audit_tags(&catalog(), &options(0.7, 0.1)).unwrap();
+        assert_eq!(report.num_items, 5);
+
+        let ids: Vec<i32> = report.entries.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, vec![4, 5, 3]);
+        let (item_4, item_5, item_3) = (&report.entries[0], &report.entries[1], &report.entries[2]);
+
+        // Every neighbour of Item 4 is a comedy and none of them is Horror
+        assert_eq!(item_4.agreement, 0.0);
+        assert_eq!(changes(&item_4.add), vec!["Comedy"]);
+        assert!((item_4.add[0].support - 1.0).abs() < 1e-6);
+        let evidence: Vec<i32> = item_4.add[0].evidence.iter().map(|neighbour| neighbour.id).collect();
+        assert_eq!(evidence, vec![1, 2]);
+        assert_eq!(changes(&item_4.remove), vec!["Horror"]);
+
+        // Romance has 0.68 of Item 5's votes, under the add threshold
+        assert_eq!(changes(&item_5.add), vec!["Comedy"]);
+        assert_eq!(changes(&item_5.remove), vec!["Horror"]);
+
+        // Item 3 keeps Comedy, but none of its neighbours is a romance
+        assert!(item_3.add.is_empty());
+        assert_eq!(changes(&item_3.remove), vec!["Romance"]);
+        assert!((item_3.agreement - 0.5 * 1.736 / 2.536).abs() < 1e-4);
+    }
+
+    #[test]
+    fn the_thresholds_decide_what_is_suggested() {
+        let report = audit_tags(&catalog(), &options(0.6, 0.0)).unwrap();
+        let entries: Vec<(i32, Vec<&str>, Vec<&str>)> =
+            report.entries.iter().map(|entry| (entry.id, changes(&entry.add), changes(&entry.remove))).collect();
+        assert_eq!(entries, vec![(4, vec!["Comedy"], vec![]), (5, vec!["Comedy", "Romance"], vec![])]);
+
+        let report = audit_tags(&catalog(), &options(1.1, 0.0)).unwrap();
+        assert!(report.entries.is_empty());
+    }
+
+    #[test]
+    fn an_item_without_an_embedding_is_an_error() {
+        let mut data = catalog();
+        data.insert(item(7, &["Comedy"], &[1.0, 0.0]).0, None);
+        assert!(audit_tags(&data, &options(0.7, 0.1)).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use super::types::{Data, TagHierarchy, TagInfo};
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Receives the path to a hierarchy file and reads it
+///
+/// @param `file_name` - the path to a JSON object mapping each parent tag to a list of child tags
+///
+/// @return `Ok()` with the hierarchy [OR] `Err()` if the file couldn't be read or deserialized
+pub(crate) fn read_hierarchy(file_name: &String) -> Result<TagHierarchy> {
+    let file = std::fs::read_to_string(file_name)?;
+    Ok(serde_json::from_str(&file)?)
+}
+
+/// Every tag under `tag` in the hierarchy, children first and then their children, spelled the way the hierarchy spells them.
+/// Tags are compared ignoring case and a tag that is (indirectly) its own child is only visited once.
+pub(crate) fn descendants(hierarchy: &TagHierarchy, tag: &str) -> Vec<String> {
+    let mut children: HashMap<String, Vec<String>> = HashMap::new();
+    for (parent, tags) in &hierarchy.children {
+        children.entry(parent.trim().to_lowercase()).or_default().extend(tags.iter().map(|x| x.trim().to_string()));
+    }
+    let tag = tag.trim().to_lowercase();
+    let mut found: Vec<String> = Vec::new();
+    let mut queue: Vec<String> = vec![tag.clone()];
+    while !queue.is_empty() {
+        let current = queue.remove(0);
+        for child in children.get(&current).into_iter().flatten() {
+            let key = child.to_lowercase();
+            if key != tag && !found.iter().any(|x| x.to_lowercase() == key) {
+                found.push(child.clone());
+                queue.push(key);
+            }
+        }
+    }
+    found
+}
+
+/// Rewrites a tags input so that each tag also matches its children, e.g. "Action,Comedy" becomes
+/// "action|superhero|dc,comedy". Tags without children and NONE are left as they are.
+pub(crate) fn expand_tags_input(hierarchy: &TagHierarchy, tags_input: &str) -> String {
+    if tags_input == "NONE" {
+        return tags_input.to_string();
+    }
+    tags_input
+        .split(',')
+        .map(|alternatives| {
+            let mut expanded: Vec<String> = Vec::new();
+            for tag in alternatives.split('|').map(|x| x.trim().to_lowercase()) {
+                for tag in std::iter::once(tag.clone()).chain(descendants(hierarchy, &tag).iter().map(|x| x.to_lowercase())) {
+                    if !expanded.contains(&tag) {
+                        expanded.push(tag);
+                    }
+                }
+            }
+            expanded.join("|")
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Receives the items of a catalog and lists every tag (ignoring case) with the number of items that have it
+/// and the tags it appears alongside
+///
+/// @param `items` - the items of the catalog
+/// @param `hierarchy` - which tags are under which, if any
+///
+/// @return the tags, most used first and ties alphabetically
+pub(crate) fn tag_vocabulary<'a>(items: impl IntoIterator<Item = &'a Data>, hierarchy: Option<&TagHierarchy>) -> Vec<TagInfo> {
+    let mut spellings: HashMap<String, BTreeMap<String, usize>> = HashMap::new();
+    let mut item_tags: Vec<BTreeSet<String>> = Vec::new();
+    for item in items {
+        let mut tags: BTreeSet<String> = BTreeSet::new();
+        for tag in item.tags.iter().map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            *spellings.entry(tag.to_lowercase()).or_default().entry(tag.to_string()).or_insert(0) += 1;
+            tags.insert(tag.to_lowercase());
+        }
+        item_tags.push(tags);
+    }
+
+    // Most common spelling, ties broken alphabetically so the choice is always the same
+    let spelling = |tag: &String| -> String {
+        match spellings.get(&tag.to_lowercase()) {
+            Some(counts) => counts.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0))).map(|(x, _)| x.clone()).unwrap_or_default(),
+            None => tag.clone(),
+        }
+    };
+
+    let mut vocabulary: Vec<TagInfo> = Vec::new();
+    for tag in spellings.keys() {
+        let children: Vec<String> = match hierarchy {
+            Some(hierarchy) => descendants(hierarchy, tag),
+            None => Vec::new(),
+        };
+        let mut count = 0;
+        let mut count_with_children = 0;
+        let mut co_occurrences: HashMap<String, usize> = HashMap::new();
+        for tags in &item_tags {
+            if tags.contains(tag) {
+                count += 1;
+                for other in tags.iter().filter(|other| *other != tag) {
+                    *co_occurrences.entry(other.clone()).or_insert(0) += 1;
+                }
+            }
+            if tags.contains(tag) || children.iter().any(|child| tags.contains(&child.to_lowercase())) {
+                count_with_children += 1;
+            }
+        }
+        let mut co_occurrences: Vec<(String, usize)> = co_occurrences.into_iter().map(|(other, n)| (spelling(&other), n)).collect();
+        co_occurrences.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        vocabulary.push(TagInfo {
+            tag: spelling(tag),
+            count,
+            count_with_children,
+            children: children.iter().map(&spelling).collect(),
+            co_occurrences,
+        });
+    }
+    vocabulary.sort_by(|a, b| b.count.cmp(&a.count).then(a.tag.cmp(&b.tag)));
+    vocabulary
+}
@@ -1,20 +1,24 @@
 use anyhow::{Error as E, Result as OtherResult};
-use candle::Device;
-use candle_nn::VarBuilder;
-use candle_transformers::models::bert::{BertModel, Config, HiddenAct, DTYPE};
-use clap::Parser;
+use candle::{Device, Tensor};
+use candle_nn::{Linear, VarBuilder};
+use candle_transformers::models::bert::{BertModel, DTYPE};
+use clap::{Parser, Subcommand, ValueEnum};
 use hf_hub::{api::sync::Api, Repo, RepoType};
 use serde::{Serialize, Deserialize};
+use super::encoder::{detect_architecture, load_encoder, Encoder};
+use super::quantization::{load_quantized_bert, read_quantization};
+use std::collections::HashMap;
 use std::fmt;
+use std::path::{Path, PathBuf};
 use tokenizers::Tokenizer;
 
 /// The struct that holds the data for one item in the model
 /// 
 /// # Fields
-///     * `id` - The id of the item
-///     * `name` - The name of the item
-///     * `summary` - The summary/description of the item
-///     * `tags` - The tags of the item that are used to filter the recommendations
+/// * `id` - The id of the item
+/// * `name` - The name of the item
+/// * `summary` - The summary/description of the item
+/// * `tags` - The tags of the item that are used to filter the recommendations
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
 pub struct Data {
     pub id: i32,
@@ -31,6 +35,1131 @@ impl fmt::Display for Data {
     }
 }
 
+/// The result of looking up an item in the model by name
+#[derive(Debug, Clone)]
+pub enum ItemLookup {
+    /// The name matched one item
+    Found(Data),
+    /// Several items have exactly this name, ordered by id
+    Collision(Vec<Data>),
+    /// The name was close to several items or not close enough to any one of them, so these are suggested instead, best match first
+    Ambiguous(Vec<(Data, f32)>),
+    /// Nothing in the model is close to the name
+    NotFound,
+}
+
+impl ItemLookup {
+    /// What to tell the user after looking up `query`: the items to pick an id from, the suggestions or that nothing
+    /// was found. Empty when the item was found.
+    pub fn prompt(&self, query: &str) -> String {
+        match self {
+            ItemLookup::Found(_) => String::new(),
+            ItemLookup::Collision(items) => {
+                let mut prompt = format!("{} items are called \"{}\":\n", items.len(), query);
+                for item in items {
+                    prompt.push_str(&format!("    {}: {} ({})\n", item.id, item.name, item.tags.join(", ")));
+                }
+                prompt + "Input the id of the one you mean:"
+            },
+            ItemLookup::Ambiguous(suggestions) => {
+                let mut prompt = format!("Couldn't find exactly one item for \"{}\", did you mean:\n", query);
+                for (suggestion, _score) in suggestions {
+                    prompt.push_str(&format!("    {}\n", suggestion.name));
+                }
+                prompt + "Please input the item again:"
+            },
+            ItemLookup::NotFound => format!("\"{}\" is not in the dataset, please input the item again:", query),
+        }
+    }
+
+    /// The item the user meant: the one that was found, or for a collision the one whose id is `id_input`
+    pub fn pick(&self, id_input: &str) -> Option<&Data> {
+        match self {
+            ItemLookup::Found(item) => Some(item),
+            ItemLookup::Collision(items) => items.iter().find(|item| Ok(item.id) == id_input.trim().parse::<i32>()),
+            _ => None,
+        }
+    }
+}
+
+/// A reference to an item in the model, either by its id or by its name (see `find_item` for how names are matched)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItemRef {
+    Id(i32),
+    Name(String),
+}
+
+/// How the seed items of `pass_items` are combined into one list of recommendations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SeedCombination {
+    /// Recommend the items closest to the weighted average of the seeds' embeddings
+    Centroid,
+    /// Score each item by its highest (weighted) similarity to any one seed
+    MaxSimilarity,
+    /// Take turns adding each seed's own top recommendations, seeds with higher weights take more turns
+    RoundRobin,
+}
+
+/// An example the user gives as feedback, either an item in the model or free text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Example {
+    Item(ItemRef),
+    Text(String),
+}
+
+/// What happens to items that are too similar to a negative example
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NegativeAction {
+    /// Leave their scores as they are, the negative examples only move the query
+    Keep,
+    /// Subtract this amount from their scores
+    Demote(f32),
+    /// Leave them out of the recommendations
+    Drop,
+}
+
+/// A query made of positive and negative examples, combined Rocchio style:
+/// `alpha * description + beta * mean(positive) - gamma * mean(negative)`
+///
+/// # Fields
+/// * `description` - An optional description of what the user wants
+/// * `positive` - Items or text the user wants recommendations like
+/// * `negative` - Items or text the user doesn't want recommendations like
+/// * `alpha` - The weight of the description
+/// * `beta` - The weight of the positive examples
+/// * `gamma` - The weight of the negative examples
+/// * `negative_threshold` - Items at least this similar to any negative example get `negative_action`
+/// * `negative_action` - What happens to items that are too similar to a negative example
+#[derive(Debug, Clone)]
+pub struct FeedbackQuery {
+    pub description: Option<String>,
+    pub positive: Vec<Example>,
+    pub negative: Vec<Example>,
+    pub alpha: f32,
+    pub beta: f32,
+    pub gamma: f32,
+    pub negative_threshold: f32,
+    pub negative_action: NegativeAction,
+}
+
+impl Default for FeedbackQuery {
+    fn default() -> Self {
+        FeedbackQuery {
+            description: None,
+            positive: Vec::new(),
+            negative: Vec::new(),
+            alpha: 1.0,
+            beta: 0.75,
+            gamma: 0.15,
+            negative_threshold: 0.7,
+            negative_action: NegativeAction::Drop,
+        }
+    }
+}
+
+/// A recommendation from `pass_item_with_description`, with the score split into the part that comes from
+/// the item and the part that comes from the description
+///
+/// # Fields
+/// * `name` - The name of the recommended item
+/// * `score` - The blended score, `item_score + text_score`
+/// * `item_score` - How much similarity to the seed item contributed, already weighted
+/// * `text_score` - How much similarity to the description contributed, already weighted
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlendedRecommendation {
+    pub name: String,
+    pub score: f32,
+    pub item_score: f32,
+    pub text_score: f32,
+}
+
+impl BlendedRecommendation {
+    /// Whether the item term contributed more to the score than the text term
+    pub fn mostly_from_item(&self) -> bool {
+        self.item_score >= self.text_score
+    }
+}
+
+impl fmt::Display for BlendedRecommendation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}% {} (item {}%, description {}%)",
+            (self.score * 100.0).round(),
+            self.name,
+            (self.item_score * 100.0).round(),
+            (self.text_score * 100.0).round()
+        )
+    }
+}
+
+/// Which page of recommendations to return. Recommendations are ordered by score and then by id,
+/// so the same query always pages through the items in the same order.
+///
+/// # Fields
+/// * `min_score` - Items scoring below this are left out, however few are left
+/// * `limit` - The number of recommendations on the page
+/// * `offset` - How many recommendations to skip, ignored when `cursor` is set
+/// * `cursor` - The `next_cursor` of the previous page, to continue right after it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageRequest {
+    pub min_score: Option<f32>,
+    pub limit: usize,
+    pub offset: usize,
+    pub cursor: Option<String>,
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        PageRequest { min_score: None, limit: 10, offset: 0, cursor: None }
+    }
+}
+
+/// A page of recommendations
+///
+/// # Fields
+/// * `items` - (Item name, similarity) tuples on this page
+/// * `total` - How many items passed the tag filter and the score floor in total
+/// * `next_cursor` - Pass this as `cursor` to get the next page, None if this is the last page
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub items: Vec<(String, f32)>,
+    pub total: usize,
+    pub next_cursor: Option<String>,
+}
+
+/// How the top candidates are re-ranked to make the recommendations less redundant
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Diversity {
+    /// Recommendations are ranked by similarity only
+    Off,
+    /// Maximal Marginal Relevance: each next recommendation is picked from the `pool_size` most similar candidates by
+    /// `lambda * similarity - (1 - lambda) * highest similarity to a recommendation already picked`.
+    /// A `lambda` of 1 is the same as Off, lower values trade relevance for diversity.
+    Mmr { lambda: f32, pool_size: usize },
+}
+
+/// Limits on how the recommendations are spread over tags. Tags are compared ignoring case and an
+/// item's primary tag is the first tag in its `tags`.
+///
+/// # Fields
+/// * `max_per_primary_tag` - At most this many recommendations can share a primary tag
+/// * `min_per_tag` - (tag, count) pairs, at least `count` recommendations have to have `tag`
+#[derive(Debug, Clone, Default)]
+pub struct TagConstraints {
+    pub max_per_primary_tag: Option<usize>,
+    pub min_per_tag: Vec<(String, usize)>,
+}
+
+/// A single constraint from `TagConstraints`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagConstraint {
+    MaxPerPrimaryTag(usize),
+    MinPerTag(String, usize),
+}
+
+/// Whether a constraint changed the recommendations
+///
+/// # Fields
+/// * `constraint` - The constraint
+/// * `binding` - Whether the recommendations would have been different without it
+/// * `satisfied` - Whether the recommendations meet it, a minimum can't be met if too few items have the tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstraintStatus {
+    pub constraint: TagConstraint,
+    pub binding: bool,
+    pub satisfied: bool,
+}
+
+impl fmt::Display for ConstraintStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.constraint {
+            TagConstraint::MaxPerPrimaryTag(count) => write!(f, "At most {} per primary tag", count)?,
+            TagConstraint::MinPerTag(tag, count) => write!(f, "At least {} with tag {}", count, tag)?,
+        }
+        let binding = if self.binding { "binding" } else { "not binding" };
+        let satisfied = if self.satisfied { "satisfied" } else { "not satisfied" };
+        write!(f, ": {}, {}", binding, satisfied)
+    }
+}
+
+/// How tag boosts change an item's score
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoostMode {
+    /// Each matching tag's boost is added to the score
+    Additive,
+    /// The score plus 1 is multiplied by (1 + boost) for each matching tag, so a positive boost raises negative scores too
+    Multiplicative,
+}
+
+/// Options that change how the recommendations of a single query are ranked
+///
+/// # Fields
+/// * `tag_boosts` - (tag, boost) pairs, items with the tag (ignoring case, alternatives separated by a |) get their score raised by a positive boost or lowered by a negative one, before anything else
+/// * `boost_mode` - Whether the boosts are added to or multiplied with the score
+/// * `min_score` - Items scoring below this after the boosts are left out, however few are left
+/// * `diversity` - Whether and how the recommendations are re-ranked for diversity
+/// * `tag_constraints` - Limits on how the recommendations are spread over tags, applied after `diversity`
+/// * `explain` - Explain why each item was recommended, which takes an extra pass through the model
+/// * `tag_hierarchy` - Which tags are under which, if any, so that filtering by or boosting a parent tag also matches its children
+#[derive(Debug, Clone)]
+pub struct QueryOptions {
+    pub tag_boosts: Vec<(String, f32)>,
+    pub boost_mode: BoostMode,
+    pub min_score: Option<f32>,
+    pub diversity: Diversity,
+    pub tag_constraints: TagConstraints,
+    pub explain: bool,
+    pub tag_hierarchy: Option<TagHierarchy>,
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        QueryOptions {
+            tag_boosts: Vec::new(),
+            boost_mode: BoostMode::Additive,
+            min_score: None,
+            diversity: Diversity::Off,
+            tag_constraints: TagConstraints::default(),
+            explain: false,
+            tag_hierarchy: None,
+        }
+    }
+}
+
+/// The recommendations of a query made with `QueryOptions`
+///
+/// # Fields
+/// * `items` - (Item name, similarity) tuples, fewer than asked for if fewer items are left after the filter and the score floor
+/// * `constraints` - Whether each tag constraint was binding and satisfied
+/// * `explanations` - Why each item was recommended, in the same order as `items`. Empty unless `explain` is on
+#[derive(Debug, Clone)]
+pub struct RankedRecommendations {
+    pub items: Vec<(String, f32)>,
+    pub constraints: Vec<ConstraintStatus>,
+    pub explanations: Vec<Explanation>,
+}
+
+/// Why an item was recommended, for a UI to show
+///
+/// # Fields
+/// * `matched_tags` - The item's tags that were filtered by, boosted or (for item queries) shared with the item the query is based on
+/// * `keywords` - Words the query and the item's summary share, rarest first
+/// * `best_sentence` - The sentence of the item's summary that is most similar to the query, if the summary isn't blank
+/// * `best_sentence_similarity` - How similar that sentence is to the query
+/// * `shared_neighbours` - For item queries, the items that are among the nearest neighbours of both the query item and this item
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Explanation {
+    pub matched_tags: Vec<String>,
+    pub keywords: Vec<String>,
+    pub best_sentence: Option<String>,
+    pub best_sentence_similarity: f32,
+    pub shared_neighbours: Vec<String>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut reasons: Vec<String> = Vec::new();
+        if !self.matched_tags.is_empty() {
+            reasons.push(format!("tagged {}", self.matched_tags.join(", ")));
+        }
+        if !self.keywords.is_empty() {
+            reasons.push(format!("mentions {}", self.keywords.join(", ")));
+        }
+        if let Some(sentence) = &self.best_sentence {
+            reasons.push(format!("\"{}\"", sentence));
+        }
+        if !self.shared_neighbours.is_empty() {
+            reasons.push(format!("also close to {}", self.shared_neighbours.join(", ")));
+        }
+        write!(f, "{}", reasons.join("; "))
+    }
+}
+
+/// How the tags found in a query are used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagUse {
+    /// Every recommendation has to have all of the tags
+    Filter,
+    /// Items with the tags get this added to their score
+    Boost(f32),
+}
+
+/// Options for turning the words of a description into tags
+///
+/// # Fields
+/// * `synonyms` - (phrase, tag) pairs on top of the built in ones, like ("whodunit", "Mystery")
+/// * `use_embeddings` - Also match words to tags whose names have similar embeddings
+/// * `embedding_threshold` - How similar a word and a tag name have to be to match when `use_embeddings` is on
+/// * `tag_use` - Whether the tags found are used as a filter or a boost
+#[derive(Debug, Clone)]
+pub struct AnalyzerOptions {
+    pub synonyms: Vec<(String, String)>,
+    pub use_embeddings: bool,
+    pub embedding_threshold: f32,
+    pub tag_use: TagUse,
+}
+
+impl Default for AnalyzerOptions {
+    fn default() -> Self {
+        AnalyzerOptions {
+            synonyms: Vec::new(),
+            use_embeddings: false,
+            embedding_threshold: 0.8,
+            tag_use: TagUse::Filter,
+        }
+    }
+}
+
+/// How a phrase in a query was matched to a tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagMatchKind {
+    /// The phrase is the tag's name (ignoring case, punctuation and a plural s)
+    Exact,
+    /// The phrase is a synonym of the tag
+    Synonym,
+    /// The phrase's embedding is similar to the tag name's embedding
+    Embedding,
+}
+
+/// A phrase in a query that was matched to a tag
+///
+/// # Fields
+/// * `phrase` - The words in the query
+/// * `tag` - The tag as it is spelled in the model, every spelling separated by a | (most common first) when items spell it differently
+/// * `kind` - How it was matched
+/// * `similarity` - How similar the phrase and the tag are, 1 unless matched by embedding
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagMatch {
+    pub phrase: String,
+    pub tag: String,
+    pub kind: TagMatchKind,
+    pub similarity: f32,
+}
+
+/// How a description was interpreted, so it can be shown to the user
+///
+/// # Fields
+/// * `query` - The description as it was given
+/// * `tags` - The phrases that were turned into tags
+/// * `remaining_text` - What is left of the description once the tags are taken out, which is what gets embedded
+/// * `tag_use` - Whether the tags are used as a filter or a boost
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryInterpretation {
+    pub query: String,
+    pub tags: Vec<TagMatch>,
+    pub remaining_text: String,
+    pub tag_use: TagUse,
+}
+
+impl fmt::Display for QueryInterpretation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tags: Vec<String> = self.tags.iter().map(|tag| format!("{} (\"{}\")", tag.tag, tag.phrase)).collect();
+        let tag_use = match self.tag_use {
+            TagUse::Filter => "Filtering by",
+            TagUse::Boost(_) => "Boosting",
+        };
+        if tags.is_empty() {
+            write!(f, "Searching for \"{}\"", self.remaining_text)
+        } else {
+            write!(f, "{} {} and searching for \"{}\"", tag_use, tags.join(", "), self.remaining_text)
+        }
+    }
+}
+
+/// A tag of the model along with how it is used
+///
+/// # Fields
+/// * `tag` - The tag, spelled the way most items spell it
+/// * `count` - The number of items with the tag
+/// * `count_with_children` - The number of items with the tag or one of its children, which is what filtering by the tag matches
+/// * `children` - The tags under this tag in the hierarchy (including their children), empty without a hierarchy
+/// * `co_occurrences` - (Tag, number of items with both tags) tuples, most common first
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagInfo {
+    pub tag: String,
+    pub count: usize,
+    pub count_with_children: usize,
+    pub children: Vec<String>,
+    pub co_occurrences: Vec<(String, usize)>,
+}
+
+impl fmt::Display for TagInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.children.is_empty() {
+            write!(f, "{} ({} items)", self.tag, self.count)
+        } else {
+            write!(f, "{} ({} items, {} with {})", self.tag, self.count, self.count_with_children, self.children.join(", "))
+        }
+    }
+}
+
+/// Which tags are under which, so that filtering by a parent tag also matches its children.
+/// Read from a JSON object mapping each parent tag to a list of child tags, like
+/// `{"Action": ["Superhero", "Martial Arts"], "Superhero": ["DC"]}`. Tags are compared ignoring case.
+///
+/// # Fields
+/// * `children` - The child tags of each parent tag
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct TagHierarchy {
+    pub children: HashMap<String, Vec<String>>,
+}
+
+/// How tags are suggested for items that don't have any
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AutoTagStrategy {
+    /// Embed each tag name (with its description, if there is one) and suggest the tags whose embeddings are similar enough to the item's
+    TagNames,
+    /// Let the item's nearest tagged neighbours vote for their tags, weighted by how similar they are
+    Neighbours,
+}
+
+/// Options for suggesting tags for items that don't have any
+///
+/// # Fields
+/// * `strategy` - How the tags are suggested
+/// * `threshold` - With `TagNames`, how similar an item and a tag have to be for the tag to be suggested
+/// * `descriptions` - With `TagNames`, descriptions of tags that are embedded along with their names, like ("Noir", "dark crime stories")
+/// * `num_neighbours` - With `Neighbours`, the number of nearest tagged items that vote
+/// * `min_confidence` - With `Neighbours`, the share of the (similarity weighted) votes a tag needs to be suggested
+/// * `max_tags` - The most tags suggested for one item
+#[derive(Debug, Clone)]
+pub struct AutoTagOptions {
+    pub strategy: AutoTagStrategy,
+    pub threshold: f32,
+    pub descriptions: Vec<(String, String)>,
+    pub num_neighbours: usize,
+    pub min_confidence: f32,
+    pub max_tags: usize,
+}
+
+impl Default for AutoTagOptions {
+    fn default() -> Self {
+        AutoTagOptions {
+            strategy: AutoTagStrategy::Neighbours,
+            threshold: 0.25,
+            descriptions: Vec::new(),
+            num_neighbours: 10,
+            min_confidence: 0.4,
+            max_tags: 3,
+        }
+    }
+}
+
+/// A tag suggested for an item
+///
+/// # Fields
+/// * `tag` - The tag, spelled the way most items spell it
+/// * `confidence` - The similarity to the tag with `TagNames` or the share of the votes with `Neighbours`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SuggestedTag {
+    pub tag: String,
+    pub confidence: f32,
+}
+
+/// The tags suggested for one item that didn't have any
+///
+/// # Fields
+/// * `id` - The id of the item
+/// * `name` - The name of the item
+/// * `tags` - The suggested tags, most confident first (empty if no tag was confident enough)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TagSuggestions {
+    pub id: i32,
+    pub name: String,
+    pub tags: Vec<SuggestedTag>,
+}
+
+impl fmt::Display for TagSuggestions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.tags.is_empty() {
+            return write!(f, "{} (id {}): no tags suggested", self.name, self.id);
+        }
+        let tags: Vec<String> = self.tags.iter().map(|tag| format!("{} ({}%)", tag.tag, (tag.confidence * 100.0).round())).collect();
+        write!(f, "{} (id {}): {}", self.name, self.id, tags.join(", "))
+    }
+}
+
+/// Options for auditing the tags of a model against each item's neighbourhood
+///
+/// # Fields
+/// * `num_neighbours` - The number of nearest tagged items each item is compared with
+/// * `add_threshold` - A tag an item doesn't have is suggested to be added when at least this share of the (similarity weighted) neighbours have it
+/// * `remove_threshold` - A tag an item has is suggested to be removed when less than this share of the neighbours have it
+/// * `max_evidence` - The most neighbours listed as evidence for one suggestion
+#[derive(Debug, Clone)]
+pub struct TagAuditOptions {
+    pub num_neighbours: usize,
+    pub add_threshold: f32,
+    pub remove_threshold: f32,
+    pub max_evidence: usize,
+}
+
+impl Default for TagAuditOptions {
+    fn default() -> Self {
+        TagAuditOptions {
+            num_neighbours: 10,
+            add_threshold: 0.7,
+            remove_threshold: 0.1,
+            max_evidence: 5,
+        }
+    }
+}
+
+/// A neighbouring item given as evidence for a suggestion
+///
+/// # Fields
+/// * `id` - The id of the neighbour
+/// * `name` - The name of the neighbour
+/// * `similarity` - How similar the neighbour is to the audited item
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditNeighbour {
+    pub id: i32,
+    pub name: String,
+    pub similarity: f32,
+}
+
+/// A tag suggested to be added to or removed from an item
+///
+/// # Fields
+/// * `tag` - The tag
+/// * `support` - The share of the (similarity weighted) neighbours that have the tag
+/// * `evidence` - The most similar neighbours that have the tag when adding it, or that don't have it when removing it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TagChange {
+    pub tag: String,
+    pub support: f32,
+    pub evidence: Vec<AuditNeighbour>,
+}
+
+/// An item whose tags disagree with its neighbourhood
+///
+/// # Fields
+/// * `id` - The id of the item
+/// * `name` - The name of the item
+/// * `tags` - The item's current tags
+/// * `agreement` - The average support of the item's tags among its neighbours, from 0 (none of them have its tags) to 1
+/// * `add` - Tags the neighbours have that the item doesn't
+/// * `remove` - Tags the item has that the neighbours don't
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TagAuditEntry {
+    pub id: i32,
+    pub name: String,
+    pub tags: Vec<String>,
+    pub agreement: f32,
+    pub add: Vec<TagChange>,
+    pub remove: Vec<TagChange>,
+}
+
+/// The result of auditing the tags of a model
+///
+/// # Fields
+/// * `num_items` - The number of tagged items that were audited
+/// * `entries` - The items with at least one suggested change, least agreement first
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TagAuditReport {
+    pub num_items: usize,
+    pub entries: Vec<TagAuditEntry>,
+}
+
+impl TagAuditReport {
+    /// The report as pretty printed JSON, for curation tools
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for TagAuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} of {} items have tags that disagree with their neighbours", self.entries.len(), self.num_items)?;
+        for entry in &self.entries {
+            writeln!(f, "{} (id {}), {}% agreement", entry.name, entry.id, (entry.agreement * 100.0).round())?;
+            for change in &entry.add {
+                let evidence: Vec<&str> = change.evidence.iter().map(|neighbour| neighbour.name.as_str()).collect();
+                writeln!(f, "    add {} ({}% of neighbours, like {})", change.tag, (change.support * 100.0).round(), evidence.join(", "))?;
+            }
+            for change in &entry.remove {
+                let evidence: Vec<&str> = change.evidence.iter().map(|neighbour| neighbour.name.as_str()).collect();
+                writeln!(f, "    remove {} ({}% of neighbours, none of {})", change.tag, (change.support * 100.0).round(), evidence.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What kept an item out of the recommendations of a query, in the order the rules are applied
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemovalReason {
+    /// The item is the item the query is based on, which is never recommended
+    SelfExclusion,
+    /// The item doesn't have these tags of the tag filter
+    TagFilter { missing_tags: Vec<String> },
+    /// The item would have been recommended without the tag boosts and penalties
+    TagBoosts,
+    /// The item's score, after the boosts, is below the score floor
+    ScoreFloor { min_score: f32 },
+    /// The item would have been recommended without the diversity re-ranking
+    Diversity,
+    /// The item would have been recommended without the tag constraints
+    TagConstraints,
+    /// The item made it through every rule but other items scored higher
+    Cutoff,
+}
+
+impl fmt::Display for RemovalReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemovalReason::SelfExclusion => write!(f, "it is the item the query is based on"),
+            RemovalReason::TagFilter { missing_tags } => write!(f, "it doesn't have the tags {}", missing_tags.join(", ")),
+            RemovalReason::TagBoosts => write!(f, "the tag boosts and penalties pushed it out"),
+            RemovalReason::ScoreFloor { min_score } => write!(f, "its score is below the floor of {}", min_score),
+            RemovalReason::Diversity => write!(f, "the diversity re-ranking pushed it out"),
+            RemovalReason::TagConstraints => write!(f, "the tag constraints pushed it out"),
+            RemovalReason::Cutoff => write!(f, "other items scored higher"),
+        }
+    }
+}
+
+/// Why an item was or wasn't recommended for a query
+///
+/// # Fields
+/// * `id` - The id of the item
+/// * `name` - The name of the item
+/// * `similarity` - The similarity between the query and the item
+/// * `score` - The score the item is ranked by, which is the similarity after the tag boosts
+/// * `rank` - The item's rank (starting at 1) by similarity among every item except the item the query is based on, ignoring every rule
+/// * `final_rank` - The item's position in the recommendations, if it was recommended
+/// * `removed_by` - The first rule that kept the item out of the recommendations, if it wasn't recommended
+/// * `cutoff_score` - The score of the last recommendation, if there were any
+/// * `gap` - How much higher the item's score would have had to be to reach the cut-off, if it wasn't recommended
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhyNotReport {
+    pub id: i32,
+    pub name: String,
+    pub similarity: f32,
+    pub score: f32,
+    pub rank: usize,
+    pub final_rank: Option<usize>,
+    pub removed_by: Option<RemovalReason>,
+    pub cutoff_score: Option<f32>,
+    pub gap: Option<f32>,
+}
+
+impl fmt::Display for WhyNotReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (id {}) has a similarity of {} (score {}) and ranks #{} overall", self.name, self.id, self.similarity, self.score, self.rank)?;
+        if let Some(final_rank) = self.final_rank {
+            return write!(f, ", it was recommended at #{}", final_rank);
+        }
+        if let Some(reason) = &self.removed_by {
+            write!(f, ", it wasn't recommended because {}", reason)?;
+        }
+        match (self.cutoff_score, self.gap) {
+            (Some(cutoff_score), Some(gap)) if gap > 0.0 => write!(f, " (the cut-off score is {}, {} higher)", cutoff_score, gap)?,
+            (Some(cutoff_score), Some(_)) => write!(f, " (its score is above the cut-off score of {})", cutoff_score)?,
+            _ => {},
+        }
+        Ok(())
+    }
+}
+
+/// How the similarities between a query and the chunks of an item's summary are combined into the item's score
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkAggregation {
+    /// The similarity of the best matching chunk
+    Max,
+    /// The average similarity over every chunk
+    Mean,
+    /// The average similarity of the k best matching chunks
+    TopKMean(usize),
+}
+
+/// Options for splitting long summaries into overlapping windows of tokens
+///
+/// # Fields
+/// * `window_tokens` - The number of tokens in each window, None to use as many as the model can take
+/// * `overlap_tokens` - The number of tokens each window shares with the one before it
+/// * `batch_size` - The number of windows embedded in each forward pass
+#[derive(Debug, Clone)]
+pub struct ChunkingOptions {
+    pub window_tokens: Option<usize>,
+    pub overlap_tokens: usize,
+    pub batch_size: usize,
+}
+
+impl Default for ChunkingOptions {
+    fn default() -> Self {
+        ChunkingOptions {
+            window_tokens: None,
+            overlap_tokens: 32,
+            batch_size: 32,
+        }
+    }
+}
+
+/// One window of an item's summary
+///
+/// # Fields
+/// * `text` - The text of the window
+/// * `start_token` - The index of the window's first token in the whole summary
+/// * `end_token` - The index after the window's last token
+/// * `embedding` - The embedding of the window
+#[derive(Debug, Clone)]
+pub struct SummaryChunk {
+    pub text: String,
+    pub start_token: usize,
+    pub end_token: usize,
+    pub embedding: Tensor,
+}
+
+/// The summaries of a model split into overlapping windows, so that long summaries aren't cut off at the model's maximum length
+///
+/// # Fields
+/// * `chunks` - The windows of each item's summary, in order
+/// * `window_tokens` - The number of tokens in each window
+/// * `overlap_tokens` - The number of tokens each window shares with the one before it
+#[derive(Debug, Clone)]
+pub struct ChunkIndex {
+    pub chunks: HashMap<Data, Vec<SummaryChunk>>,
+    pub window_tokens: usize,
+    pub overlap_tokens: usize,
+}
+
+/// A recommendation found through the chunks of the items' summaries
+///
+/// # Fields
+/// * `name` - The name of the item
+/// * `score` - The aggregated similarity over the item's chunks
+/// * `chunk` - The index of the item's best matching chunk
+/// * `chunk_text` - The text of the best matching chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkedRecommendation {
+    pub name: String,
+    pub score: f32,
+    pub chunk: usize,
+    pub chunk_text: String,
+}
+
+impl fmt::Display for ChunkedRecommendation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}% {} (matched chunk {}: \"{}\")", (self.score * 100.0).round(), self.name, self.chunk + 1, self.chunk_text)
+    }
+}
+
+/// The token embeddings of one summary, compressed to one byte per value. Token embeddings are L2 normalized so every
+/// value is between -1 and 1 and is stored as `round(value * 127)`, which takes a quarter of the space of f32.
+///
+/// # Fields
+/// * `num_tokens` - The number of tokens
+/// * `dimensions` - The size of each token embedding
+/// * `values` - The compressed values, one token after another
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenMatrix {
+    pub num_tokens: usize,
+    pub dimensions: usize,
+    pub values: Vec<i8>,
+}
+
+/// The token embeddings of every summary of a model, for re-ranking with late interaction (MaxSim)
+///
+/// # Fields
+/// * `items` - The token embeddings of each item's summary
+#[derive(Debug, Clone, Default)]
+pub struct MultiVectorIndex {
+    pub items: HashMap<Data, TokenMatrix>,
+}
+
+/// A BERT cross-encoder (a sequence classification checkpoint such as ms-marco-MiniLM-L-6-v2) that reads a query and a summary
+/// together and scores how relevant the summary is to the query. Loaded from a local directory with `load_cross_encoder`.
+pub struct CrossEncoder {
+    pub(crate) model: BertModel,
+    pub(crate) pooler: Linear,
+    pub(crate) classifier: Linear,
+    pub(crate) tokenizer: Tokenizer,
+}
+
+impl fmt::Debug for CrossEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CrossEncoder")
+    }
+}
+
+/// How the embeddings saved in a SQLite database were made. Queries have to be embedded the same way for the
+/// saved embeddings to be comparable with them.
+///
+/// # Fields
+/// * `model_id` - The model the embeddings were made with
+/// * `revision` - The revision of the model
+/// * `architecture` - The architecture the model was run as: bert, distil-bert or jina-bert
+/// * `quantization` - The quantization type of the model's weights, or none for f32 weights
+/// * `query_template` - The template queries are embedded with, {} is replaced with the query
+/// * `document_template` - The template summaries were embedded with, {} is replaced with the summary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexMetadata {
+    pub model_id: String,
+    pub revision: String,
+    pub architecture: String,
+    pub quantization: String,
+    pub query_template: String,
+    pub document_template: String,
+}
+
+impl fmt::Display for IndexMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}) as {} with {} quantization, queries embedded as \"{}\" and summaries as \"{}\"",
+            self.model_id, self.revision, self.architecture, self.quantization, self.query_template, self.document_template
+        )
+    }
+}
+
+/// A line of a JSON Lines file that was skipped while creating the model
+///
+/// # Fields
+/// * `line_number` - The line number (starting at 1) of the skipped line
+/// * `error` - Why the line couldn't be deserialized
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedLine {
+    pub line_number: usize,
+    pub error: String,
+}
+
+impl fmt::Display for SkippedLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line_number, self.error)
+    }
+}
+
+/// The architecture of the embedding model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Architecture {
+    /// Picked from the model_type, position_embedding_type and architectures of config.json
+    Auto,
+    /// BERT and the models that share its layout, like MiniLM and BGE
+    Bert,
+    /// DistilBERT
+    DistilBert,
+    /// JinaBERT, with ALiBi instead of position embeddings so it can embed long summaries
+    JinaBert,
+}
+
+impl fmt::Display for Architecture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Architecture::Auto => "auto",
+            Architecture::Bert => "bert",
+            Architecture::DistilBert => "distil-bert",
+            Architecture::JinaBert => "jina-bert",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The quantization type of the weights written by `quantize_model`, from the most to the least accurate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Quantization {
+    /// 8 bits per weight
+    #[value(name = "q8_0")]
+    Q8_0,
+    /// 6 bits per weight in blocks of 256, for models whose hidden size is a multiple of 256
+    #[value(name = "q6k")]
+    Q6K,
+    /// 5 bits per weight
+    #[value(name = "q5_0")]
+    Q5_0,
+    /// 5 bits per weight with an offset per block
+    #[value(name = "q5_1")]
+    Q5_1,
+    /// 4 bits per weight in blocks of 256, for models whose hidden size is a multiple of 256
+    #[value(name = "q4k")]
+    Q4K,
+    /// 4 bits per weight
+    #[value(name = "q4_0")]
+    Q4_0,
+    /// 4 bits per weight with an offset per block
+    #[value(name = "q4_1")]
+    Q4_1,
+}
+
+impl fmt::Display for Quantization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Quantization::Q8_0 => "q8_0",
+            Quantization::Q6K => "q6k",
+            Quantization::Q5_0 => "q5_0",
+            Quantization::Q5_1 => "q5_1",
+            Quantization::Q4K => "q4k",
+            Quantization::Q4_0 => "q4_0",
+            Quantization::Q4_1 => "q4_1",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How close the embeddings of a quantized model are to the f32 ones, from `compare_quantized`
+///
+/// # Fields
+/// * `quantization` - The quantization type of the quantized model
+/// * `num_items` - The number of items in the sample
+/// * `mean_similarity` - The average cosine similarity between an item's f32 and quantized embeddings
+/// * `min_similarity` - The lowest cosine similarity between an item's f32 and quantized embeddings
+/// * `num_neighbours` - The number of nearest neighbours compared for each item
+/// * `neighbour_overlap` - The average share of an item's f32 nearest neighbours that are also its quantized nearest neighbours
+/// * `f32_seconds` - How long the f32 model took to embed the sample
+/// * `quantized_seconds` - How long the quantized model took to embed the sample
+/// * `f32_bytes` - The size of the f32 weights file
+/// * `quantized_bytes` - The size of the quantized weights file
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizationReport {
+    pub quantization: String,
+    pub num_items: usize,
+    pub mean_similarity: f32,
+    pub min_similarity: f32,
+    pub num_neighbours: usize,
+    pub neighbour_overlap: f32,
+    pub f32_seconds: f64,
+    pub quantized_seconds: f64,
+    pub f32_bytes: u64,
+    pub quantized_bytes: u64,
+}
+
+impl QuantizationReport {
+    /// How many times faster the quantized model embedded the sample
+    pub fn speedup(&self) -> f64 {
+        self.f32_seconds / self.quantized_seconds.max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for QuantizationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Compared {} items embedded in f32 and {}", self.num_items, self.quantization)?;
+        writeln!(f, "    cosine similarity: {:.4} on average, {:.4} at worst", self.mean_similarity, self.min_similarity)?;
+        writeln!(f, "    nearest neighbours: {:.1}% of the top {} are the same", self.neighbour_overlap * 100.0, self.num_neighbours)?;
+        writeln!(f, "    time: {:.2}s in f32, {:.2}s quantized ({:.2}x faster)", self.f32_seconds, self.quantized_seconds, self.speedup())?;
+        write!(
+            f,
+            "    size: {:.1} MB in f32, {:.1} MB quantized",
+            self.f32_bytes as f64 / 1_000_000.0,
+            self.quantized_bytes as f64 / 1_000_000.0
+        )
+    }
+}
+
+/// How a validation check is treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Severity {
+    /// The check is not run
+    Off,
+    /// Problems are reported but the catalog still passes
+    Warn,
+    /// Problems are reported and the catalog fails validation
+    Fail,
+}
+
+/// The checks that `validate` runs on a catalog
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationCheck {
+    DuplicateIds,
+    DuplicateNames,
+    ShortSummaries,
+    TruncatedSummaries,
+    TagCasing,
+    MissingTags,
+}
+
+/// The configuration for `validate`, setting how each check is treated
+///
+/// # Fields
+/// * `duplicate_ids` - Items that share an id
+/// * `duplicate_names` - Items that share a name (ignoring case), which makes `pass_item` use the one with the smallest id unless the others are picked with `pass_item_by_id`
+/// * `short_summaries` - Blank summaries or summaries with fewer than `min_summary_words` words
+/// * `min_summary_words` - The number of words a summary needs to not be considered very short
+/// * `truncated_summaries` - Summaries with more tokens than the model keeps
+/// * `tag_casing` - Tags that only differ by casing, like "Sci-Fi" and "sci-fi"
+/// * `missing_tags` - Items without any tags
+#[derive(Debug, Clone)]
+pub struct ValidationConfig {
+    pub duplicate_ids: Severity,
+    pub duplicate_names: Severity,
+    pub short_summaries: Severity,
+    pub min_summary_words: usize,
+    pub truncated_summaries: Severity,
+    pub tag_casing: Severity,
+    pub missing_tags: Severity,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            duplicate_ids: Severity::Fail,
+            duplicate_names: Severity::Warn,
+            short_summaries: Severity::Warn,
+            min_summary_words: 5,
+            truncated_summaries: Severity::Warn,
+            tag_casing: Severity::Warn,
+            missing_tags: Severity::Warn,
+        }
+    }
+}
+
+/// One problem found by `validate`
+///
+/// # Fields
+/// * `check` - The check that found the problem
+/// * `severity` - Whether the problem is a warning or fails validation
+/// * `ids` - The ids of the items involved
+/// * `message` - A description of the problem
+#[derive(Debug, Clone)]
+pub struct ValidationIssue {
+    pub check: ValidationCheck,
+    pub severity: Severity,
+    pub ids: Vec<i32>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = if self.severity == Severity::Fail { "FAIL" } else { "WARN" };
+        write!(f, "[{}] {}", label, self.message)
+    }
+}
+
+/// The result of `validate`
+///
+/// # Fields
+/// * `num_items` - The number of items that were checked
+/// * `issues` - Every problem that was found
+#[derive(Debug, Clone)]
+pub struct ValidationReport {
+    pub num_items: usize,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Whether the catalog passed, meaning no check set to fail found a problem
+    pub fn passed(&self) -> bool {
+        !self.issues.iter().any(|issue| issue.severity == Severity::Fail)
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        let failures = self.issues.iter().filter(|issue| issue.severity == Severity::Fail).count();
+        write!(
+            f,
+            "Checked {} items: {} failures, {} warnings",
+            self.num_items,
+            failures,
+            self.issues.len() - failures
+        )
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub(crate) struct Args {
@@ -49,6 +1178,14 @@ pub(crate) struct Args {
     #[arg(long)]
     revision: Option<String>,
 
+    /// Load the model from a local directory with config.json, tokenizer.json and the weights instead of downloading it
+    #[arg(long)]
+    model_dir: Option<String>,
+
+    /// The architecture of the model, picked from config.json by default
+    #[arg(long, value_enum, default_value = "auto")]
+    architecture: Architecture,
+
     /// When set, compute embeddings for this prompt.
     #[arg(long)]
     pub prompt: Option<String>,
@@ -67,49 +1204,298 @@ pub(crate) struct Args {
 
     /// Use tanh based approximation for Gelu instead of erf implementation.
     #[arg(long, default_value = "true")]
-    approximate_gelu: bool,
+    pub approximate_gelu: bool,
+
+    /// The number of summaries embedded in each forward pass when creating a model
+    #[arg(long, default_value = "32")]
+    pub batch_size: usize,
+
+    /// The number of batches embedded at the same time, the number of cores by default
+    #[arg(long)]
+    workers: Option<usize>,
+
+    /// Embed with the quantized weights in this GGUF file, written by the quantize command, instead of the f32 ones
+    #[arg(long)]
+    quantized: Option<String>,
+
+    /// The template queries are embedded with, {} is replaced with the query (e.g. "query: {}" for E5 models).
+    #[arg(long, default_value = "{}")]
+    pub query_template: String,
+
+    /// The template summaries are embedded with, {} is replaced with the summary (e.g. "passage: {}" for E5 models).
+    #[arg(long, default_value = "{}")]
+    pub document_template: String,
+
+    /// Run a command instead of the interactive prompt.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// The commands that can be run from the command line instead of the interactive prompt
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Check a JSON file for problems before creating a model from it
+    Validate {
+        /// The file path to the JSON file
+        file_path: String,
+
+        /// How to treat items that share an id
+        #[arg(long, value_enum, default_value = "fail")]
+        duplicate_ids: Severity,
+
+        /// How to treat items that share a name
+        #[arg(long, value_enum, default_value = "warn")]
+        duplicate_names: Severity,
+
+        /// How to treat blank or very short summaries
+        #[arg(long, value_enum, default_value = "warn")]
+        short_summaries: Severity,
+
+        /// Summaries with fewer words than this are considered very short
+        #[arg(long, default_value = "5")]
+        min_summary_words: usize,
+
+        /// How to treat summaries that are too long for the tokenizer and will be truncated
+        #[arg(long, value_enum, default_value = "warn")]
+        truncated_summaries: Severity,
+
+        /// How to treat tags that only differ by casing, like "Sci-Fi" and "sci-fi"
+        #[arg(long, value_enum, default_value = "warn")]
+        tag_casing: Severity,
+
+        /// How to treat items without tags
+        #[arg(long, value_enum, default_value = "warn")]
+        missing_tags: Severity,
+    },
+    /// List the tags of a JSON file with how many items have each one
+    Tags {
+        /// The file path to the JSON file
+        file_path: String,
+
+        /// A JSON file mapping parent tags to their child tags
+        #[arg(long)]
+        hierarchy: Option<String>,
+
+        /// The number of tags that most often appear alongside each tag to show
+        #[arg(long, default_value = "3")]
+        co_occurrences: usize,
+    },
+    /// Suggest tags for the items of a JSON file that don't have any
+    AutoTag {
+        /// The file path to the JSON file
+        file_path: String,
+
+        /// How the tags are suggested
+        #[arg(long, value_enum, default_value = "neighbours")]
+        strategy: AutoTagStrategy,
+
+        /// With tag-names, how similar an item and a tag have to be for the tag to be suggested
+        #[arg(long, default_value = "0.25")]
+        threshold: f32,
+
+        /// With tag-names, a JSON file mapping tags to descriptions that are embedded along with their names
+        #[arg(long)]
+        tag_descriptions: Option<String>,
+
+        /// With neighbours, the number of nearest tagged items that vote
+        #[arg(long, default_value = "10")]
+        num_neighbours: usize,
+
+        /// With neighbours, the share of the votes a tag needs to be suggested
+        #[arg(long, default_value = "0.4")]
+        min_confidence: f32,
+
+        /// The most tags suggested for one item
+        #[arg(long, default_value = "3")]
+        max_tags: usize,
+
+        /// Write the catalog with the suggested tags filled in to this file
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// Find items whose tags disagree with the tags of their nearest neighbours
+    AuditTags {
+        /// The file path to the JSON file
+        file_path: String,
+
+        /// The number of nearest tagged items each item is compared with
+        #[arg(long, default_value = "10")]
+        num_neighbours: usize,
+
+        /// Suggest adding a tag when at least this share of the neighbours have it
+        #[arg(long, default_value = "0.7")]
+        add_threshold: f32,
+
+        /// Suggest removing a tag when less than this share of the neighbours have it
+        #[arg(long, default_value = "0.1")]
+        remove_threshold: f32,
+
+        /// The most neighbours listed as evidence for one suggestion
+        #[arg(long, default_value = "5")]
+        max_evidence: usize,
+
+        /// Write the report as JSON to this file instead of printing it
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// Quantize the weights of the model into a GGUF file that can be used with --quantized
+    Quantize {
+        /// The file path to write the GGUF file to
+        output: String,
+
+        /// The quantization type
+        #[arg(long, value_enum, default_value = "q8_0")]
+        quantization: Quantization,
+    },
+    /// Compare the embeddings of a quantized model with the f32 ones on a sample of a JSON file
+    CompareQuantized {
+        /// The file path to the JSON file
+        file_path: String,
+
+        /// The file path to the GGUF file written by quantize
+        quantized: String,
+
+        /// The number of items compared, taken from the start of the file
+        #[arg(long, default_value = "200")]
+        sample_size: usize,
+
+        /// The number of nearest neighbours compared for each item
+        #[arg(long, default_value = "10")]
+        num_neighbours: usize,
+    },
 }
 
 impl Args {
-    pub(crate) fn build_model_and_tokenizer(&self) -> OtherResult<(BertModel, Tokenizer)> {
-        let device = Device::Cpu;
+    /// The model id and revision the embeddings are made with
+    pub(crate) fn model_and_revision(&self) -> (String, String) {
+        if let Some(model_dir) = &self.model_dir {
+            return (model_dir.clone(), "local".to_string());
+        }
         let default_model = "sentence-transformers/all-MiniLM-L6-v2".to_string();
         let default_revision = "refs/pr/21".to_string();
-        let (model_id, revision) = match (self.model_id.to_owned(), self.revision.to_owned()) {
+        match (self.model_id.to_owned(), self.revision.to_owned()) {
             (Some(model_id), Some(revision)) => (model_id, revision),
             (Some(model_id), None) => (model_id, "main".to_string()),
             (None, Some(revision)) => (default_model, revision),
             (None, None) => (default_model, default_revision),
-        };
+        }
+    }
+
+    fn repo(&self) -> Repo {
+        let (model_id, revision) = self.model_and_revision();
+        Repo::with_revision(model_id, RepoType::Model, revision)
+    }
+
+    /// The text a query is embedded as, with the query template applied
+    pub(crate) fn query_text(&self, query: &str) -> String {
+        apply_template(&self.query_template, query)
+    }
 
-        let repo = Repo::with_revision(model_id, RepoType::Model, revision);
-        let (config_filename, tokenizer_filename, weights_filename) = {
-            let api = Api::new()?;
-            let api = api.repo(repo);
-            let config = api.get("config.json")?;
-            let tokenizer = api.get("tokenizer.json")?;
-            let weights = if self.use_pth {
-                api.get("pytorch_model.bin")?
-            } else {
-                api.get("model.safetensors")?
-            };
-            (config, tokenizer, weights)
+    /// The text a summary is embedded as, with the document template applied
+    pub(crate) fn document_text(&self, summary: &str) -> String {
+        apply_template(&self.document_template, summary)
+    }
+
+    /// How embeddings made with these arguments are made, to be saved with them. The architecture is read from
+    /// config.json when it's picked automatically and the quantization from the GGUF file of a quantized model.
+    pub(crate) fn index_metadata(&self) -> OtherResult<IndexMetadata> {
+        let (model_id, revision) = self.model_and_revision();
+        let architecture = match (&self.quantized, self.architecture) {
+            (Some(_), _) => Architecture::Bert,
+            (None, Architecture::Auto) => detect_architecture(&serde_json::from_str(&std::fs::read_to_string(self.model_file("config.json")?)?)?),
+            (None, architecture) => architecture,
+        };
+        let quantization = match &self.quantized {
+            Some(path) => read_quantization(path)?,
+            None => "none".to_string(),
         };
-        let config = std::fs::read_to_string(config_filename)?;
-        let mut config: Config = serde_json::from_str(&config)?;
-        let tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
+        Ok(IndexMetadata {
+            model_id,
+            revision,
+            architecture: architecture.to_string(),
+            quantization,
+            query_template: self.query_template.clone(),
+            document_template: self.document_template.clone(),
+        })
+    }
+
+    /// The path of one of the model's files, from the local model directory or otherwise downloaded from the hub
+    pub(crate) fn model_file(&self, name: &str) -> OtherResult<PathBuf> {
+        match &self.model_dir {
+            Some(model_dir) => {
+                let path = Path::new(model_dir).join(name);
+                match path.exists() {
+                    true => Ok(path),
+                    false => Err(E::msg(format!("{} has no {}", model_dir, name))),
+                }
+            },
+            None => Ok(Api::new()?.repo(self.repo()).get(name)?),
+        }
+    }
+
+    /// The number of batches embedded at the same time
+    pub(crate) fn workers(&self) -> usize {
+        match self.workers {
+            Some(workers) => workers.max(1),
+            None => std::thread::available_parallelism().map_or(1, |x| x.get()),
+        }
+    }
+
+    /// The path of the f32 weights of the model
+    pub(crate) fn weights_file(&self) -> OtherResult<PathBuf> {
+        match self.use_pth {
+            true => self.model_file("pytorch_model.bin"),
+            false => self.model_file("model.safetensors"),
+        }
+    }
+
+    pub(crate) fn build_model_and_tokenizer(&self) -> OtherResult<(Encoder, Tokenizer)> {
+        self.build_model_and_tokenizer_with(self.quantized.as_deref())
+    }
+
+    /// The same as `build_model_and_tokenizer` but with the quantized weights in `quantized`, or the f32 weights if it's NONE
+    pub(crate) fn build_model_and_tokenizer_with(&self, quantized: Option<&str>) -> OtherResult<(Encoder, Tokenizer)> {
+        let device = Device::Cpu;
+        let tokenizer = Tokenizer::from_file(self.model_file("tokenizer.json")?).map_err(E::msg)?;
+        if let Some(quantized) = quantized {
+            return Ok((Encoder::QuantizedBert(load_quantized_bert(quantized, self.approximate_gelu)?.0), tokenizer));
+        }
+        let config = std::fs::read_to_string(self.model_file("config.json")?)?;
 
         let vb = if self.use_pth {
-            VarBuilder::from_pth(&weights_filename, DTYPE, &device)?
+            VarBuilder::from_pth(self.weights_file()?, DTYPE, &device)?
         } else {
-            unsafe { VarBuilder::from_mmaped_safetensors(&[weights_filename], DTYPE, &device)? }
+            unsafe { VarBuilder::from_mmaped_safetensors(&[self.weights_file()?], DTYPE, &device)? }
         };
-        if self.approximate_gelu {
-            config.hidden_act = HiddenAct::GeluApproximate;
-        }
-        let model = BertModel::load(vb, &config)?;
+        let model = load_encoder(&config, vb, self.architecture, self.approximate_gelu)?;
         Ok((model, tokenizer))
     }
+
+    /// Loads only the tokenizer of the model along with the maximum number of tokens it keeps,
+    /// which is the tokenizer's truncation length or otherwise the model's maximum position embeddings
+    pub(crate) fn build_tokenizer(&self) -> OtherResult<(Tokenizer, usize)> {
+        let tokenizer = Tokenizer::from_file(self.model_file("tokenizer.json")?).map_err(E::msg)?;
+        let max_length = self.max_length(&tokenizer)?;
+        Ok((tokenizer, max_length))
+    }
+
+    /// The maximum number of tokens `tokenizer` (the model's tokenizer) keeps, see `build_tokenizer`
+    pub(crate) fn max_length(&self, tokenizer: &Tokenizer) -> OtherResult<usize> {
+        if let Some(max_length) = tokenizer.get_truncation().map(|truncation| truncation.max_length) {
+            return Ok(max_length);
+        }
+        let config = std::fs::read_to_string(self.model_file("config.json")?)?;
+        let config: serde_json::Value = serde_json::from_str(&config)?;
+        Ok(config["max_position_embeddings"].as_u64().unwrap_or(512) as usize)
+    }
+}
+
+/// Replaces the first {} in the template with the text, or puts the template in front of the text if it has no {}
+pub(crate) fn apply_template(template: &str, text: &str) -> String {
+    match template.contains("{}") {
+        true => template.replacen("{}", text, 1),
+        false => format!("{}{}", template, text),
+    }
 }
 
 pub(crate) struct Recommendations {
@@ -118,19 +1504,17 @@ pub(crate) struct Recommendations {
 }
 
 impl Recommendations {
+    /// Starts with `size` placeholder entries, none when `size` is 0
     pub(crate) fn new(size: usize) -> Recommendations {
-        if size <= 0 {
-            panic!("Size must be greater than 0");
-        }
         let mut temp = Vec::new();
         for _ in 0..size {
             temp.push((String::from("Couldn't find any more recommendations"), 0.0));
         }
-        Recommendations { size: size,  items: temp }
+        Recommendations { size,  items: temp }
     }
 
     pub(crate) fn insert_or_skip(&mut self, item: String, score: f32) {
-        if score < self.items[self.size - 1].1 {
+        if self.size == 0 || score < self.items[self.size - 1].1 {
             return;
         }
         self.items.remove(self.size - 1);
@@ -141,7 +1525,7 @@ impl Recommendations {
     pub(crate) fn get_recommendations(&self) -> Vec<(String, f32)> {
         let mut temp = Vec::new();
         for (item, sim_score) in &self.items {
-            temp.push((item.clone(), sim_score.clone()));
+            temp.push((item.clone(), *sim_score));
         }
         temp
     }
@@ -1,6 +1,221 @@
-use anyhow::Result;
-use candle::Tensor;
+use anyhow::{Error as E, Result};
+use candle::{Device, Tensor};
+use super::encoder::Encoder;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokenizers::{Encoding, Tokenizer};
 
 pub(crate) fn normalize_l2(v: &Tensor) -> Result<Tensor> {
     Ok(v.broadcast_div(&v.sqr()?.sum_keepdim(1)?.sqrt()?)?)
-}
\ No newline at end of file
+}
+
+/// Receives a batch of texts and runs them through the model in a single forward pass.
+/// The tokenizer is expected to already be set up to pad to the longest text in the batch.
+///
+/// @param `model` - the model used to create the embeddings
+/// @param `tokenizer` - the tokenizer that belongs to the model
+/// @param `texts` - the texts to embed
+///
+/// @return `Ok()` with a (number of texts, hidden size) Tensor of mean pooled, L2 normalized embeddings [OR] `Err()`
+pub(crate) fn embed_batch(model: &Encoder, tokenizer: &Tokenizer, texts: Vec<&str>) -> Result<Tensor> {
+    let (embeddings, attention_mask) = run_batch(model, tokenizer, texts)?;
+    normalize_l2(&mean_pool(&embeddings, &attention_mask)?)
+}
+
+/// Tokenizes a batch of texts and runs it through the model
+///
+/// @return `Ok()` with the (number of texts, number of tokens, hidden size) token embeddings and the
+/// (number of texts, number of tokens) attention mask, 1 for real tokens and 0 for padding [OR] `Err()`
+pub(crate) fn run_batch(model: &Encoder, tokenizer: &Tokenizer, texts: Vec<&str>) -> Result<(Tensor, Tensor)> {
+    let device = model.device();
+
+    // Tokenize the data
+    let tokens = tokenizer
+        .encode_batch(texts, true)
+        .map_err(E::msg)?;
+
+    // Convert the tokens to tensors
+    let token_ids = tokens
+        .iter()
+        .map(|tokens| Ok(Tensor::new(tokens.get_ids(), device)?))
+        .collect::<Result<Vec<_>>>()?;
+    let token_ids = Tensor::stack(&token_ids, 0)?;
+    let attention_mask = attention_mask(&tokens, device)?;
+
+    // Get the embeddings
+    let embeddings = model.forward(&token_ids, &attention_mask)?;
+    Ok((embeddings, attention_mask))
+}
+
+/// Averages the token embeddings of each text over its real tokens, leaving the padding out, so that a text's
+/// embedding doesn't depend on how long the other texts in its batch are
+///
+/// @param `embeddings` - the (number of texts, number of tokens, hidden size) token embeddings
+/// @param `attention_mask` - the (number of texts, number of tokens) attention mask
+///
+/// @return `Ok()` with the (number of texts, hidden size) mean pooled embeddings [OR] `Err()`
+pub(crate) fn mean_pool(embeddings: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+    let mask = attention_mask.to_dtype(embeddings.dtype())?.unsqueeze(2)?;
+    let sum = embeddings.broadcast_mul(&mask)?.sum(1)?;
+    let count = mask.sum(1)?.clamp(1.0, f64::MAX)?;
+    Ok(sum.broadcast_div(&count)?)
+}
+
+/// Receives texts and embeds them in batches of `batch_size` spread over `workers` threads. Each worker tokenizes and runs
+/// the batches it takes, and the embeddings are put back in the order of `texts`, so the result doesn't depend on which
+/// worker ran which batch.
+///
+/// @param `model` - the model used to create the embeddings
+/// @param `tokenizer` - the tokenizer that belongs to the model, set up to pad to the longest text in a batch
+/// @param `texts` - the texts to embed
+/// @param `batch_size` - the number of texts in each forward pass
+/// @param `workers` - the number of batches embedded at the same time
+///
+/// @return `Ok()` with one embedding per text, in the same order [OR] `Err()` if a batch couldn't be embedded
+pub(crate) fn embed_parallel(model: &Encoder, tokenizer: &Tokenizer, texts: &[&str], batch_size: usize, workers: usize) -> Result<Vec<Tensor>> {
+    let batches: Vec<&[&str]> = texts.chunks(batch_size.max(1)).collect();
+    let next_batch = AtomicUsize::new(0);
+    let run_worker = || -> Result<Vec<(usize, Tensor)>> {
+        let mut embedded: Vec<(usize, Tensor)> = Vec::new();
+        loop {
+            let i = next_batch.fetch_add(1, Ordering::Relaxed);
+            match batches.get(i) {
+                Some(batch) => embedded.push((i, embed_batch(model, tokenizer, batch.to_vec())?)),
+                None => return Ok(embedded),
+            }
+        }
+    };
+
+    let workers = workers.clamp(1, batches.len().max(1));
+    let mut embedded: Vec<(usize, Tensor)> = match workers {
+        1 => run_worker()?,
+        _ => std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers).map(|_| scope.spawn(run_worker)).collect();
+            let mut embedded: Vec<(usize, Tensor)> = Vec::with_capacity(batches.len());
+            for handle in handles {
+                embedded.extend(handle.join().map_err(|_| E::msg("An embedding worker panicked"))??);
+            }
+            Ok::<_, E>(embedded)
+        })?,
+    };
+    embedded.sort_by_key(|(i, _)| *i);
+
+    let mut embeddings: Vec<Tensor> = Vec::with_capacity(texts.len());
+    for (_, batch_embeddings) in embedded {
+        for j in 0..batch_embeddings.dim(0)? {
+            embeddings.push(batch_embeddings.get(j)?);
+        }
+    }
+    Ok(embeddings)
+}
+
+/// Receives a batch of texts and runs them through the model, keeping the embedding of every token instead of pooling them.
+/// Padding tokens are left out and every token embedding is L2 normalized.
+///
+/// @param `model` - the model used to create the embeddings
+/// @param `tokenizer` - the tokenizer that belongs to the model
+/// @param `texts` - the texts to embed
+///
+/// @return `Ok()` with the token embeddings of each text [OR] `Err()`
+pub(crate) fn embed_tokens(model: &Encoder, tokenizer: &Tokenizer, texts: Vec<&str>) -> Result<Vec<Vec<Vec<f32>>>> {
+    let (embeddings, attention_mask) = run_batch(model, tokenizer, texts)?;
+    split_tokens(&embeddings, &attention_mask)
+}
+
+/// Splits the output of `run_batch` into the L2 normalized embeddings of the real tokens of each text
+pub(crate) fn split_tokens(embeddings: &Tensor, attention_mask: &Tensor) -> Result<Vec<Vec<Vec<f32>>>> {
+    let masks = attention_mask.to_vec2::<u32>()?;
+    let mut texts_tokens: Vec<Vec<Vec<f32>>> = Vec::with_capacity(masks.len());
+    for (i, mask) in masks.iter().enumerate() {
+        let rows = embeddings.get(i)?.to_vec2::<f32>()?;
+        let text_tokens: Vec<Vec<f32>> = rows
+            .into_iter()
+            .zip(mask)
+            .filter(|(_, mask)| **mask == 1)
+            .map(|(row, _)| {
+                let norm = row.iter().map(|x| x * x).sum::<f32>().sqrt().max(f32::EPSILON);
+                row.iter().map(|x| x / norm).collect()
+            })
+            .collect();
+        texts_tokens.push(text_tokens);
+    }
+    Ok(texts_tokens)
+}
+
+/// The (number of texts, number of tokens) attention masks of a batch of encodings
+fn attention_mask(tokens: &[Encoding], device: &Device) -> Result<Tensor> {
+    let masks = tokens
+        .iter()
+        .map(|tokens| Ok(Tensor::new(tokens.get_attention_mask(), device)?))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Tensor::stack(&masks, 0)?)
+}
+
+/// Cosine similarity between two embeddings that have already been copied out of their Tensors
+pub(crate) fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let a_dot_b: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+    let a_dot_a: f32 = a.iter().map(|x| x * x).sum();
+    let b_dot_b: f32 = b.iter().map(|x| x * x).sum();
+    a_dot_b / (a_dot_a * b_dot_b).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::bert::BertModel;
+    use candle::DType;
+    use candle_nn::{VarBuilder, VarMap};
+
+    const CONFIG: &str = r#"{"model_type": "bert", "vocab_size": 16, "hidden_size": 32, "num_hidden_layers": 2, "num_attention_heads": 4,
+        "intermediate_size": 64, "hidden_act": "gelu", "max_position_embeddings": 32, "type_vocab_size": 2, "layer_norm_eps": 1e-12}"#;
+
+    const TOKENIZER: &str = r#"{"version": "1.0", "truncation": null,
+        "padding": {"strategy": "BatchLongest", "direction": "Right", "pad_to_multiple_of": null, "pad_id": 0, "pad_type_id": 0, "pad_token": "[PAD]"},
+        "added_tokens": [], "normalizer": null, "pre_tokenizer": {"type": "Whitespace"},
+        "post_processor": {"type": "TemplateProcessing",
+            "single": [{"SpecialToken": {"id": "[CLS]", "type_id": 0}}, {"Sequence": {"id": "A", "type_id": 0}}, {"SpecialToken": {"id": "[SEP]", "type_id": 0}}],
+            "pair": [{"Sequence": {"id": "A", "type_id": 0}}, {"Sequence": {"id": "B", "type_id": 1}}],
+            "special_tokens": {"[CLS]": {"id": "[CLS]", "ids": [2], "tokens": ["[CLS]"]}, "[SEP]": {"id": "[SEP]", "ids": [3], "tokens": ["[SEP]"]}}},
+        "decoder": null,
+        "model": {"type": "WordLevel", "unk_token": "[UNK]", "vocab": {"[PAD]": 0, "[UNK]": 1, "[CLS]": 2, "[SEP]": 3, "a": 4, "space": 5, "hero": 6,
+            "fights": 7, "the": 8, "empire": 9, "love": 10, "story": 11, "magic": 12, "dragon": 13, "quest": 14, "robot": 15}}}"#;
+
+    /// A randomly initialized BERT model and its tokenizer
+    fn tiny_model() -> (Encoder, Tokenizer) {
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        let model = BertModel::load(CONFIG, vb, false).unwrap();
+        let tokenizer = TOKENIZER.parse::<Tokenizer>().unwrap();
+        (Encoder::Bert(model), tokenizer)
+    }
+
+    fn embed(model: &Encoder, tokenizer: &Tokenizer, texts: &[&str], batch_size: usize, workers: usize) -> Vec<Vec<f32>> {
+        embed_parallel(model, tokenizer, texts, batch_size, workers)
+            .unwrap()
+            .iter()
+            .map(|embedding| embedding.to_vec1::<f32>().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn embeddings_do_not_depend_on_workers_or_batch_size() {
+        let (model, tokenizer) = tiny_model();
+        let texts = [
+            "a hero",
+            "the space hero fights the empire",
+            "love story",
+            "a magic dragon quest with a robot",
+            "robot",
+            "the dragon fights a hero in space",
+            "magic love story",
+        ];
+        let expected = embed(&model, &tokenizer, &texts, 1, 1);
+        for (batch_size, workers) in [(1, 3), (3, 1), (3, 2), (4, 4), (7, 1), (32, 2)] {
+            let embeddings = embed(&model, &tokenizer, &texts, batch_size, workers);
+            assert_eq!(embeddings.len(), expected.len());
+            for (text, (a, b)) in texts.iter().zip(expected.iter().zip(embeddings.iter())) {
+                let difference = a.iter().zip(b).map(|(x, y)| (x - y).abs()).fold(0.0, f32::max);
+                assert!(difference < 1e-5, "{:?} differs by {} with batch size {} and {} workers", text, difference, batch_size, workers);
+            }
+        }
+    }
+}
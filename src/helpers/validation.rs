@@ -0,0 +1,215 @@
+use super::types::{apply_template, Data, Severity, ValidationCheck, ValidationConfig, ValidationIssue, ValidationReport};
+use anyhow::{Error as E, Result};
+use std::collections::BTreeMap;
+use tokenizers::Tokenizer;
+
+/// Receives the items of a catalog and runs every check that isn't turned off in `config`.
+/// The tokenizer is only needed for the truncated summaries check and that check is skipped without one.
+///
+/// @param `items` - the items of the catalog
+/// @param `config` - how each check is treated
+/// @param `tokenizer` - the tokenizer of the model, the maximum number of tokens it keeps and the document template summaries are embedded with
+///
+/// @return `Ok()` with the report [OR] `Err()` if the summaries couldn't be tokenized
+pub(crate) fn validate_items(items: &[Data], config: &ValidationConfig, tokenizer: Option<(&Tokenizer, usize, &str)>) -> Result<ValidationReport> {
+    let mut issues: Vec<ValidationIssue> = Vec::new();
+
+    if config.duplicate_ids != Severity::Off {
+        let mut by_id: BTreeMap<i32, usize> = BTreeMap::new();
+        for item in items {
+            *by_id.entry(item.id).or_insert(0) += 1;
+        }
+        for (id, count) in by_id.into_iter().filter(|(_, count)| *count > 1) {
+            issues.push(ValidationIssue {
+                check: ValidationCheck::DuplicateIds,
+                severity: config.duplicate_ids,
+                ids: vec![id],
+                message: format!("Id {} is used by {} items", id, count),
+            });
+        }
+    }
+
+    if config.duplicate_names != Severity::Off {
+        let mut by_name: BTreeMap<String, Vec<i32>> = BTreeMap::new();
+        for item in items {
+            by_name.entry(item.name.trim().to_lowercase()).or_default().push(item.id);
+        }
+        for (name, ids) in by_name.into_iter().filter(|(_, ids)| ids.len() > 1) {
+            issues.push(ValidationIssue {
+                check: ValidationCheck::DuplicateNames,
+                severity: config.duplicate_names,
+                message: format!("Name \"{}\" is used by {} items (ids {:?})", name, ids.len(), ids),
+                ids,
+            });
+        }
+    }
+
+    if config.short_summaries != Severity::Off {
+        for item in items {
+            let num_words = item.summary.split_whitespace().count();
+            if num_words == 0 {
+                issues.push(ValidationIssue {
+                    check: ValidationCheck::ShortSummaries,
+                    severity: config.short_summaries,
+                    ids: vec![item.id],
+                    message: format!("\"{}\" (id {}) has a blank summary", item.name, item.id),
+                });
+            } else if num_words < config.min_summary_words {
+                issues.push(ValidationIssue {
+                    check: ValidationCheck::ShortSummaries,
+                    severity: config.short_summaries,
+                    ids: vec![item.id],
+                    message: format!("\"{}\" (id {}) has a very short summary ({} words)", item.name, item.id, num_words),
+                });
+            }
+        }
+    }
+
+    if config.truncated_summaries != Severity::Off {
+        if let Some((tokenizer, max_length, document_template)) = tokenizer {
+            // Truncation has to be turned off to know how many tokens the summaries really have
+            let mut tokenizer = tokenizer.clone();
+            tokenizer.with_truncation(None).map_err(E::msg)?;
+            tokenizer.with_padding(None);
+            // Summaries are counted with the document template, the way they are embedded
+            let summaries: Vec<String> = items.iter().map(|item| apply_template(document_template, &item.summary)).collect();
+            let tokens = tokenizer.encode_batch(summaries, true).map_err(E::msg)?;
+            for (item, tokens) in items.iter().zip(tokens.iter()) {
+                let num_tokens = tokens.get_ids().len();
+                if num_tokens > max_length {
+                    issues.push(ValidationIssue {
+                        check: ValidationCheck::TruncatedSummaries,
+                        severity: config.truncated_summaries,
+                        ids: vec![item.id],
+                        message: format!(
+                            "\"{}\" (id {}) has a summary of {} tokens which will be truncated to {}",
+                            item.name, item.id, num_tokens, max_length
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    if config.tag_casing != Severity::Off {
+        // Lowercased tag -> spelling -> ids of the items using that spelling
+        let mut by_tag: BTreeMap<String, BTreeMap<String, Vec<i32>>> = BTreeMap::new();
+        for item in items {
+            for tag in &item.tags {
+                by_tag
+                    .entry(tag.trim().to_lowercase())
+                    .or_default()
+                    .entry(tag.clone())
+                    .or_default()
+                    .push(item.id);
+            }
+        }
+        for (_, spellings) in by_tag.into_iter().filter(|(_, spellings)| spellings.len() > 1) {
+            let variants: Vec<String> = spellings
+                .iter()
+                .map(|(spelling, ids)| format!("\"{}\" ({} items)", spelling, ids.len()))
+                .collect();
+            let mut ids: Vec<i32> = spellings.into_values().flatten().collect();
+            ids.sort();
+            ids.dedup();
+            issues.push(ValidationIssue {
+                check: ValidationCheck::TagCasing,
+                severity: config.tag_casing,
+                ids,
+                message: format!("Tag has casing variants: {}", variants.join(", ")),
+            });
+        }
+    }
+
+    if config.missing_tags != Severity::Off {
+        for item in items.iter().filter(|item| item.tags.iter().all(|tag| tag.trim().is_empty())) {
+            issues.push(ValidationIssue {
+                check: ValidationCheck::MissingTags,
+                severity: config.missing_tags,
+                ids: vec![item.id],
+                message: format!("\"{}\" (id {}) has no tags", item.name, item.id),
+            });
+        }
+    }
+
+    Ok(ValidationReport { num_items: items.len(), issues })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKENIZER: &str = r#"{"version": "1.0", "truncation": null, "padding": null, "added_tokens": [], "normalizer": null,
+        "pre_tokenizer": {"type": "Whitespace"},
+        "post_processor": {"type": "TemplateProcessing",
+            "single": [{"SpecialToken": {"id": "[CLS]", "type_id": 0}}, {"Sequence": {"id": "A", "type_id": 0}}, {"SpecialToken": {"id": "[SEP]", "type_id": 0}}],
+            "pair": [{"Sequence": {"id": "A", "type_id": 0}}, {"Sequence": {"id": "B", "type_id": 1}}],
+            "special_tokens": {"[CLS]": {"id": "[CLS]", "ids": [1], "tokens": ["[CLS]"]}, "[SEP]": {"id": "[SEP]", "ids": [2], "tokens": ["[SEP]"]}}},
+        "decoder": null, "model": {"type": "WordLevel", "unk_token": "[UNK]", "vocab": {"[UNK]": 0, "[CLS]": 1, "[SEP]": 2}}}"#;
+
+    fn item(id: i32, name: &str, summary: &str, tags: &[&str]) -> Data {
+        Data { id, name: name.to_string(), summary: summary.to_string(), tags: tags.iter().map(|tag| tag.to_string()).collect() }
+    }
+
+    /// Only `check` is on, as a warning
+    fn only(check: ValidationCheck) -> ValidationConfig {
+        let severity = |other: ValidationCheck| if other == check { Severity::Warn } else { Severity::Off };
+        ValidationConfig {
+            duplicate_ids: severity(ValidationCheck::DuplicateIds),
+            duplicate_names: severity(ValidationCheck::DuplicateNames),
+            short_summaries: severity(ValidationCheck::ShortSummaries),
+            min_summary_words: 3,
+            truncated_summaries: severity(ValidationCheck::TruncatedSummaries),
+            tag_casing: severity(ValidationCheck::TagCasing),
+            missing_tags: severity(ValidationCheck::MissingTags),
+        }
+    }
+
+    fn off() -> ValidationConfig {
+        ValidationConfig {
+            duplicate_ids: Severity::Off,
+            duplicate_names: Severity::Off,
+            short_summaries: Severity::Off,
+            truncated_summaries: Severity::Off,
+            tag_casing: Severity::Off,
+            missing_tags: Severity::Off,
+            ..ValidationConfig::default()
+        }
+    }
+
+    #[test]
+    fn each_check_fires_and_is_suppressed_when_off() {
+        let tokenizer = TOKENIZER.parse::<Tokenizer>().unwrap();
+        // (check, catalog, ids of the issues)
+        let cases: Vec<(ValidationCheck, Vec<Data>, Vec<Vec<i32>>)> = vec![
+            (ValidationCheck::DuplicateIds, vec![item(1, "Heat", "a b c", &["Crime"]), item(1, "Up", "a b c", &["Family"])], vec![vec![1]]),
+            (ValidationCheck::DuplicateNames, vec![item(1, "Heat", "a b c", &["Crime"]), item(2, " heat", "a b c", &["Crime"])], vec![vec![1, 2]]),
+            (ValidationCheck::ShortSummaries, vec![item(1, "Heat", "  ", &["Crime"]), item(2, "Up", "a b", &["Family"]), item(3, "Big", "a b c", &["Comedy"])], vec![vec![1], vec![2]]),
+            (ValidationCheck::TruncatedSummaries, vec![item(1, "Heat", "a b c d e f", &["Crime"]), item(2, "Up", "a b", &["Family"])], vec![vec![1]]),
+            (ValidationCheck::TagCasing, vec![item(1, "Heat", "a b c", &["Sci-Fi"]), item(2, "Up", "a b c", &["sci-fi"]), item(3, "Big", "a b c", &["SCI-FI", "Drama"])], vec![vec![1, 2, 3]]),
+            (ValidationCheck::MissingTags, vec![item(1, "Heat", "a b c", &[]), item(2, "Up", "a b c", &[" "]), item(3, "Big", "a b c", &["Comedy"])], vec![vec![1], vec![2]]),
+        ];
+        for (check, items, expected) in cases {
+            let report = validate_items(&items, &only(check), Some((&tokenizer, 6, ""))).unwrap();
+            assert!(report.issues.iter().all(|issue| issue.check == check && issue.severity == Severity::Warn), "{:?}", check);
+            let ids: Vec<Vec<i32>> = report.issues.iter().map(|issue| issue.ids.clone()).collect();
+            assert_eq!(ids, expected, "{:?}", check);
+
+            let report = validate_items(&items, &off(), Some((&tokenizer, 6, ""))).unwrap();
+            assert!(report.issues.is_empty(), "{:?} wasn't turned off", check);
+        }
+    }
+
+    #[test]
+    fn summaries_are_counted_with_the_document_template() {
+        let tokenizer = TOKENIZER.parse::<Tokenizer>().unwrap();
+        let items = vec![item(1, "Heat", "a b c d", &["Crime"])];
+        let config = only(ValidationCheck::TruncatedSummaries);
+
+        // [CLS] a b c d [SEP] fits in 6 tokens, "passage: " adds two more
+        assert!(validate_items(&items, &config, Some((&tokenizer, 6, ""))).unwrap().issues.is_empty());
+        let report = validate_items(&items, &config, Some((&tokenizer, 6, "passage: {}"))).unwrap();
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].ids, vec![1]);
+    }
+}
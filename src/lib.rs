@@ -61,17 +61,23 @@
 //! Required JSON file format:
 //! ```json
 //! [
-//! 	{
-//! 		"id": int,
-//! 		"name": "string",
-//! 		"summary": "string",
-//! 		"tags": ["string1", "string2"]
-//! 	},
-//! 	{
-//! 		...
-//! 	}
+//!     {
+//!         "id": int,
+//!         "name": "string",
+//!         "summary": "string",
+//!         "tags": ["string1", "string2"]
+//!     },
+//!     {
+//!         ...
+//!     }
 //! ]
 //! ```
+//! 
+//! Large datasets can also be given as a JSON Lines file (one item per line) and loaded with `create_model_jsonl`:
+//! ```json
+//! {"id": int, "name": "string", "summary": "string", "tags": ["string1", "string2"]}
+//! {"id": int, "name": "string", "summary": "string", "tags": ["string1"]}
+//! ```
 
 
 pub(crate) mod helpers;
@@ -79,123 +85,1393 @@ pub(crate) mod helpers;
 extern crate candle;
 
 pub use candle::Tensor;
-pub use helpers::types::Data;
+pub use helpers::types::{AnalyzerOptions, AuditNeighbour, AutoTagOptions, AutoTagStrategy, BlendedRecommendation, BoostMode, ChunkAggregation, ChunkIndex, ChunkedRecommendation, ChunkingOptions, Command, ConstraintStatus, CrossEncoder, Data, Diversity, Example, Explanation, FeedbackQuery, IndexMetadata, ItemLookup, ItemRef, MultiVectorIndex, NegativeAction, Page, PageRequest, Quantization, QuantizationReport, QueryInterpretation, QueryOptions, RankedRecommendations, RemovalReason, SeedCombination, Severity, SkippedLine, SuggestedTag, SummaryChunk, TagAuditEntry, TagAuditOptions, TagAuditReport, TagChange, TagConstraint, TagConstraints, TagHierarchy, TagInfo, TagMatch, TagMatchKind, TagSuggestions, TagUse, TokenMatrix, ValidationCheck, ValidationConfig, ValidationIssue, ValidationReport, WhyNotReport};
 pub use std::collections::HashMap;
 
-use helpers::pre_recommendation::{extract_data, insert_embeddings, find_embedding};
-use helpers::recommendation::{get_recommendations, create_input_embedding};
+use helpers::pre_recommendation::{extract_data, extract_data_jsonl, insert_embeddings, find_embedding, find_embedding_by_id, read_items};
+use helpers::recommendation::{get_recommendations, get_recommendations_page, get_recommendations_with, get_recommendations_multi, get_blended_recommendations, create_input_embedding, create_input_embeddings};
+use helpers::chunking::{build_chunk_index, get_chunked_recommendations};
+use helpers::cross_encoder::{get_cross_encoder_recommendations, read_cross_encoder};
+use helpers::diagnostics::why_not as diagnose_item;
+use helpers::feedback::{get_feedback_recommendations, rocchio};
+use helpers::late_interaction::{build_multi_vector_index, embed_query_tokens, get_late_interaction_recommendations};
+use helpers::lookup::lookup_item;
+use helpers::quantization::{compare_quantized as compare_quantized_embeddings, quantize_weights};
+use helpers::query_analysis::analyze_query;
+use helpers::auto_tagging::{apply_suggestions, suggest_tags as suggest_item_tags};
+use helpers::tag_audit::audit_tags as audit_item_tags;
+use helpers::tags::{expand_tags_input, read_hierarchy, tag_vocabulary};
+use helpers::sqlite::{extract_data_sqlite, read_embeddings_sqlite, read_index_metadata, read_token_embeddings_sqlite, write_embeddings_sqlite, write_neighbours_sqlite, write_token_embeddings_sqlite};
+use helpers::types::Args;
+use helpers::validation::validate_items;
+use clap::Parser;
+use std::io::BufRead;
 
 /// # create_model
 /// This function creates the model from the file path given by the user
 /// 
 /// # Arguments
-/// ```no_run
+/// ```text
 ///     * file_path: &String - The file path to the model
 /// ```
 /// 
 /// # Returns
-/// ```no_run
+/// ```text
 ///    * Result<HashMap<Data, Option<Tensor>>, String> - The model if it was created successfully, otherwise a wrapped error message
 /// ```
 /// 
 /// # Example
 /// ```no_run
-/// 	let file_path = "path/to/model".to_string();
-/// 	let model = create_model(&file_path);
-/// 	match model {
-/// 		Ok(model) => println!("Model created successfully"),
-/// 		Err(e) => println!("Error: {}", e),
-/// 	}
+/// # use reco_forge::create_model;
+///     let file_path = "path/to/model".to_string();
+///     let model = create_model(&file_path);
+///     match model {
+///         Ok(model) => println!("Model created successfully"),
+///         Err(e) => println!("Error: {}", e),
+///     }
 /// ```
 pub fn create_model(file_path: &String) -> Result<HashMap<Data, Option<Tensor>>, String> {
-    let nodes_wrapped: Result<HashMap<Data, Option<Tensor>>, ()> = extract_data(&file_path);
+    let nodes_wrapped: Result<HashMap<Data, Option<Tensor>>, ()> = extract_data(file_path);
     if nodes_wrapped.is_err() {
         return Err(("File path is not valid or file cannot be deserialized, please input the correct file path and try again:").to_string());
     }
     let mut nodes: HashMap<Data, Option<Tensor>> = nodes_wrapped.unwrap();
     println!("Creating model, please be patient...");
-    if let Ok(_) = insert_embeddings(&mut nodes) {
+    if insert_embeddings(&mut nodes).is_ok() {
         return Ok(nodes);
     }
-    return Err("Error inserting embeddings".to_string());
+    Err("Error inserting embeddings".to_string())
 }
 
-/// # pass_description
-/// This function is used when the user wants to find recommendations based on a description
+/// # create_model_jsonl
+/// This function creates the model from a JSON Lines file (one item per line) given by the user.
+/// The file is streamed and embedded in batches, so large catalogs don't have to be loaded into memory all at once.
+/// Lines that can't be deserialized are skipped and returned instead of failing the whole load.
+/// 
+/// # Arguments
+/// ```text
+///     * file_path: &String - The file path to the JSON Lines file
+///     * batch_size: usize - The number of items to embed at a time
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<(HashMap<Data, Option<Tensor>>, Vec<SkippedLine>), String> - The model and the skipped lines if it was created successfully, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::create_model_jsonl;
+///     let file_path = "path/to/model.jsonl".to_string();
+///     match create_model_jsonl(&file_path, 32) {
+///         Ok((model, skipped)) => {
+///             println!("Model created successfully with {} items", model.len());
+///             for line in skipped {
+///                 println!("Skipped {}", line);
+///             }
+///         },
+///         Err(e) => println!("Error: {}", e),
+///     }
+/// ```
+#[allow(clippy::type_complexity)]
+pub fn create_model_jsonl(file_path: &String, batch_size: usize) -> Result<(HashMap<Data, Option<Tensor>>, Vec<SkippedLine>), String> {
+    println!("Creating model, please be patient...");
+    extract_data_jsonl(file_path, batch_size).map_err(|e| format!("Error creating model: {}", e))
+}
+
+/// # create_model_sqlite
+/// This function creates the model from the rows returned by a query against a SQLite database
+/// 
+/// # Arguments
+/// ```text
+///     * db_path: &String - The file path to the SQLite database
+///     * query: &str - A query returning the columns id, name, summary and tags (in that order). Tags can be a JSON array or a comma separated string
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<HashMap<Data, Option<Tensor>>, String> - The model if it was created successfully, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::create_model_sqlite;
+///     let db_path = "path/to/catalog.db".to_string();
+///     let query = "SELECT id, name, summary, tags FROM products";
+///     match create_model_sqlite(&db_path, query) {
+///         Ok(model) => println!("Model created successfully"),
+///         Err(e) => println!("Error: {}", e),
+///     }
+/// ```
+pub fn create_model_sqlite(db_path: &String, query: &str) -> Result<HashMap<Data, Option<Tensor>>, String> {
+    let mut nodes = extract_data_sqlite(db_path, query).map_err(|e| format!("Error reading from the database: {}", e))?;
+    println!("Creating model, please be patient...");
+    if insert_embeddings(&mut nodes).is_ok() {
+        return Ok(nodes);
+    }
+    Err("Error inserting embeddings".to_string())
+}
+
+/// # save_embeddings_sqlite
+/// This function writes the embedding of every item in the model into the `item_embeddings` table of a SQLite database.
+/// The table has the columns id, name, dimensions and embedding (little endian f32 blob) and is created if it doesn't exist.
+/// The model, revision, architecture, quantization and templates the embeddings were made with are written into the `index_metadata` table
+/// (see `load_index_metadata`) and checked by `load_model_sqlite` when the embeddings are read back.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor>> - The model
+///     * db_path: &String - The file path to the SQLite database
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<usize, String> - The number of rows written, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, save_embeddings_sqlite};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     match save_embeddings_sqlite(&model, &"path/to/catalog.db".to_string()) {
+///         Ok(rows) => println!("Saved {} embeddings", rows),
+///         Err(e) => println!("Error: {}", e),
+///     }
+/// ```
+pub fn save_embeddings_sqlite(node_embeddings: &HashMap<Data, Option<Tensor>>, db_path: &String) -> Result<usize, String> {
+    let metadata = Args::parse().index_metadata().map_err(|e| format!("Error reading how the model embeds: {}", e))?;
+    write_embeddings_sqlite(node_embeddings, db_path, &metadata).map_err(|e| format!("Error saving embeddings: {}", e))
+}
+
+/// # load_model_sqlite
+/// This function creates the model from the rows returned by a query against a SQLite database, like `create_model_sqlite`, but reads
+/// the embeddings saved with `save_embeddings_sqlite` instead of embedding the summaries again. Items are matched to their embeddings by id.
+/// 
+/// # Arguments
+/// ```text
+///     * db_path: &String - The file path to the SQLite database
+///     * query: &str - A query returning the columns id, name, summary and tags (in that order). Tags can be a JSON array or a comma separated string
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<HashMap<Data, Option<Tensor>>, String> - The model, otherwise a wrapped error message if an item has no saved embedding or
+///       the embeddings were made with a different model, architecture, quantization or templates than queries would be embedded with
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::load_model_sqlite;
+///     let db_path = "path/to/catalog.db".to_string();
+///     let query = "SELECT id, name, summary, tags FROM products";
+///     match load_model_sqlite(&db_path, query) {
+///         Ok(model) => println!("Model loaded successfully"),
+///         Err(e) => println!("Error: {}", e),
+///     }
+/// ```
+pub fn load_model_sqlite(db_path: &String, query: &str) -> Result<HashMap<Data, Option<Tensor>>, String> {
+    let mut nodes = extract_data_sqlite(db_path, query).map_err(|e| format!("Error reading from the database: {}", e))?;
+    let metadata = Args::parse().index_metadata().map_err(|e| format!("Error reading how the model embeds: {}", e))?;
+    read_embeddings_sqlite(&mut nodes, db_path, &metadata).map_err(|e| format!("Error loading embeddings: {}", e))?;
+    Ok(nodes)
+}
+
+/// # load_index_metadata
+/// This function reads the model, revision, architecture, quantization and query and document templates the embeddings in a SQLite database
+/// were made with. Queries have to be embedded the same way (same --model-id, --revision or --model-dir, --architecture, --quantized,
+/// --query-template and --document-template) to be compared with them.
 /// 
 /// # Arguments
+/// ```text
+///     * db_path: &String - The file path to the SQLite database
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Option<IndexMetadata>, String> - How the embeddings were made, or None if the database has no metadata, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
 /// ```no_run
-///    	* node_embeddings: &HashMap<Data, Option<Tensor> - The model
-///    	* description_input: String - The description input by the user
-/// 	* tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
-/// 	* num_recommendations: usize - The number of recommendations the user wants
+/// # use reco_forge::load_index_metadata;
+///     match load_index_metadata(&"path/to/catalog.db".to_string()) {
+///         Ok(Some(metadata)) => println!("Embedded with {}", metadata),
+///         Ok(None) => println!("No metadata"),
+///         Err(e) => println!("Error: {}", e),
+///     }
+/// ```
+pub fn load_index_metadata(db_path: &String) -> Result<Option<IndexMetadata>, String> {
+    read_index_metadata(db_path).map_err(|e| format!("Error loading the index metadata: {}", e))
+}
+
+/// # save_neighbours_sqlite
+/// This function precomputes the most similar items of every item in the model and writes them into the `item_neighbours` table of a SQLite database.
+/// The table has the columns id, neighbour_id, rank (starting at 1) and similarity and is created if it doesn't exist.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor>> - The model
+///     * db_path: &String - The file path to the SQLite database
+///     * num_neighbours: usize - The number of neighbours to store for each item
 /// ```
 /// 
 /// # Returns
+/// ```text
+///     * Result<usize, String> - The number of rows written, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
 /// ```no_run
-///   	* Result<Vec<String, f32>, ()> - A vector of (Item name, similarity) tuples if recommendations were found, otherwise Err
+/// # use reco_forge::{create_model, save_neighbours_sqlite};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     match save_neighbours_sqlite(&model, &"path/to/catalog.db".to_string(), 10) {
+///         Ok(rows) => println!("Saved {} neighbours", rows),
+///         Err(e) => println!("Error: {}", e),
+///     }
+/// ```
+pub fn save_neighbours_sqlite(node_embeddings: &HashMap<Data, Option<Tensor>>, db_path: &String, num_neighbours: usize) -> Result<usize, String> {
+    write_neighbours_sqlite(node_embeddings, db_path, num_neighbours).map_err(|e| format!("Error saving neighbours: {}", e))
+}
+
+/// # validate
+/// This function checks a JSON file for problems before a model is created from it.
+/// It reports duplicate ids and names, blank or very short summaries, summaries that the tokenizer will truncate, tags that only differ by casing and items without tags.
+/// 
+/// # Arguments
+/// ```text
+///     * file_path: &String - The file path to the JSON file
+///     * config: &ValidationConfig - Whether each check is off, a warning or a failure
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<ValidationReport, String> - The report if the file could be checked, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{validate, ValidationConfig};
+///     let file_path = "path/to/model".to_string();
+///     match validate(&file_path, &ValidationConfig::default()) {
+///         Ok(report) => {
+///             println!("{}", report);
+///             if !report.passed() {
+///                 println!("Please fix the catalog before creating the model");
+///             }
+///         },
+///         Err(e) => println!("Error: {}", e),
+///     }
+/// ```
+pub fn validate(file_path: &String, config: &ValidationConfig) -> Result<ValidationReport, String> {
+    let items = read_items(file_path).map_err(|_| "File path is not valid or file cannot be deserialized, please input the correct file path and try again:".to_string())?;
+    validate_data(&items, config)
+}
+
+/// # validate_data
+/// This function runs the same checks as `validate` on items that were loaded some other way
+/// 
+/// # Arguments
+/// ```text
+///     * items: &[Data] - The items to check
+///     * config: &ValidationConfig - Whether each check is off, a warning or a failure
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<ValidationReport, String> - The report if the items could be checked, otherwise a wrapped error message
+/// ```
+pub fn validate_data(items: &[Data], config: &ValidationConfig) -> Result<ValidationReport, String> {
+    if config.truncated_summaries == Severity::Off {
+        return validate_items(items, config, None).map_err(|e| format!("Error validating: {}", e));
+    }
+    let args = Args::parse();
+    let (tokenizer, max_length) = args.build_tokenizer().map_err(|e| format!("Error loading the tokenizer: {}", e))?;
+    validate_items(items, config, Some((&tokenizer, max_length, &args.document_template))).map_err(|e| format!("Error validating: {}", e))
+}
+
+/// # cli_command
+/// This function returns the command given on the command line, if any, so that a binary can run it instead of its interactive prompt
+/// 
+/// # Returns
+/// ```text
+///     * Option<Command> - The command if one was given, otherwise None
+/// ```
+pub fn cli_command() -> Option<Command> {
+    Args::parse().command
+}
+
+/// # pass_description
+/// This function is used when the user wants to find recommendations based on a description
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * description_input: String - The description input by the user
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE.
+///       A tag can be given alternatives separated by a | ("Action|Superhero") to match items with any of them, so tags can't contain a |
+///     * num_recommendations: usize - The number of recommendations the user wants
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Vec<String, f32>, ()> - A vector of (Item name, similarity) tuples if recommendations were found, otherwise Err
 /// ```
 /// 
 /// # Example
 /// ```no_run
-/// 	let recommendations = pass_description(&model, "description".to_string(), "tag1,tag2".to_string(), 10);
-/// 	match recommendations {
-/// 		Ok(recommendations) => {
-/// 			println!("Recommendations:");
-/// 			for recommendation in recommendations {
-/// 				println!("{}% {}", (recommendation.1 * 100.0).round(), recommendation.0);
-/// 			}
-/// 		},
-/// 		Err(_) => println!("No recommendations found"),
-/// 	}
+/// # use reco_forge::{create_model, pass_description};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let recommendations = pass_description(&model, "description".to_string(), "tag1,tag2".to_string(), 10);
+///     match recommendations {
+///         Ok(recommendations) => {
+///             println!("Recommendations:");
+///             for recommendation in recommendations {
+///                 println!("{}% {}", (recommendation.1 * 100.0).round(), recommendation.0);
+///             }
+///         },
+///         Err(_) => println!("No recommendations found"),
+///     }
 /// ```
+#[allow(clippy::result_unit_err)]
 pub fn pass_description(node_embeddings: &HashMap<Data, Option<Tensor>>, description_input: String, tags_input: String, num_recommendations: usize) -> Result<Vec<(String, f32)>, ()> {
 
     // When we are given a description, we need to create an embedding for it and then find recommendations based on that
-    let input_embedding = create_input_embedding(&description_input).unwrap().unwrap();
-    let recommendations = get_recommendations(node_embeddings, None, &input_embedding, &tags_input, num_recommendations);
-    recommendations
+    let input_embedding = create_input_embedding(&description_input).map_err(|_| ())?.ok_or(())?;
+    get_recommendations(node_embeddings, &[], &input_embedding, &tags_input, num_recommendations)
 }
 
 /// # pass_item
-/// This function is used when the user wants to find recommendations based on a specific item that is already in the model
+/// This function is used when the user wants to find recommendations based on a specific item that is already in the model.
+/// The item name doesn't have to match exactly (see `find_item`). If several items have exactly that name, the one with the smallest id is used,
+/// use `pass_item_by_id` to choose a different one. Only the item itself is left out of the recommendations.
 /// 
 /// # Arguments
-/// ```no_run
-///   	* node_embeddings: &HashMap<Data, Option<Tensor> - The model
-///  	* item: String - The item the user wants recommendations for
-/// 	* tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
-/// 	* num_recommendations: usize - The number of recommendations the user wants
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * item: String - The item the user wants recommendations for
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE.
+///       A tag can be given alternatives separated by a | ("Action|Superhero") to match items with any of them, so tags can't contain a |
+///     * num_recommendations: usize - The number of recommendations the user wants
 /// ```
 /// 
 /// # Returns
-/// ```no_run
-///   	* Result<Vec<String, f32>, ()> - A vector of (Item name, similarity) tuples if recommendations were found, otherwise Err
+/// ```text
+///     * Result<Vec<String, f32>, ()> - A vector of (Item name, similarity) tuples if recommendations were found, otherwise Err
 /// ```
 /// 
 /// # Example
 /// ```no_run
-/// 	let recommendations = pass_item(&model, "item".to_string(), "tag1,tag2".to_string(), 10);
-/// 	match recommendations {
-/// 		Ok(recommendations) => {
-/// 			println!("Recommendations:");
-/// 			for recommendation in recommendations {
+/// # use reco_forge::{create_model, pass_item};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let recommendations = pass_item(&model, "item".to_string(), "tag1,tag2".to_string(), 10);
+///     match recommendations {
+///         Ok(recommendations) => {
+///             println!("Recommendations:");
+///             for recommendation in recommendations {
 ///                 println!("{}% {}", (recommendation.1 * 100.0).round(), recommendation.0);
-/// 			}
-/// 		},
-/// 		Err(_) => println!("No recommendations found"),
-/// 	}
+///             }
+///         },
+///         Err(_) => println!("No recommendations found"),
+///     }
 /// ```
+#[allow(clippy::result_unit_err)]
 pub fn pass_item(node_embeddings: &HashMap<Data, Option<Tensor>>, item: String, tags_input: String, num_recommendations: usize) -> Result<Vec<(String, f32)>, ()> {
 
     // When we want to find items similar to a specific item, we need to make sure that the item is in the embeddings and then retrieve the embedding
-    let input_embedding = {
-        if let Ok(embedding) = find_embedding(node_embeddings, &item) {
-            embedding
+    let (item, input_embedding) = {
+        if let Ok(found) = find_embedding(node_embeddings, &item) {
+            found
         } else {
             return Err(());
         }
     };
-    let recommendations = get_recommendations(node_embeddings, Some(&item), &input_embedding, &tags_input, num_recommendations);
-    recommendations
-}
\ No newline at end of file
+    get_recommendations(node_embeddings, &[item], &input_embedding, &tags_input, num_recommendations)
+}
+
+/// # pass_description_with
+/// This function is the same as `pass_description` but the ranking can be changed for this query with `options`,
+/// for example to boost or penalize tags without filtering on them, to re-rank the recommendations for diversity
+/// so they aren't all sequels of the same movie, or to limit how many of them share a primary tag.
+/// With `explain` on, each recommendation also comes with the tags, keywords and summary sentence that made it a match.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * description_input: String - The description input by the user
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * num_recommendations: usize - The number of recommendations the user wants
+///     * options: &QueryOptions - How the recommendations are ranked
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<RankedRecommendations, ()> - The (Item name, similarity) tuples, which tag constraints were binding and, if options.explain is on, why each item was recommended if recommendations were found, otherwise Err
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, load_tag_hierarchy, pass_description_with, BoostMode, Diversity, QueryOptions, TagConstraints};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let options = QueryOptions {
+///         tag_boosts: vec![("Comedy".to_string(), 0.1), ("Horror".to_string(), -0.2)],
+///         boost_mode: BoostMode::Additive,
+///         min_score: Some(0.2),
+///         diversity: Diversity::Mmr { lambda: 0.7, pool_size: 50 },
+///         tag_constraints: TagConstraints {
+///             max_per_primary_tag: Some(3),
+///             min_per_tag: vec![("Comedy".to_string(), 1), ("Drama".to_string(), 1)],
+///         },
+///         explain: true,
+///         tag_hierarchy: load_tag_hierarchy(&"path/to/hierarchy.json".to_string()).ok(),
+///     };
+///     if let Ok(recommendations) = pass_description_with(&model, "batman".to_string(), "NONE".to_string(), 10, &options) {
+///         for (recommendation, explanation) in recommendations.items.iter().zip(recommendations.explanations.iter()) {
+///             println!("{}% {}", (recommendation.1 * 100.0).round(), recommendation.0);
+///             println!("    {}", explanation);
+///         }
+///         for constraint in recommendations.constraints {
+///             println!("{}", constraint);
+///         }
+///     }
+/// ```
+#[allow(clippy::result_unit_err)]
+pub fn pass_description_with(node_embeddings: &HashMap<Data, Option<Tensor>>, description_input: String, tags_input: String, num_recommendations: usize, options: &QueryOptions) -> Result<RankedRecommendations, ()> {
+    let input_embedding = create_input_embedding(&description_input).map_err(|_| ())?.ok_or(())?;
+    get_recommendations_with(node_embeddings, &[], &input_embedding, &tags_input, num_recommendations, options, &description_input)
+}
+
+/// # pass_item_with
+/// This function is the same as `pass_item` but the item is given by id or name and the ranking can be changed for this query with `options`, see `pass_description_with`
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * item: ItemRef - The item the user wants recommendations for, by id or name
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * num_recommendations: usize - The number of recommendations the user wants
+///     * options: &QueryOptions - How the recommendations are ranked
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<RankedRecommendations, ()> - The (Item name, similarity) tuples and which tag constraints were binding if recommendations were found, otherwise Err
+/// ```
+#[allow(clippy::result_unit_err)]
+pub fn pass_item_with(node_embeddings: &HashMap<Data, Option<Tensor>>, item: ItemRef, tags_input: String, num_recommendations: usize, options: &QueryOptions) -> Result<RankedRecommendations, ()> {
+    let (item, input_embedding) = resolve_item(node_embeddings, &item).map_err(|_| ())?;
+    get_recommendations_with(node_embeddings, std::slice::from_ref(&item), &input_embedding, &tags_input, num_recommendations, options, &item.summary)
+}
+
+/// # analyze_description
+/// This function picks out the words of a description that name tags in the model, like "sci-fi" and "action" in "sci-fi action movies about time travel".
+/// Words are matched to tag names ignoring case, punctuation and plurals, through synonyms, and optionally through embedding similarity to the tag names.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * description_input: &str - The description input by the user
+///     * options: &AnalyzerOptions - Extra synonyms, whether to match by embedding and whether the tags are used as a filter or a boost
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<QueryInterpretation, String> - The tags that were found and the text that is left to embed, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, analyze_description, AnalyzerOptions};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let interpretation = analyze_description(&model, "sci-fi action movies about time travel", &AnalyzerOptions::default()).unwrap();
+///     println!("{}", interpretation);
+/// ```
+pub fn analyze_description(node_embeddings: &HashMap<Data, Option<Tensor>>, description_input: &str, options: &AnalyzerOptions) -> Result<QueryInterpretation, String> {
+    analyze_query(node_embeddings, description_input, options).map_err(|e| format!("Error analyzing the description: {}", e))
+}
+
+/// # pass_description_analyzed
+/// This function is the same as `pass_description_with` but the description is analyzed first (see `analyze_description`).
+/// The tags found are added to the tag filter or to the tag boosts and only the rest of the description is embedded.
+/// The interpretation is returned along with the recommendations so it can be shown to the user.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * description_input: String - The description input by the user
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * num_recommendations: usize - The number of recommendations the user wants
+///     * analyzer_options: &AnalyzerOptions - How the description is turned into tags
+///     * options: &QueryOptions - How the recommendations are ranked
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<(QueryInterpretation, RankedRecommendations), ()> - How the description was interpreted and the recommendations if they were found, otherwise Err
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, pass_description_analyzed, AnalyzerOptions, QueryOptions};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let result = pass_description_analyzed(&model, "sci-fi action movies about time travel".to_string(), "NONE".to_string(), 10, &AnalyzerOptions::default(), &QueryOptions::default());
+///     if let Ok((interpretation, recommendations)) = result {
+///         println!("{}", interpretation);
+///         for recommendation in recommendations.items {
+///             println!("{}% {}", (recommendation.1 * 100.0).round(), recommendation.0);
+///         }
+///     }
+/// ```
+#[allow(clippy::result_unit_err)]
+pub fn pass_description_analyzed(node_embeddings: &HashMap<Data, Option<Tensor>>, description_input: String, tags_input: String, num_recommendations: usize, analyzer_options: &AnalyzerOptions, options: &QueryOptions) -> Result<(QueryInterpretation, RankedRecommendations), ()> {
+    let interpretation = analyze_query(node_embeddings, &description_input, analyzer_options).map_err(|_| ())?;
+
+    let mut tags_input = tags_input;
+    let mut options = options.clone();
+    for tag_match in &interpretation.tags {
+        match interpretation.tag_use {
+            TagUse::Filter if tags_input == "NONE" => tags_input = tag_match.tag.clone(),
+            TagUse::Filter => tags_input = format!("{},{}", tags_input, tag_match.tag),
+            TagUse::Boost(boost) => options.tag_boosts.push((tag_match.tag.clone(), boost)),
+        }
+    }
+
+    let input_embedding = create_input_embedding(&interpretation.remaining_text).map_err(|_| ())?.ok_or(())?;
+    let recommendations = get_recommendations_with(node_embeddings, &[], &input_embedding, &tags_input, num_recommendations, &options, &interpretation.remaining_text)?;
+    Ok((interpretation, recommendations))
+}
+
+/// # pass_description_page
+/// This function is the same as `pass_description` but returns one page of recommendations, for UIs that let the user scroll through more of them.
+/// Recommendations are ordered by similarity and then by id, so pages never repeat or skip items. Items below `min_score` are left out.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * description_input: String - The description input by the user
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * request: &PageRequest - The score floor, the page size and either an offset or the cursor of the previous page
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Page, ()> - The page if it was found, otherwise Err (also if the cursor is invalid)
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, pass_description_page, PageRequest};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let mut request = PageRequest { min_score: Some(0.3), limit: 10, ..PageRequest::default() };
+///     loop {
+///         let page = pass_description_page(&model, "description".to_string(), "NONE".to_string(), &request).unwrap();
+///         for recommendation in &page.items {
+///             println!("{}% {}", (recommendation.1 * 100.0).round(), recommendation.0);
+///         }
+///         match page.next_cursor {
+///             Some(cursor) => request.cursor = Some(cursor),
+///             None => break,
+///         }
+///     }
+/// ```
+#[allow(clippy::result_unit_err)]
+pub fn pass_description_page(node_embeddings: &HashMap<Data, Option<Tensor>>, description_input: String, tags_input: String, request: &PageRequest) -> Result<Page, ()> {
+    let input_embedding = create_input_embedding(&description_input).map_err(|_| ())?.ok_or(())?;
+    get_recommendations_page(node_embeddings, &[], &input_embedding, &tags_input, request)
+}
+
+/// # pass_item_page
+/// This function is the same as `pass_item` but returns one page of recommendations, see `pass_description_page`
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * item: ItemRef - The item the user wants recommendations for, by id or name
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * request: &PageRequest - The score floor, the page size and either an offset or the cursor of the previous page
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Page, ()> - The page if it was found, otherwise Err (also if the item couldn't be found or the cursor is invalid)
+/// ```
+#[allow(clippy::result_unit_err)]
+pub fn pass_item_page(node_embeddings: &HashMap<Data, Option<Tensor>>, item: ItemRef, tags_input: String, request: &PageRequest) -> Result<Page, ()> {
+    let (item, input_embedding) = resolve_item(node_embeddings, &item).map_err(|_| ())?;
+    get_recommendations_page(node_embeddings, &[item], &input_embedding, &tags_input, request)
+}
+
+/// # pass_item_by_id
+/// This function is the same as `pass_item` but the item is given by its id, which is needed when several items share a name
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * id: i32 - The id of the item the user wants recommendations for
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * num_recommendations: usize - The number of recommendations the user wants
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Vec<String, f32>, ()> - A vector of (Item name, similarity) tuples if recommendations were found, otherwise Err (also if the id isn't unique)
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, pass_item_by_id};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let recommendations = pass_item_by_id(&model, 27205, "NONE".to_string(), 10);
+/// ```
+#[allow(clippy::result_unit_err)]
+pub fn pass_item_by_id(node_embeddings: &HashMap<Data, Option<Tensor>>, id: i32, tags_input: String, num_recommendations: usize) -> Result<Vec<(String, f32)>, ()> {
+    let (item, input_embedding) = find_embedding_by_id(node_embeddings, id).map_err(|_| ())?;
+    get_recommendations(node_embeddings, &[item], &input_embedding, &tags_input, num_recommendations)
+}
+
+/// # pass_item_with_description
+/// This function is used when the user wants things like a specific item but with something changed, like "games like Portal but with multiplayer co-op".
+/// The seed item and the description are blended with `text_weight`, and every recommendation says how much of its score came from each of them.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * item: ItemRef - The item the user wants recommendations like, by id or name
+///     * description_input: String - What the user wants changed
+///     * text_weight: f32 - How much the description counts compared to the item, between 0 (only the item) and 1 (only the description)
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * num_recommendations: usize - The number of recommendations the user wants
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Vec<BlendedRecommendation>, ()> - The recommendations, best first, if they were found, otherwise Err (also if the item couldn't be found or text_weight is out of range)
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, pass_item_with_description, ItemRef};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let recommendations = pass_item_with_description(&model, ItemRef::Name("Portal".to_string()), "multiplayer co-op".to_string(), 0.4, "NONE".to_string(), 10);
+///     if let Ok(recommendations) = recommendations {
+///         for recommendation in recommendations {
+///             let source = if recommendation.mostly_from_item() { "item" } else { "description" };
+///             println!("{} (mostly from the {})", recommendation, source);
+///         }
+///     }
+/// ```
+#[allow(clippy::result_unit_err)]
+pub fn pass_item_with_description(node_embeddings: &HashMap<Data, Option<Tensor>>, item: ItemRef, description_input: String, text_weight: f32, tags_input: String, num_recommendations: usize) -> Result<Vec<BlendedRecommendation>, ()> {
+    let (item, item_embedding) = resolve_item(node_embeddings, &item).map_err(|_| ())?;
+    let text_embedding = create_input_embedding(&description_input).map_err(|_| ())?.ok_or(())?;
+    get_blended_recommendations(node_embeddings, &item, &item_embedding, &text_embedding, text_weight, &tags_input, num_recommendations)
+}
+
+/// # pass_items
+/// This function is used when the user wants to find recommendations based on several items that are already in the model, like a watch history.
+/// None of the seed items are included in the recommendations.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * seeds: Vec<(ItemRef, f32)> - The items the user wants recommendations for, each by id or name, with a weight greater than 0 (use 1.0 for all of them to weigh them equally)
+///     * combination: SeedCombination - How the seeds are combined: Centroid, MaxSimilarity or RoundRobin
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * num_recommendations: usize - The number of recommendations the user wants
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Vec<String, f32>, ()> - A vector of (Item name, similarity) tuples, fewer than num_recommendations if fewer items are left, otherwise Err (also if a seed couldn't be found)
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, pass_items, ItemRef, SeedCombination};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let seeds = vec![(ItemRef::Name("Inception".to_string()), 2.0), (ItemRef::Id(155), 1.0)];
+///     let recommendations = pass_items(&model, seeds, SeedCombination::Centroid, "NONE".to_string(), 10);
+/// ```
+#[allow(clippy::result_unit_err)]
+pub fn pass_items(node_embeddings: &HashMap<Data, Option<Tensor>>, seeds: Vec<(ItemRef, f32)>, combination: SeedCombination, tags_input: String, num_recommendations: usize) -> Result<Vec<(String, f32)>, ()> {
+    let mut resolved: Vec<(Data, Tensor, f32)> = Vec::new();
+    for (seed, weight) in seeds {
+        let (item, embedding) = resolve_item(node_embeddings, &seed).map_err(|_| ())?;
+        resolved.push((item, embedding, weight));
+    }
+    get_recommendations_multi(node_embeddings, &resolved, combination, &tags_input, num_recommendations)
+}
+
+fn resolve_item(node_embeddings: &HashMap<Data, Option<Tensor>>, item: &ItemRef) -> anyhow::Result<(Data, Tensor)> {
+    match item {
+        ItemRef::Id(id) => find_embedding_by_id(node_embeddings, *id),
+        ItemRef::Name(name) => find_embedding(node_embeddings, name),
+    }
+}
+
+/// # pass_feedback
+/// This function is used when the user has examples of what they want and what they don't want, like "like Inception but not horror, and nothing like Saw".
+/// Examples can be items in the model or free text. They are combined into one query Rocchio style (see `FeedbackQuery`),
+/// and items too similar to a negative example are dropped or demoted. None of the example items are included in the recommendations.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * query: &FeedbackQuery - The description, the positive and negative examples and how to weigh them
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * num_recommendations: usize - The number of recommendations the user wants
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Vec<String, f32>, ()> - A vector of (Item name, score) tuples if recommendations were found, otherwise Err (also if an example item couldn't be found or there is nothing positive in the query)
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, pass_feedback, Example, FeedbackQuery, ItemRef};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let query = FeedbackQuery {
+///         positive: vec![Example::Item(ItemRef::Name("Inception".to_string()))],
+///         negative: vec![Example::Text("horror".to_string()), Example::Item(ItemRef::Name("Saw".to_string()))],
+///         ..FeedbackQuery::default()
+///     };
+///     let recommendations = pass_feedback(&model, &query, "NONE".to_string(), 10);
+/// ```
+#[allow(clippy::result_unit_err)]
+pub fn pass_feedback(node_embeddings: &HashMap<Data, Option<Tensor>>, query: &FeedbackQuery, tags_input: String, num_recommendations: usize) -> Result<Vec<(String, f32)>, ()> {
+    // Every piece of text in the query is embedded in one go, then split back up
+    let mut texts: Vec<&str> = Vec::new();
+    if let Some(description) = &query.description {
+        texts.push(description);
+    }
+    for example in query.positive.iter().chain(query.negative.iter()) {
+        if let Example::Text(text) = example {
+            texts.push(text);
+        }
+    }
+    let mut text_embeddings = create_input_embeddings(&texts).map_err(|_| ())?.into_iter();
+    let description = match query.description {
+        Some(_) => text_embeddings.next(),
+        None => None,
+    };
+
+    let mut exclude: Vec<Data> = Vec::new();
+    let mut embed_examples = |examples: &[Example]| -> Result<Vec<Tensor>, ()> {
+        let mut embeddings = Vec::new();
+        for example in examples {
+            match example {
+                Example::Item(item) => {
+                    let (item, embedding) = resolve_item(node_embeddings, item).map_err(|_| ())?;
+                    exclude.push(item);
+                    embeddings.push(embedding);
+                },
+                Example::Text(_) => embeddings.push(text_embeddings.next().ok_or(())?),
+            }
+        }
+        Ok(embeddings)
+    };
+    let positive = embed_examples(&query.positive)?;
+    let negative = embed_examples(&query.negative)?;
+
+    let query_vector = rocchio(description.as_ref(), &positive, &negative, (query.alpha, query.beta, query.gamma)).map_err(|_| ())?;
+    get_feedback_recommendations(
+        node_embeddings,
+        &exclude,
+        &query_vector,
+        &negative,
+        query.negative_threshold,
+        query.negative_action,
+        &tags_input,
+        num_recommendations,
+    )
+}
+
+/// # find_item
+/// This function looks up an item in the model by name. The lookup ignores case, accents and punctuation and allows for typos and missing words,
+/// so "dark knight" finds "The Dark Knight" and "Incepton" finds "Inception". When the name is close to several items, they are returned as suggestions instead.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor>> - The model
+///     * item: &str - The name of the item
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * ItemLookup - Found with the item, Collision with every item that has exactly that name, Ambiguous with suggestions (best match first) or NotFound
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, find_item, ItemLookup};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     match find_item(&model, "dark knight") {
+///         ItemLookup::Found(item) => println!("Found {}", item.name),
+///         ItemLookup::Collision(items) => println!("{} items have this name, please use an id", items.len()),
+///         ItemLookup::Ambiguous(suggestions) => {
+///             println!("Did you mean:");
+///             for (suggestion, _score) in suggestions {
+///                 println!("    {}", suggestion.name);
+///             }
+///         },
+///         ItemLookup::NotFound => println!("Item not found"),
+///     }
+/// ```
+pub fn find_item(node_embeddings: &HashMap<Data, Option<Tensor>>, item: &str) -> ItemLookup {
+    lookup_item(node_embeddings, item)
+}
+
+/// # read_item
+/// This function asks the user for an item until exactly one is picked. Each line read from `input` is looked up with `find_item`:
+/// when several items have the name the user is asked for the id of the one they mean, and when the name is ambiguous or not
+/// in the model the user is shown the suggestions and asked again. What to show the user is handed to `show`, so the caller
+/// decides how to display it.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor>> - The model
+///     * input: &mut impl BufRead - Where the names and ids are read from, usually the locked stdin
+///     * show: impl FnMut(&str) - Called with every prompt and list of suggestions for the user
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Data, String> - The item the user picked, otherwise Err if the input couldn't be read or ended first
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, read_item};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     println!("Input an item:");
+///     let item = read_item(&model, &mut std::io::stdin().lock(), |prompt| println!("{}", prompt)).unwrap();
+///     println!("Finding items like {}", item.name);
+/// ```
+pub fn read_item(node_embeddings: &HashMap<Data, Option<Tensor>>, input: &mut impl BufRead, mut show: impl FnMut(&str)) -> Result<Data, String> {
+    let mut read_line = || -> Result<String, String> {
+        let mut line = String::new();
+        match input.read_line(&mut line) {
+            Ok(0) => Err("Error reading the item: the input ended".to_string()),
+            Ok(_) => Ok(line.trim().to_string()),
+            Err(e) => Err(format!("Error reading the item: {}", e)),
+        }
+    };
+    loop {
+        let query = read_line()?;
+        let lookup = find_item(node_embeddings, &query);
+        if let ItemLookup::Found(item) = lookup {
+            return Ok(item);
+        }
+        show(&lookup.prompt(&query));
+        if let ItemLookup::Collision(_) = lookup {
+            if let Some(item) = lookup.pick(&read_line()?) {
+                return Ok(item.clone());
+            }
+            show("That id is not one of them, please input the item again:");
+        }
+    }
+}
+
+/// # list_tags
+/// This function lists every tag in the model (ignoring case) with the number of items that have it and the tags it appears alongside,
+/// so that users know what they can enter as tags_input
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor>> - The model
+///     * hierarchy: Option<&TagHierarchy> - Which tags are under which, used to fill in each tag's children and count_with_children
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Vec<TagInfo> - The tags, most used first
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, list_tags};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     for tag in list_tags(&model, None) {
+///         println!("{}", tag);
+///     }
+/// ```
+pub fn list_tags(node_embeddings: &HashMap<Data, Option<Tensor>>, hierarchy: Option<&TagHierarchy>) -> Vec<TagInfo> {
+    tag_vocabulary(node_embeddings.keys(), hierarchy)
+}
+
+/// # list_tags_in_file
+/// This function lists the tags of a JSON file the same way as `list_tags`, without creating the model
+/// 
+/// # Arguments
+/// ```text
+///     * file_path: &String - The file path to the JSON file
+///     * hierarchy: Option<&TagHierarchy> - Which tags are under which
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Vec<TagInfo>, String> - The tags, most used first, if the file could be read, otherwise a wrapped error message
+/// ```
+pub fn list_tags_in_file(file_path: &String, hierarchy: Option<&TagHierarchy>) -> Result<Vec<TagInfo>, String> {
+    let items = read_items(file_path).map_err(|_| "File path is not valid or file cannot be deserialized, please input the correct file path and try again:".to_string())?;
+    Ok(tag_vocabulary(&items, hierarchy))
+}
+
+/// # load_tag_hierarchy
+/// This function reads a tag hierarchy from a JSON file mapping each parent tag to a list of child tags,
+/// like {"Action": ["Superhero", "Martial Arts"], "Superhero": ["DC"]}
+/// 
+/// # Arguments
+/// ```text
+///     * file_path: &String - The file path to the hierarchy file
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<TagHierarchy, String> - The hierarchy if it could be read, otherwise a wrapped error message
+/// ```
+pub fn load_tag_hierarchy(file_path: &String) -> Result<TagHierarchy, String> {
+    read_hierarchy(file_path).map_err(|e| format!("Error reading the tag hierarchy: {}", e))
+}
+
+/// # expand_tags
+/// This function rewrites tags_input so that filtering by a parent tag also matches items that only have one of its children.
+/// The result can be passed as tags_input to any of the pass_ functions. The functions that take `QueryOptions` do this themselves
+/// when `tag_hierarchy` is set.
+/// 
+/// # Arguments
+/// ```text
+///     * hierarchy: &TagHierarchy - Which tags are under which
+///     * tags_input: &str - The tags input by the user, each tag separated by a comma, or NONE
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * String - The tags input where each tag is followed by its children, separated by a |
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, expand_tags, load_tag_hierarchy, pass_description};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let hierarchy = load_tag_hierarchy(&"path/to/hierarchy.json".to_string()).unwrap();
+///     // "Action" now also matches items tagged "Superhero"
+///     let tags_input = expand_tags(&hierarchy, "Action");
+///     let recommendations = pass_description(&model, "description".to_string(), tags_input, 10);
+/// ```
+pub fn expand_tags(hierarchy: &TagHierarchy, tags_input: &str) -> String {
+    expand_tags_input(hierarchy, tags_input)
+}
+
+/// # suggest_tags
+/// This function suggests tags for every item in the model that doesn't have any, either from the similarity between the item
+/// and each tag name or from the tags of the item's nearest tagged neighbours
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor>> - The model
+///     * options: &AutoTagOptions - The strategy, its thresholds and the most tags to suggest for one item
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Vec<TagSuggestions>, String> - The suggested tags with their confidences for each untagged item, ordered by id, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, suggest_tags, AutoTagOptions, AutoTagStrategy};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let options = AutoTagOptions { strategy: AutoTagStrategy::TagNames, ..AutoTagOptions::default() };
+///     match suggest_tags(&model, &options) {
+///         Ok(suggestions) => {
+///             for suggestion in suggestions {
+///                 println!("{}", suggestion);
+///             }
+///         },
+///         Err(e) => println!("Error: {}", e),
+///     }
+/// ```
+pub fn suggest_tags(node_embeddings: &HashMap<Data, Option<Tensor>>, options: &AutoTagOptions) -> Result<Vec<TagSuggestions>, String> {
+    suggest_item_tags(node_embeddings, options).map_err(|e| format!("Error suggesting tags: {}", e))
+}
+
+/// # apply_tag_suggestions
+/// This function gives the untagged items of the model the tags suggested for them, so that they can be filtered by tag
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: HashMap<Data, Option<Tensor>> - The model
+///     * suggestions: &[TagSuggestions] - The suggestions from suggest_tags
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * HashMap<Data, Option<Tensor>> - The model with the suggested tags filled in
+/// ```
+pub fn apply_tag_suggestions(node_embeddings: HashMap<Data, Option<Tensor>>, suggestions: &[TagSuggestions]) -> HashMap<Data, Option<Tensor>> {
+    node_embeddings
+        .into_iter()
+        .map(|(mut key, value)| {
+            apply_suggestions(std::slice::from_mut(&mut key), suggestions);
+            (key, value)
+        })
+        .collect()
+}
+
+/// # write_tag_suggestions
+/// This function writes a copy of a JSON file where the untagged items have the tags suggested for them
+/// 
+/// # Arguments
+/// ```text
+///     * file_path: &String - The file path to the JSON file
+///     * output_path: &String - The file path to write the copy to, which can be the same file
+///     * suggestions: &[TagSuggestions] - The suggestions from suggest_tags
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<usize, String> - The number of items that were given tags, otherwise a wrapped error message
+/// ```
+pub fn write_tag_suggestions(file_path: &String, output_path: &String, suggestions: &[TagSuggestions]) -> Result<usize, String> {
+    let mut items = read_items(file_path).map_err(|_| "File path is not valid or file cannot be deserialized, please input the correct file path and try again:".to_string())?;
+    let applied = apply_suggestions(&mut items, suggestions);
+    let file = std::fs::File::create(output_path).map_err(|e| format!("Error writing the catalog: {}", e))?;
+    serde_json::to_writer_pretty(std::io::BufWriter::new(file), &items).map_err(|e| format!("Error writing the catalog: {}", e))?;
+    Ok(applied)
+}
+
+/// # audit_tags
+/// This function compares the tags of every tagged item with the tags of its nearest neighbours and reports the items whose tags disagree,
+/// with tags to add or remove and the neighbouring items that are the evidence for each
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor>> - The model
+///     * options: &TagAuditOptions - The number of neighbours, the thresholds for adding and removing tags and how much evidence to list
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<TagAuditReport, String> - The report, which can be turned into JSON with to_json, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, audit_tags, TagAuditOptions};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     match audit_tags(&model, &TagAuditOptions::default()) {
+///         Ok(report) => std::fs::write("audit.json", report.to_json()).unwrap(),
+///         Err(e) => println!("Error: {}", e),
+///     }
+/// ```
+pub fn audit_tags(node_embeddings: &HashMap<Data, Option<Tensor>>, options: &TagAuditOptions) -> Result<TagAuditReport, String> {
+    audit_item_tags(node_embeddings, options).map_err(|e| format!("Error auditing tags: {}", e))
+}
+
+/// # why_not
+/// This function explains why an item did or didn't show up in the recommendations of a query, like "why isn't The Dark Knight recommended for batman?".
+/// It reports the item's similarity, its rank among all items and, if it wasn't recommended, the first rule that kept it out
+/// (the item being the query item, the tag filter, the tag boosts, the score floor, the diversity re-ranking or the tag constraints)
+/// and how far its score was from the cut-off. The rules are the ones `pass_description_with` and `pass_item_with` apply with the same options.
+/// Pages only have a tag filter and a score floor, so a page is diagnosed with the default options and the page's `min_score`.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor>> - The model
+///     * query: &Example - The query, either an item in the model or a description
+///     * target_id: i32 - The id of the item to diagnose
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * num_recommendations: usize - The number of recommendations the user wants
+///     * options: &QueryOptions - How the recommendations are ranked, including the score floor
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<WhyNotReport, String> - The report if the query and the item were found, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, why_not, Example, QueryOptions};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let query = Example::Text("batman".to_string());
+///     match why_not(&model, &query, 155, "NONE".to_string(), 10, &QueryOptions::default()) {
+///         Ok(report) => println!("{}", report),
+///         Err(e) => println!("Error: {}", e),
+///     }
+/// ```
+pub fn why_not(node_embeddings: &HashMap<Data, Option<Tensor>>, query: &Example, target_id: i32, tags_input: String, num_recommendations: usize, options: &QueryOptions) -> Result<WhyNotReport, String> {
+    let (exclude, input_embedding) = match query {
+        Example::Item(item) => {
+            let (item, embedding) = resolve_item(node_embeddings, item).map_err(|e| format!("Error finding the query item: {}", e))?;
+            (vec![item], embedding)
+        },
+        Example::Text(description) => {
+            let embedding = create_input_embedding(description).map_err(|e| format!("Error embedding the query: {}", e))?;
+            (Vec::new(), embedding.ok_or("Error embedding the query".to_string())?)
+        },
+    };
+    let (target, _) = find_embedding_by_id(node_embeddings, target_id).map_err(|e| format!("Error finding the item: {}", e))?;
+    diagnose_item(node_embeddings, &exclude, &input_embedding, &target, &tags_input, num_recommendations, options).map_err(|e| format!("Error diagnosing the item: {}", e))
+}
+
+/// # create_chunk_index
+/// This function splits every summary of the model into overlapping windows of tokens and embeds each window.
+/// The model only sees the first few hundred tokens of a summary, so long summaries lose the rest of their text
+/// unless they are searched through their chunks with `pass_description_chunked`.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor>> - The model
+///     * options: &ChunkingOptions - The size and overlap of the windows
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<ChunkIndex, String> - The windows of every summary with their embeddings, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, create_chunk_index, pass_description_chunked, ChunkAggregation, ChunkingOptions};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let index = create_chunk_index(&model, &ChunkingOptions::default()).unwrap();
+///     if let Ok(recommendations) = pass_description_chunked(&index, "description".to_string(), "NONE".to_string(), 10, ChunkAggregation::TopKMean(2)) {
+///         for recommendation in recommendations {
+///             println!("{}", recommendation);
+///         }
+///     }
+/// ```
+pub fn create_chunk_index(node_embeddings: &HashMap<Data, Option<Tensor>>, options: &ChunkingOptions) -> Result<ChunkIndex, String> {
+    build_chunk_index(node_embeddings, options).map_err(|e| format!("Error creating the chunk index: {}", e))
+}
+
+/// # pass_description_chunked
+/// This function is the same as `pass_description` but compares the description with every chunk of each summary
+/// (see `create_chunk_index`) and combines the similarities with `aggregation`. Each recommendation records which chunk matched best.
+/// 
+/// # Arguments
+/// ```text
+///     * index: &ChunkIndex - The chunk index of the model
+///     * description_input: String - The description input by the user
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * num_recommendations: usize - The number of recommendations the user wants
+///     * aggregation: ChunkAggregation - Whether an item's score is the best, the average or the average of the k best chunk similarities
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Vec<ChunkedRecommendation>, ()> - Up to num_recommendations recommendations, best first, if they were found, otherwise Err
+/// ```
+#[allow(clippy::result_unit_err)]
+pub fn pass_description_chunked(index: &ChunkIndex, description_input: String, tags_input: String, num_recommendations: usize, aggregation: ChunkAggregation) -> Result<Vec<ChunkedRecommendation>, ()> {
+    let input_embedding = create_input_embedding(&description_input).map_err(|_| ())?.ok_or(())?;
+    get_chunked_recommendations(index, &[], &input_embedding, &tags_input, num_recommendations, aggregation)
+}
+
+/// # create_multi_vector_index
+/// This function keeps the embedding of every token of every summary instead of only their average, for re-ranking with late interaction
+/// (see `pass_description_late_interaction`). The token embeddings are compressed to one byte per value.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor>> - The model
+///     * batch_size: usize - The number of summaries run through the model at once
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<MultiVectorIndex, String> - The token embeddings of every summary, otherwise a wrapped error message
+/// ```
+pub fn create_multi_vector_index(node_embeddings: &HashMap<Data, Option<Tensor>>, batch_size: usize) -> Result<MultiVectorIndex, String> {
+    build_multi_vector_index(node_embeddings, batch_size).map_err(|e| format!("Error creating the token embeddings: {}", e))
+}
+
+/// # save_token_embeddings_sqlite
+/// This function writes the token embeddings of a multi vector index into the `item_token_embeddings` table of a SQLite database,
+/// next to the tables written by `save_embeddings_sqlite`. The table has the columns id, name, num_tokens, dimensions and tokens
+/// (one signed byte per value) and is created if it doesn't exist.
+/// 
+/// # Arguments
+/// ```text
+///     * index: &MultiVectorIndex - The token embeddings
+///     * db_path: &String - The file path to the SQLite database
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<usize, String> - The number of rows written, otherwise a wrapped error message
+/// ```
+pub fn save_token_embeddings_sqlite(index: &MultiVectorIndex, db_path: &String) -> Result<usize, String> {
+    let metadata = Args::parse().index_metadata().map_err(|e| format!("Error reading how the model embeds: {}", e))?;
+    write_token_embeddings_sqlite(index, db_path, &metadata).map_err(|e| format!("Error saving token embeddings: {}", e))
+}
+
+/// # load_token_embeddings_sqlite
+/// This function reads the token embeddings saved with `save_token_embeddings_sqlite` back for the items of the model, matching them by id
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor>> - The model
+///     * db_path: &String - The file path to the SQLite database
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<MultiVectorIndex, String> - The token embeddings of the items that have them, otherwise a wrapped error message
+/// ```
+pub fn load_token_embeddings_sqlite(node_embeddings: &HashMap<Data, Option<Tensor>>, db_path: &String) -> Result<MultiVectorIndex, String> {
+    let metadata = Args::parse().index_metadata().map_err(|e| format!("Error reading how the model embeds: {}", e))?;
+    read_token_embeddings_sqlite(node_embeddings, db_path, &metadata).map_err(|e| format!("Error loading token embeddings: {}", e))
+}
+
+/// # pass_description_late_interaction
+/// This function is the same as `pass_description` but in two stages: the closest `num_candidates` items are found with the usual
+/// embeddings, then they are re-ranked ColBERT style by matching every word of the description with its closest word in each summary (MaxSim).
+/// This catches specific matches that get lost when a whole summary is averaged into one embedding.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * index: &MultiVectorIndex - The token embeddings of the model, which must include every candidate
+///     * description_input: String - The description input by the user
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * num_candidates: usize - The number of candidates to re-rank
+///     * num_recommendations: usize - The number of recommendations the user wants
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Vec<String, f32>, ()> - A vector of (Item name, MaxSim score) tuples if recommendations were found, otherwise Err
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, create_multi_vector_index, pass_description_late_interaction};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let index = create_multi_vector_index(&model, 32).unwrap();
+///     if let Ok(recommendations) = pass_description_late_interaction(&model, &index, "description".to_string(), "NONE".to_string(), 50, 10) {
+///         for recommendation in recommendations {
+///             println!("{}% {}", (recommendation.1 * 100.0).round(), recommendation.0);
+///         }
+///     }
+/// ```
+#[allow(clippy::result_unit_err)]
+pub fn pass_description_late_interaction(node_embeddings: &HashMap<Data, Option<Tensor>>, index: &MultiVectorIndex, description_input: String, tags_input: String, num_candidates: usize, num_recommendations: usize) -> Result<Vec<(String, f32)>, ()> {
+    let (input_embedding, query_tokens) = embed_query_tokens(&description_input).map_err(|_| ())?;
+    get_late_interaction_recommendations(node_embeddings, index, &[], &input_embedding, &query_tokens, &tags_input, num_candidates, num_recommendations)
+}
+
+/// # load_cross_encoder
+/// This function loads a BERT cross-encoder, such as cross-encoder/ms-marco-MiniLM-L-6-v2, from a local directory for `pass_description_reranked`.
+/// The directory must have the checkpoint's config.json, tokenizer.json and model.safetensors (or pytorch_model.bin).
+/// 
+/// # Arguments
+/// ```text
+///     * dir: &str - The path of the directory
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<CrossEncoder, String> - The cross-encoder if it could be loaded, otherwise a wrapped error message
+/// ```
+pub fn load_cross_encoder(dir: &str) -> Result<CrossEncoder, String> {
+    read_cross_encoder(dir).map_err(|e| format!("Error loading the cross-encoder: {}", e))
+}
+
+/// # pass_description_reranked
+/// This function is the same as `pass_description` but in two stages: the closest `num_candidates` items are found with the usual
+/// embeddings, then the cross-encoder reads the description together with each candidate's summary and the candidates are
+/// ordered by its relevance score. This is slower (one pass through the cross-encoder per candidate) but more precise at the top of the list.
+/// 
+/// # Arguments
+/// ```text
+///     * node_embeddings: &HashMap<Data, Option<Tensor> - The model
+///     * encoder: &CrossEncoder - The cross-encoder
+///     * description_input: String - The description input by the user
+///     * tags_input: String - The tags input by the user, each tag separated by a comma. If the user doesn't want to filter by tags, they can enter NONE
+///     * num_candidates: usize - The number of candidates to re-rank, like 50
+///     * num_recommendations: usize - The number of recommendations the user wants
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<Vec<String, f32>, ()> - A vector of (Item name, relevance between 0 and 1) tuples if recommendations were found, otherwise Err
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{create_model, load_cross_encoder, pass_description_reranked};
+/// # let model = create_model(&"path/to/model".to_string()).unwrap();
+///     let encoder = load_cross_encoder("path/to/ms-marco-MiniLM-L-6-v2").unwrap();
+///     if let Ok(recommendations) = pass_description_reranked(&model, &encoder, "description".to_string(), "NONE".to_string(), 50, 10) {
+///         for recommendation in recommendations {
+///             println!("{}% {}", (recommendation.1 * 100.0).round(), recommendation.0);
+///         }
+///     }
+/// ```
+#[allow(clippy::result_unit_err)]
+pub fn pass_description_reranked(node_embeddings: &HashMap<Data, Option<Tensor>>, encoder: &CrossEncoder, description_input: String, tags_input: String, num_candidates: usize, num_recommendations: usize) -> Result<Vec<(String, f32)>, ()> {
+    let input_embedding = create_input_embedding(&description_input).map_err(|_| ())?.ok_or(())?;
+    get_cross_encoder_recommendations(node_embeddings, encoder, &[], &input_embedding, &description_input, &tags_input, num_candidates, num_recommendations)
+}
+
+/// # quantize_model
+/// This function quantizes the weights of the BERT model set by the command line arguments (--model-id, --revision or --model-dir)
+/// and writes them into a GGUF file. Embeddings are then made with the quantized weights, which is faster on CPU, by passing
+/// --quantized with the path of the file. The embeddings, layer norms and biases are kept in f32.
+/// Use `compare_quantized` to check how much the quantization changes the embeddings before using it.
+/// 
+/// # Arguments
+/// ```text
+///     * output: &String - The file path to write the GGUF file to
+///     * quantization: Quantization - The quantization type, like Q8_0 or Q4_0
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<usize, String> - The number of weight matrices that were quantized, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::{quantize_model, Quantization};
+///     match quantize_model(&"model-q8_0.gguf".to_string(), Quantization::Q8_0) {
+///         Ok(quantized) => println!("Quantized {} weight matrices", quantized),
+///         Err(e) => println!("Error: {}", e),
+///     }
+/// ```
+pub fn quantize_model(output: &String, quantization: Quantization) -> Result<usize, String> {
+    quantize_weights(quantization, output).map_err(|e| format!("Error quantizing the model: {}", e))
+}
+
+/// # compare_quantized
+/// This function embeds the first `sample_size` items of a JSON file with both the f32 model and the quantized model
+/// written by `quantize_model`, and reports how similar the embeddings are, how many nearest neighbours stay the same,
+/// how long each model took and how big each weights file is, to decide whether the speedup is worth it
+/// 
+/// # Arguments
+/// ```text
+///     * file_path: &String - The file path to the JSON file
+///     * quantized_path: &String - The file path to the GGUF file
+///     * sample_size: usize - The number of items to compare
+///     * num_neighbours: usize - The number of nearest neighbours compared for each item
+/// ```
+/// 
+/// # Returns
+/// ```text
+///     * Result<QuantizationReport, String> - The comparison, otherwise a wrapped error message
+/// ```
+/// 
+/// # Example
+/// ```no_run
+/// # use reco_forge::compare_quantized;
+///     match compare_quantized(&"games_clean.json".to_string(), &"model-q8_0.gguf".to_string(), 200, 10) {
+///         Ok(report) => println!("{}", report),
+///         Err(e) => println!("Error: {}", e),
+///     }
+/// ```
+pub fn compare_quantized(file_path: &String, quantized_path: &String, sample_size: usize, num_neighbours: usize) -> Result<QuantizationReport, String> {
+    compare_quantized_embeddings(file_path, quantized_path, sample_size, num_neighbours).map_err(|e| format!("Error comparing the quantized model: {}", e))
+}
@@ -1,6 +1,10 @@
-use reco_forge::{create_model, pass_item, Data, Tensor, HashMap};
+use reco_forge::{audit_tags, cli_command, compare_quantized, create_model, list_tags_in_file, load_tag_hierarchy, pass_item_by_id, quantize_model, read_item, suggest_tags, validate, write_tag_suggestions, AutoTagOptions, Command, Data, HashMap, TagAuditOptions, Tensor, ValidationConfig};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(command) = cli_command() {
+        return run_command(command);
+    }
+
     let mut path = String::new();
     println!("Please enter the file path:");
 
@@ -27,12 +31,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     tags_input = tags_input.trim().to_string();
 
     println!("Input what you want.");
-    let mut query: String = String::new();
-    std::io::stdin().read_line(&mut query).expect("Failed to read line");
-    query = query.trim().to_string();
+    let seed_id = read_item(&model, &mut std::io::stdin().lock(), |prompt| println!("{}", prompt))?.id;
     println!();
 
-    let recommendations = pass_item(&model, query, tags_input, 10);
+    let recommendations = pass_item_by_id(&model, seed_id, tags_input, 10);
     match recommendations {
         Ok(recommendations) => {
             println!("Recommendations:");
@@ -44,3 +46,98 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     Ok(())
 }
+
+fn run_command(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::Validate {
+            file_path,
+            duplicate_ids,
+            duplicate_names,
+            short_summaries,
+            min_summary_words,
+            truncated_summaries,
+            tag_casing,
+            missing_tags,
+        } => {
+            let config = ValidationConfig {
+                duplicate_ids,
+                duplicate_names,
+                short_summaries,
+                min_summary_words,
+                truncated_summaries,
+                tag_casing,
+                missing_tags,
+            };
+            let report = validate(&file_path, &config)?;
+            println!("{}", report);
+            if !report.passed() {
+                std::process::exit(1);
+            }
+        },
+        Command::Tags { file_path, hierarchy, co_occurrences } => {
+            let hierarchy = match hierarchy {
+                Some(hierarchy) => Some(load_tag_hierarchy(&hierarchy)?),
+                None => None,
+            };
+            let tags = list_tags_in_file(&file_path, hierarchy.as_ref())?;
+            println!("{} tags:", tags.len());
+            for tag in tags {
+                println!("{}", tag);
+                let common: Vec<String> = tag.co_occurrences.iter().take(co_occurrences).map(|(other, n)| format!("{} ({})", other, n)).collect();
+                if !common.is_empty() {
+                    println!("    often with: {}", common.join(", "));
+                }
+            }
+        },
+        Command::AutoTag {
+            file_path,
+            strategy,
+            threshold,
+            tag_descriptions,
+            num_neighbours,
+            min_confidence,
+            max_tags,
+            output,
+        } => {
+            let descriptions: Vec<(String, String)> = match tag_descriptions {
+                Some(tag_descriptions) => {
+                    let descriptions: HashMap<String, String> = serde_json::from_str(&std::fs::read_to_string(tag_descriptions)?)?;
+                    descriptions.into_iter().collect()
+                },
+                None => Vec::new(),
+            };
+            let options = AutoTagOptions { strategy, threshold, descriptions, num_neighbours, min_confidence, max_tags };
+            let model = create_model(&file_path)?;
+            let suggestions = suggest_tags(&model, &options)?;
+            println!("{} untagged items:", suggestions.len());
+            for suggestion in &suggestions {
+                println!("{}", suggestion);
+            }
+            if let Some(output) = output {
+                let applied = write_tag_suggestions(&file_path, &output, &suggestions)?;
+                println!("Wrote {} with tags for {} items", output, applied);
+            }
+        },
+        Command::AuditTags { file_path, num_neighbours, add_threshold, remove_threshold, max_evidence, output } => {
+            let options = TagAuditOptions { num_neighbours, add_threshold, remove_threshold, max_evidence };
+            let model = create_model(&file_path)?;
+            let report = audit_tags(&model, &options)?;
+            match output {
+                Some(output) => {
+                    std::fs::write(&output, report.to_json())?;
+                    println!("Wrote the audit of {} items ({} flagged) to {}", report.num_items, report.entries.len(), output);
+                },
+                None => print!("{}", report),
+            }
+        },
+        Command::Quantize { output, quantization } => {
+            let quantized = quantize_model(&output, quantization)?;
+            println!("Quantized {} weight matrices to {} and wrote them to {}", quantized, quantization, output);
+        },
+        Command::CompareQuantized { file_path, quantized, sample_size, num_neighbours } => {
+            let report = compare_quantized(&file_path, &quantized, sample_size, num_neighbours)?;
+            println!("{}", report);
+        },
+    }
+    Ok(())
+}